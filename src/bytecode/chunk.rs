@@ -0,0 +1,330 @@
+use std::cmp::Ordering;
+use std::fmt::{ self, Display, Formatter };
+use crate::types::JType;
+
+/// Every instruction the VM understands.
+/// Instructions are a single byte, optionally
+/// followed by a little endian u16 operand.
+/// Type specific instructions end in I for int, F for float
+/// or S for string.
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum OpCode {
+    // Constants, operand is an index into the constant pool
+    PushInt,
+    PushFloat,
+    PushChar,
+    // Pushes a reference to a string in the VM's string table
+    PushStr,
+    PushTrue,
+    PushFalse,
+
+    // Stack
+    Pop,
+    // Keeps the top of the stack but pops
+    // the operand's number of slots under it
+    PopUnder,
+    // Pushes a copy of a variable, operand is its stack slot.
+    // Variables are just the values their `let` left on the stack.
+    GetLocal,
+    // Pops a value into a variable, operand is its stack slot
+    SetLocal,
+
+    // Conversions
+    IntToFloat,
+    // Same, but for the slot under the top, since the left
+    // operand only turns out to need converting once the
+    // right one has been compiled
+    IntToFloatUnder,
+
+    // Arithmetic
+    AddI,
+    AddF,
+    SubI,
+    SubF,
+    MulI,
+    MulF,
+    DivI,
+    DivF,
+    NegI,
+    NegF,
+
+    // Logic, bools are 1 for true and 0 for false
+    Not,
+    // Pops two values and pushes a bool,
+    // operand is the Comparison to do.
+    // Chars and bools compare as ints.
+    CompareI,
+    CompareF,
+    CompareS,
+
+    // Control flow, operand is the offset to jump to
+    Jump,
+    // Pops a bool and jumps if it's false
+    JumpIfFalse,
+
+    // Finishes execution, returning the top of the stack
+    Return,
+}
+
+/// Every opcode, indexed by its byte value.
+const OPCODES: &[OpCode] = &[
+    OpCode::PushInt, OpCode::PushFloat, OpCode::PushChar, OpCode::PushStr,
+    OpCode::PushTrue, OpCode::PushFalse,
+    OpCode::Pop, OpCode::PopUnder, OpCode::GetLocal, OpCode::SetLocal,
+    OpCode::IntToFloat, OpCode::IntToFloatUnder,
+    OpCode::AddI, OpCode::AddF, OpCode::SubI, OpCode::SubF,
+    OpCode::MulI, OpCode::MulF, OpCode::DivI, OpCode::DivF,
+    OpCode::NegI, OpCode::NegF,
+    OpCode::Not, OpCode::CompareI, OpCode::CompareF, OpCode::CompareS,
+    OpCode::Jump, OpCode::JumpIfFalse,
+    OpCode::Return,
+];
+
+impl OpCode {
+    /// Turns a byte back into an opcode,
+    /// or None if no opcode has that value.
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OPCODES.get(byte as usize).copied()
+    }
+
+    /// Returns how many operand bytes follow the opcode.
+    pub fn operand_len(&self) -> usize {
+        match self {
+            Self::PushInt | Self::PushFloat | Self::PushChar | Self::PushStr
+                | Self::PopUnder | Self::GetLocal | Self::SetLocal
+                | Self::CompareI | Self::CompareF | Self::CompareS
+                | Self::Jump | Self::JumpIfFalse => 2,
+            _ => 0,
+        }
+    }
+
+    /// Returns true if the operand is an offset to jump to.
+    pub fn is_jump(&self) -> bool {
+        matches!(self, Self::Jump | Self::JumpIfFalse)
+    }
+
+    /// Returns true if the operand is a Comparison.
+    pub fn is_comparison(&self) -> bool {
+        matches!(self, Self::CompareI | Self::CompareF | Self::CompareS)
+    }
+
+    /// Returns true if the operand is an index into the constant pool.
+    pub fn takes_constant(&self) -> bool {
+        matches!(self, Self::PushInt | Self::PushFloat | Self::PushChar | Self::PushStr)
+    }
+
+    /// Returns how many slots the instruction pops
+    /// off the stack and how many it pushes back.
+    /// Only PopUnder depends on its operand.
+    pub fn stack_effect(&self, operand: usize) -> (usize, usize) {
+        match self {
+            Self::PopUnder => (operand + 1, 1),
+            Self::SetLocal => (1, 0),
+            Self::PushInt | Self::PushFloat | Self::PushChar | Self::PushStr
+                | Self::PushTrue | Self::PushFalse | Self::GetLocal => (0, 1),
+            Self::Pop | Self::JumpIfFalse => (1, 0),
+            Self::IntToFloatUnder => (2, 2),
+            Self::IntToFloat | Self::NegI | Self::NegF | Self::Not => (1, 1),
+            Self::AddI | Self::AddF | Self::SubI | Self::SubF
+                | Self::MulI | Self::MulF | Self::DivI | Self::DivF
+                | Self::CompareI | Self::CompareF | Self::CompareS => (2, 1),
+            Self::Jump => (0, 0),
+            Self::Return => (0, 0),
+        }
+    }
+
+    /// Name of the opcode, used when disassembling.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PushInt => "PushInt",
+            Self::PushFloat => "PushFloat",
+            Self::PushChar => "PushChar",
+            Self::PushStr => "PushStr",
+            Self::PushTrue => "PushTrue",
+            Self::PushFalse => "PushFalse",
+            Self::Pop => "Pop",
+            Self::PopUnder => "PopUnder",
+            Self::GetLocal => "GetLocal",
+            Self::SetLocal => "SetLocal",
+            Self::IntToFloat => "IntToFloat",
+            Self::IntToFloatUnder => "IntToFloatUnder",
+            Self::AddI => "AddI",
+            Self::AddF => "AddF",
+            Self::SubI => "SubI",
+            Self::SubF => "SubF",
+            Self::MulI => "MulI",
+            Self::MulF => "MulF",
+            Self::DivI => "DivI",
+            Self::DivF => "DivF",
+            Self::NegI => "NegI",
+            Self::NegF => "NegF",
+            Self::Not => "Not",
+            Self::CompareI => "CompareI",
+            Self::CompareF => "CompareF",
+            Self::CompareS => "CompareS",
+            Self::Jump => "Jump",
+            Self::JumpIfFalse => "JumpIfFalse",
+            Self::Return => "Return",
+        }
+    }
+}
+
+/// What a compare instruction checks, stored as its operand.
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u16)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Every comparison, indexed by its operand value.
+const COMPARISONS: &[Comparison] = &[
+    Comparison::Eq, Comparison::Ne,
+    Comparison::Lt, Comparison::Le, Comparison::Gt, Comparison::Ge,
+];
+
+impl Comparison {
+    /// Turns an operand back into a comparison,
+    /// or None if no comparison has that value.
+    pub fn from_operand(operand: u16) -> Option<Comparison> {
+        COMPARISONS.get(operand as usize).copied()
+    }
+
+    /// Returns true if two values ordered like this pass the comparison.
+    /// The ordering is None for floats when one of them is NaN,
+    /// which only passes `!=`.
+    pub fn test(&self, ordering: Option<Ordering>) -> bool {
+        match self {
+            Self::Eq => ordering == Some(Ordering::Equal),
+            Self::Ne => ordering != Some(Ordering::Equal),
+            Self::Lt => ordering == Some(Ordering::Less),
+            Self::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            Self::Gt => ordering == Some(Ordering::Greater),
+            Self::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        }
+    }
+
+    /// The operator as it's written in code.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+}
+
+/// Values stored in the constant pool.
+#[derive(Clone)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    Char(char),
+    Str(String),
+}
+
+impl PartialEq for Constant {
+    // Floats compare by their bits so 0.0 and -0.0
+    // don't get merged into one constant.
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => a == b,
+            (Self::Float(a), Self::Float(b)) => a.to_bits() == b.to_bits(),
+            (Self::Char(a), Self::Char(b)) => a == b,
+            (Self::Str(a), Self::Str(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Constant {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(n) => write!(f, "{}", n),
+            Self::Float(n) => write!(f, "{:?}", n),
+            Self::Char(c) => write!(f, "{:?}", c),
+            Self::Str(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+/// Maps the instruction at `offset` back to the
+/// source selection it was compiled from.
+#[derive(Clone, Copy)]
+pub struct Span {
+    pub offset: usize,
+    pub index: usize,
+    pub len: usize,
+}
+
+/// A compiled program.
+/// Doesn't borrow the source, so it can outlive it.
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Constant>,
+    // Sorted by offset, one per instruction
+    pub spans: Vec<Span>,
+    pub filename: String,
+    // Static type of the value left by Return
+    pub result: JType,
+}
+
+impl Chunk {
+    pub fn new(filename: &str) -> Self {
+        Self {
+            code: Vec::new(),
+            constants: Vec::new(),
+            spans: Vec::new(),
+            filename: filename.to_string(),
+            result: JType::Void,
+        }
+    }
+
+    /// Finds the source selection of the instruction at the given offset.
+    pub fn span_at(&self, offset: usize) -> Option<&Span> {
+        match self.spans.binary_search_by_key(&offset, |s| s.offset) {
+            Ok(i) => self.spans.get(i),
+            // Offset is in the middle of an instruction,
+            // so use the instruction it belongs to.
+            Err(i) => i.checked_sub(1).and_then(|i| self.spans.get(i)),
+        }
+    }
+}
+
+impl Display for Chunk {
+    // Disassembles the chunk, one instruction per line
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let op = match OpCode::from_byte(self.code[offset]) {
+                Some(op) => op,
+                None => {
+                    writeln!(f, "{:04} <invalid {}>", offset, self.code[offset])?;
+                    offset += 1;
+                    continue;
+                }
+            };
+            write!(f, "{:04} {}", offset, op.name())?;
+            if op.operand_len() == 2 && offset + 2 < self.code.len() {
+                let operand = u16::from_le_bytes([self.code[offset + 1], self.code[offset + 2]]);
+                match (self.constants.get(operand as usize), Comparison::from_operand(operand)) {
+                    (Some(constant), _) if op.takes_constant() => write!(f, " {} ({})", operand, constant)?,
+                    (_, Some(comparison)) if op.is_comparison() => write!(f, " {}", comparison.symbol())?,
+                    _ if op.is_jump() => write!(f, " -> {:04}", operand)?,
+                    _ => write!(f, " {}", operand)?,
+                }
+            }
+            writeln!(f)?;
+            offset += 1 + op.operand_len();
+        }
+        Ok(())
+    }
+}
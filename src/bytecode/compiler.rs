@@ -0,0 +1,651 @@
+
+use crate::utils::{ codes, diagnostic::Diagnostic };
+use super::chunk::{ Chunk, Comparison, Constant, OpCode, Span };
+use super::result::CompileResult;
+use crate::parser::expr::{ Expr, LoopLabel };
+use crate::lexer::token::Position;
+use crate::stdlib;
+use crate::types::JType;
+use std::collections::HashMap;
+
+/// A loop around the code being compiled.
+struct Loop {
+    label: Option<String>,
+    // Stack depth at the start of every iteration.
+    // Break and continue pop everything above it.
+    depth: usize,
+    // Jumps to patch once the end of the loop is known
+    breaks: Vec<usize>,
+    // Same for jumps to the next iteration
+    continues: Vec<usize>,
+}
+
+struct Compiler {
+    chunk: Chunk,
+    // Stack slot and type of every variable, innermost scope last.
+    // Operations pick their instruction from the types.
+    scopes: Vec<HashMap<String, (u16, JType)>>,
+    // How many slots are on the stack when the
+    // code compiled so far has run
+    depth: usize,
+    // Loops around the code being compiled, innermost last
+    loops: Vec<Loop>,
+}
+
+impl Compiler {
+    fn new(filename: &str) -> Self {
+        Self {
+            chunk: Chunk::new(filename),
+            scopes: vec![HashMap::new()],
+            depth: 0,
+            loops: Vec::new(),
+        }
+    }
+
+    /// Starts a new scope for variables.
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Gives a variable a stack slot in the innermost scope.
+    fn declare(&mut self, name: &str, slot: u16, t: JType) {
+        self.scopes.last_mut().expect("compiler always has a scope").insert(name.to_string(), (slot, t));
+    }
+
+    /// Appends an instruction, remembering where it came from.
+    fn emit(&mut self, op: OpCode, pos: &Position) {
+        self.emit_with(op, None, pos);
+    }
+
+    /// Appends an instruction with an operand.
+    fn emit_operand(&mut self, op: OpCode, operand: u16, pos: &Position) {
+        self.emit_with(op, Some(operand), pos);
+    }
+
+    fn emit_with(&mut self, op: OpCode, operand: Option<u16>, pos: &Position) {
+        self.chunk.spans.push(Span {
+            offset: self.chunk.code.len(),
+            index: pos.start(),
+            len: pos.len(),
+        });
+        self.chunk.code.push(op as u8);
+        if let Some(operand) = operand {
+            self.chunk.code.extend_from_slice(&operand.to_le_bytes());
+        }
+        let (pops, pushes) = op.stack_effect(operand.unwrap_or(0) as usize);
+        self.depth = self.depth - pops + pushes;
+    }
+
+    /// Pops slots off the stack, optionally keeping the top one.
+    fn pop_slots(&mut self, count: usize, keep_top: bool, pos: &Position) {
+        if count == 0 {
+            return;
+        }
+        if keep_top {
+            // Never more than the stack's depth, which fits in a u16
+            self.emit_operand(OpCode::PopUnder, count as u16, pos);
+        } else {
+            for _ in 0..count {
+                self.emit(OpCode::Pop, pos);
+            }
+        }
+    }
+
+    /// Appends an instruction that takes a constant operand.
+    fn emit_constant(&mut self, op: OpCode, constant: Constant, pos: &Position) -> CompileResult<()> {
+        // Reuse the constant if it's already in the pool
+        let index = match self.chunk.constants.iter().position(|c| *c == constant) {
+            Some(index) => index,
+            None => {
+                self.chunk.constants.push(constant);
+                self.chunk.constants.len() - 1
+            }
+        };
+        let index = match u16::try_from(index) {
+            Ok(index) => index,
+            Err(_) => return CompileResult::Err(Diagnostic::error(codes::TOO_MANY_CONSTANTS,
+                format!("A program can only have {} constants.", u16::MAX as usize + 1), *pos)),
+        };
+        self.emit_operand(op, index, pos);
+        CompileResult::Ok(())
+    }
+
+    /// Appends a jump with a placeholder target,
+    /// returning where the target goes so it can be patched.
+    fn emit_jump(&mut self, op: OpCode, pos: &Position) -> usize {
+        self.emit_operand(op, u16::MAX, pos);
+        self.chunk.code.len() - 2
+    }
+
+    /// Points a jump emitted by emit_jump at the end of the code,
+    /// which is where the next instruction will go.
+    fn patch_jump(&mut self, at: usize, pos: &Position) -> CompileResult<()> {
+        self.patch_jump_to(at, self.chunk.code.len(), pos)
+    }
+
+    /// Points a jump emitted by emit_jump at the given offset.
+    fn patch_jump_to(&mut self, at: usize, target: usize, pos: &Position) -> CompileResult<()> {
+        let target = match u16::try_from(target) {
+            Ok(target) => target,
+            Err(_) => return CompileResult::Err(Diagnostic::error(codes::TOO_MUCH_CODE,
+                format!("A program can only have {} bytes of code.", u16::MAX as usize + 1), *pos)),
+        };
+        self.chunk.code[at..at + 2].copy_from_slice(&target.to_le_bytes());
+        CompileResult::Ok(())
+    }
+
+    /// Compiles both operands of an operation, converting the int
+    /// to a float when one side is an int and the other a float.
+    /// Returns the type the operation works on.
+    fn operands(&mut self, left: &Expr, right: &Expr) -> CompileResult<JType> {
+        let left_type = match self.expr(left) {
+            CompileResult::Ok(t) => t,
+            err => return err,
+        };
+        let right_type = match self.expr(right) {
+            CompileResult::Ok(t) => t,
+            err => return err,
+        };
+        match (&left_type, &right_type) {
+            (JType::Float, JType::Int) => self.emit(OpCode::IntToFloat, right.pos()),
+            // The left side is already under the right one
+            (JType::Int, JType::Float) => self.emit(OpCode::IntToFloatUnder, left.pos()),
+            _ => return CompileResult::Ok(left_type),
+        }
+        CompileResult::Ok(JType::Float)
+    }
+
+    /// Compiles a binary operation, picking the int or float
+    /// instruction from the types of the operands.
+    fn binary(&mut self, pos: &Position, left: &Expr, right: &Expr, int_op: OpCode, float_op: OpCode) -> CompileResult<JType> {
+        let t = match self.operands(left, right) {
+            CompileResult::Ok(t) => t,
+            err => return err,
+        };
+        let op = if t == JType::Float { float_op } else { int_op };
+        self.emit(op, pos);
+        CompileResult::Ok(t)
+    }
+
+    /// Compiles a comparison, picking the instruction
+    /// from the types of both sides.
+    fn comparison(&mut self, pos: &Position, left: &Expr, right: &Expr, comparison: Comparison) -> CompileResult<JType> {
+        let op = match self.operands(left, right) {
+            CompileResult::Ok(JType::Float) => OpCode::CompareF,
+            CompileResult::Ok(JType::String) => OpCode::CompareS,
+            CompileResult::Ok(_) => OpCode::CompareI,
+            CompileResult::Err(e) => return CompileResult::Err(e),
+        };
+        self.emit_operand(op, comparison as u16, pos);
+        CompileResult::Ok(JType::Bool)
+    }
+
+    /// Compiles `&&` or `||` so the right side only runs when needed.
+    ///
+    ///   a && b                     a || b
+    ///       a                          a
+    ///       JumpIfFalse short          JumpIfFalse long
+    ///       b                          PushTrue
+    ///       Jump end                   Jump end
+    ///   short:                     long:
+    ///       PushFalse                  b
+    ///   end:                       end:
+    fn logical(&mut self, pos: &Position, left: &Expr, right: &Expr, and: bool) -> CompileResult<JType> {
+        if let CompileResult::Err(e) = self.expr(left) {
+            return CompileResult::Err(e);
+        }
+        let to_else = self.emit_jump(OpCode::JumpIfFalse, pos);
+        let depth = self.depth;
+        if and {
+            if let CompileResult::Err(e) = self.expr(right) {
+                return CompileResult::Err(e);
+            }
+        } else {
+            self.emit(OpCode::PushTrue, pos);
+        }
+        let to_end = self.emit_jump(OpCode::Jump, pos);
+        if let CompileResult::Err(e) = self.patch_jump(to_else, pos) {
+            return CompileResult::Err(e);
+        }
+        // Both paths end with one bool on the stack
+        self.depth = depth;
+        if and {
+            self.emit(OpCode::PushFalse, pos);
+        } else if let CompileResult::Err(e) = self.expr(right) {
+            return CompileResult::Err(e);
+        }
+        match self.patch_jump(to_end, pos) {
+            CompileResult::Ok(_) => CompileResult::Ok(JType::Bool),
+            CompileResult::Err(e) => CompileResult::Err(e),
+        }
+    }
+
+    /// Compiles statements in the current scope, leaving the value
+    /// of the last one on the stack. Returns its type.
+    fn body(&mut self, exprs: &[Expr]) -> CompileResult<JType> {
+        let mut body_type = JType::Void;
+        for (i, e) in exprs.iter().enumerate() {
+            body_type = match self.expr(e) {
+                CompileResult::Ok(t) => t,
+                err => return err,
+            };
+            // Only the last expression keeps its value around
+            if i + 1 < exprs.len() && body_type != JType::Void {
+                self.emit(OpCode::Pop, e.pos());
+            }
+        }
+        CompileResult::Ok(body_type)
+    }
+
+    /// Compiles statements in a new scope. Its variables
+    /// get popped at the end, leaving just the block's value.
+    fn block(&mut self, pos: &Position, exprs: &[Expr]) -> CompileResult<JType> {
+        let depth = self.depth;
+        self.push_scope();
+        let t = self.body(exprs);
+        self.pop_scope();
+        let t = match t {
+            CompileResult::Ok(t) => t,
+            err => return err,
+        };
+        let keep = t != JType::Void;
+        self.pop_slots(self.depth - depth - keep as usize, keep, pos);
+        CompileResult::Ok(t)
+    }
+
+    /// Compiles the body of a loop, throwing its value away.
+    /// Returns the jumps out of it that still need patching.
+    fn loop_body(&mut self, label: &Option<LoopLabel>, body: &Expr) -> CompileResult<Loop> {
+        self.loops.push(Loop {
+            label: label.as_ref().map(|label| label.name.clone()),
+            depth: self.depth,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+        let t = match self.expr(body) {
+            CompileResult::Ok(t) => t,
+            CompileResult::Err(e) => return CompileResult::Err(e),
+        };
+        if t != JType::Void {
+            self.emit(OpCode::Pop, body.pos());
+        }
+        CompileResult::Ok(self.loops.pop().expect("pushed above"))
+    }
+
+    /// Compiles an if, with the then branch falling
+    /// through and the else branch jumped to.
+    ///
+    ///       condition
+    ///       JumpIfFalse else
+    ///       then
+    ///       Jump end
+    ///   else:
+    ///       otherwise
+    ///   end:
+    fn if_expr(&mut self, pos: &Position, condition: &Expr, then: &Expr, otherwise: Option<&Expr>) -> CompileResult<JType> {
+        if let CompileResult::Err(e) = self.expr(condition) {
+            return CompileResult::Err(e);
+        }
+        let to_else = self.emit_jump(OpCode::JumpIfFalse, pos);
+        let depth = self.depth;
+        let t = match self.expr(then) {
+            CompileResult::Ok(t) => t,
+            err => return err,
+        };
+        let otherwise = match otherwise {
+            Some(otherwise) => otherwise,
+            // Without an else the if is void, so the value goes
+            None => {
+                if t != JType::Void {
+                    self.emit(OpCode::Pop, pos);
+                }
+                return match self.patch_jump(to_else, pos) {
+                    CompileResult::Ok(_) => CompileResult::Ok(JType::Void),
+                    CompileResult::Err(e) => CompileResult::Err(e),
+                };
+            },
+        };
+        let to_end = self.emit_jump(OpCode::Jump, pos);
+        if let CompileResult::Err(e) = self.patch_jump(to_else, pos) {
+            return CompileResult::Err(e);
+        }
+        // The else branch starts from where the then branch did
+        self.depth = depth;
+        let otherwise_t = match self.expr(otherwise) {
+            CompileResult::Ok(t) => t,
+            err => return err,
+        };
+        // A then branch that always jumps away doesn't leave
+        // anything, so the value comes from the else branch
+        let t = if then.diverges() { otherwise_t } else { t };
+        match self.patch_jump(to_end, pos) {
+            CompileResult::Ok(_) => CompileResult::Ok(t),
+            CompileResult::Err(e) => CompileResult::Err(e),
+        }
+    }
+
+    /// Compiles a while loop.
+    ///
+    ///   start:
+    ///       condition
+    ///       JumpIfFalse end
+    ///       body
+    ///       Jump start
+    ///   end:
+    fn while_loop(&mut self, pos: &Position, label: &Option<LoopLabel>, condition: &Expr, body: &Expr) -> CompileResult<JType> {
+        let start = self.chunk.code.len();
+        if let CompileResult::Err(e) = self.expr(condition) {
+            return CompileResult::Err(e);
+        }
+        let to_end = self.emit_jump(OpCode::JumpIfFalse, pos);
+        let jumps = match self.loop_body(label, body) {
+            CompileResult::Ok(jumps) => jumps,
+            CompileResult::Err(e) => return CompileResult::Err(e),
+        };
+        let to_start = self.emit_jump(OpCode::Jump, pos);
+        let mut patches = vec![(to_start, start), (to_end, self.chunk.code.len())];
+        patches.extend(jumps.continues.iter().map(|at| (*at, start)));
+        patches.extend(jumps.breaks.iter().map(|at| (*at, self.chunk.code.len())));
+        for (at, target) in patches {
+            if let CompileResult::Err(e) = self.patch_jump_to(at, target, pos) {
+                return CompileResult::Err(e);
+            }
+        }
+        CompileResult::Ok(JType::Void)
+    }
+
+    /// Compiles a for loop over a range. The variable and the end of
+    /// the range live on the stack for as long as the loop runs.
+    ///
+    ///       from
+    ///       to
+    ///   start:
+    ///       GetLocal i
+    ///       GetLocal to
+    ///       CompareI <
+    ///       JumpIfFalse end
+    ///       body
+    ///   next:
+    ///       i = i + 1
+    ///       Jump start
+    ///   end:
+    ///       Pop
+    ///       Pop
+    fn for_loop(&mut self, pos: &Position, label: &Option<LoopLabel>, name: &str, from: &Expr, to: &Expr, body: &Expr) -> CompileResult<JType> {
+        if let CompileResult::Err(e) = self.expr(from) {
+            return CompileResult::Err(e);
+        }
+        if let CompileResult::Err(e) = self.expr(to) {
+            return CompileResult::Err(e);
+        }
+        let (i, end) = match u16::try_from(self.depth - 1) {
+            Ok(end) => (end - 1, end),
+            Err(_) => return CompileResult::Err(Diagnostic::error(codes::TOO_MANY_VARIABLES,
+                format!("A program can only have {} variables.", u16::MAX as usize + 1), *pos)),
+        };
+        self.push_scope();
+        self.declare(name, i, JType::Int);
+
+        let start = self.chunk.code.len();
+        self.emit_operand(OpCode::GetLocal, i, pos);
+        self.emit_operand(OpCode::GetLocal, end, pos);
+        self.emit_operand(OpCode::CompareI, Comparison::Lt as u16, pos);
+        let to_end = self.emit_jump(OpCode::JumpIfFalse, pos);
+        let jumps = match self.loop_body(label, body) {
+            CompileResult::Ok(jumps) => jumps,
+            CompileResult::Err(e) => return CompileResult::Err(e),
+        };
+        let next = self.chunk.code.len();
+        // i < to, so this can't overflow
+        self.emit_operand(OpCode::GetLocal, i, pos);
+        if let CompileResult::Err(e) = self.emit_constant(OpCode::PushInt, Constant::Int(1), pos) {
+            return CompileResult::Err(e);
+        }
+        self.emit(OpCode::AddI, pos);
+        self.emit_operand(OpCode::SetLocal, i, pos);
+        let to_start = self.emit_jump(OpCode::Jump, pos);
+        let end_offset = self.chunk.code.len();
+        self.pop_slots(2, false, pos);
+        self.pop_scope();
+
+        let mut patches = vec![(to_start, start), (to_end, end_offset)];
+        patches.extend(jumps.continues.iter().map(|at| (*at, next)));
+        patches.extend(jumps.breaks.iter().map(|at| (*at, end_offset)));
+        for (at, target) in patches {
+            if let CompileResult::Err(e) = self.patch_jump_to(at, target, pos) {
+                return CompileResult::Err(e);
+            }
+        }
+        CompileResult::Ok(JType::Void)
+    }
+
+    /// Compiles `break` or `continue`, popping everything the
+    /// loop put on the stack before jumping out of the iteration.
+    fn jump(&mut self, pos: &Position, label: &Option<LoopLabel>, is_break: bool) -> CompileResult<JType> {
+        let index = match label {
+            Some(label) => self.loops.iter().rposition(|l| l.label.as_ref() == Some(&label.name)),
+            None => self.loops.len().checked_sub(1),
+        };
+        let index = match index {
+            Some(index) => index,
+            None => return CompileResult::Err(Diagnostic::error(codes::MISPLACED_BREAK,
+                "Compiled a 'break' or 'continue' outside of a loop", *pos)),
+        };
+        let depth = self.depth;
+        self.pop_slots(depth - self.loops[index].depth, false, pos);
+        let at = self.emit_jump(OpCode::Jump, pos);
+        let target = &mut self.loops[index];
+        if is_break { target.breaks.push(at) } else { target.continues.push(at) }
+        // Nothing after this runs, but the code after it
+        // still gets compiled as if the jump wasn't there
+        self.depth = depth;
+        CompileResult::Ok(JType::Void)
+    }
+
+    /// Compiles an expression, leaving its value on the stack
+    /// unless it's void. Returns the type of the expression.
+    fn expr(&mut self, expr: &Expr) -> CompileResult<JType> {
+        match expr {
+            Expr::Body(pos, exprs) => self.block(pos, exprs),
+            Expr::If(pos, condition, then, otherwise) => self.if_expr(pos, condition, then, otherwise.as_deref()),
+            Expr::While(pos, label, condition, body) => self.while_loop(pos, label, condition, body),
+            Expr::For(pos, label, name, from, to, body) => self.for_loop(pos, label, name, from, to, body),
+            Expr::Break(pos, label) => self.jump(pos, label, true),
+            Expr::Continue(pos, label) => self.jump(pos, label, false),
+            Expr::Fn(pos, _, _) | Expr::Lambda(pos, _) | Expr::Call(pos, _, _) | Expr::Return(pos, _) =>
+                CompileResult::Err(unsupported("Functions", pos)),
+            Expr::Struct(pos, _) | Expr::Field(pos, _, _) =>
+                CompileResult::Err(unsupported("Structs and tuples", pos)),
+            Expr::Enum(pos, _) | Expr::Variant(pos, _, _) | Expr::Match(pos, _, _) =>
+                CompileResult::Err(unsupported("Enums", pos)),
+            Expr::List(pos, _) => CompileResult::Err(unsupported("Lists", pos)),
+            Expr::Tuple(pos, _) => CompileResult::Err(unsupported("Tuples", pos)),
+            Expr::Map(pos, _) => CompileResult::Err(unsupported("Maps", pos)),
+            Expr::Null(pos) | Expr::Coalesce(pos, _, _) => CompileResult::Err(unsupported("Optionals", pos)),
+            Expr::Import(pos, _, _) => CompileResult::Err(unsupported("Imports", pos)),
+            // Nothing imports a compiled file, so exporting changes nothing
+            Expr::Export(_, decl) => self.expr(decl),
+            Expr::Let(pos, name, _, value) => {
+                let t = match self.expr(value) {
+                    CompileResult::Ok(t) => t,
+                    err => return err,
+                };
+                if t == JType::Void {
+                    return CompileResult::Err(Diagnostic::error(codes::TYPE_MISMATCH,
+                        format!("You cannot store '{}' in a variable", t), *pos));
+                }
+                // The value stays on the stack and becomes the variable
+                let slot = match u16::try_from(self.depth - 1) {
+                    Ok(slot) => slot,
+                    Err(_) => return CompileResult::Err(Diagnostic::error(codes::TOO_MANY_VARIABLES,
+                        format!("A program can only have {} variables.", u16::MAX as usize + 1), *pos)),
+                };
+                self.declare(name, slot, t);
+                CompileResult::Ok(JType::Void)
+            },
+            Expr::Assign(pos, name, value) => {
+                let (slot, t) = match self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+                    Some((slot, t)) => (*slot, t.clone()),
+                    None => return CompileResult::Err(Diagnostic::error(codes::UNDEFINED_VARIABLE,
+                        format!("'{}' is not defined", name), *pos)),
+                };
+                let found = match self.expr(value) {
+                    CompileResult::Ok(t) => t,
+                    err => return err,
+                };
+                if found != t {
+                    return CompileResult::Err(Diagnostic::error(codes::TYPE_MISMATCH,
+                        format!("You cannot store '{}' in a variable holding '{}'", found, t), *pos));
+                }
+                // The new value takes the variable's slot, like a let's value does
+                self.emit_operand(OpCode::SetLocal, slot, pos);
+                CompileResult::Ok(JType::Void)
+            },
+            Expr::Var(pos, name) => {
+                let (slot, t) = match self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+                    Some((slot, t)) => (*slot, t.clone()),
+                    None if stdlib::global(name).is_some() =>
+                        return CompileResult::Err(unsupported("The standard library", pos)),
+                    _ => return CompileResult::Err(Diagnostic::error(codes::UNDEFINED_VARIABLE,
+                        format!("'{}' is not defined", name), *pos)),
+                };
+                self.emit_operand(OpCode::GetLocal, slot, pos);
+                CompileResult::Ok(t)
+            },
+            Expr::Int(pos, n) => match self.emit_constant(OpCode::PushInt, Constant::Int(*n), pos) {
+                CompileResult::Ok(_) => CompileResult::Ok(JType::Int),
+                CompileResult::Err(e) => CompileResult::Err(e),
+            },
+            Expr::Float(pos, n) => match self.emit_constant(OpCode::PushFloat, Constant::Float(*n), pos) {
+                CompileResult::Ok(_) => CompileResult::Ok(JType::Float),
+                CompileResult::Err(e) => CompileResult::Err(e),
+            },
+            Expr::Char(pos, c) => match self.emit_constant(OpCode::PushChar, Constant::Char(*c), pos) {
+                CompileResult::Ok(_) => CompileResult::Ok(JType::Char),
+                CompileResult::Err(e) => CompileResult::Err(e),
+            },
+            Expr::Str(pos, s) => match self.emit_constant(OpCode::PushStr, Constant::Str(s.to_string()), pos) {
+                CompileResult::Ok(_) => CompileResult::Ok(JType::String),
+                CompileResult::Err(e) => CompileResult::Err(e),
+            },
+            Expr::Bool(pos, b) => {
+                self.emit(if *b { OpCode::PushTrue } else { OpCode::PushFalse }, pos);
+                CompileResult::Ok(JType::Bool)
+            },
+            Expr::Neg(pos, operand) => {
+                let t = match self.expr(operand) {
+                    CompileResult::Ok(t) => t,
+                    err => return err,
+                };
+                match t {
+                    JType::Int => self.emit(OpCode::NegI, pos),
+                    JType::Float => self.emit(OpCode::NegF, pos),
+                    _ => return CompileResult::Err(Diagnostic::error(codes::TYPE_MISMATCH,
+                        format!("You cannot negate '{}'", t), *pos)),
+                }
+                CompileResult::Ok(t)
+            },
+            Expr::Add(pos, left, right) => self.binary(pos, left, right, OpCode::AddI, OpCode::AddF),
+            Expr::Sub(pos, left, right) => self.binary(pos, left, right, OpCode::SubI, OpCode::SubF),
+            Expr::Mul(pos, left, right) => self.binary(pos, left, right, OpCode::MulI, OpCode::MulF),
+            Expr::Div(pos, left, right) => self.binary(pos, left, right, OpCode::DivI, OpCode::DivF),
+            Expr::Not(pos, operand) => {
+                if let CompileResult::Err(e) = self.expr(operand) {
+                    return CompileResult::Err(e);
+                }
+                self.emit(OpCode::Not, pos);
+                CompileResult::Ok(JType::Bool)
+            },
+            Expr::Eq(pos, left, right) => self.comparison(pos, left, right, Comparison::Eq),
+            Expr::Ne(pos, left, right) => self.comparison(pos, left, right, Comparison::Ne),
+            Expr::Lt(pos, left, right) => self.comparison(pos, left, right, Comparison::Lt),
+            Expr::Le(pos, left, right) => self.comparison(pos, left, right, Comparison::Le),
+            Expr::Gt(pos, left, right) => self.comparison(pos, left, right, Comparison::Gt),
+            Expr::Ge(pos, left, right) => self.comparison(pos, left, right, Comparison::Ge),
+            Expr::And(pos, left, right) => self.logical(pos, left, right, true),
+            Expr::Or(pos, left, right) => self.logical(pos, left, right, false),
+        }
+    }
+}
+
+/// Makes the error for something the VM can't run yet.
+/// The usage text lists what it can run.
+fn unsupported(what: &str, pos: &Position) -> Diagnostic {
+    Diagnostic::error(codes::UNSUPPORTED, format!("{} can't be compiled to bytecode yet", what), *pos)
+        .with_help("Run it without --vm or compiling it instead, 'jpizzacrust --help' lists what the VM handles")
+}
+
+/// Compiles an expression tree into a chunk of bytecode.
+/// The tree has to pass the type checker first.
+pub fn compile(filename: &str, expr: &Expr) -> CompileResult<Chunk> {
+    let mut compiler = Compiler::new(filename);
+    // The outermost body doesn't need its variables popped
+    let result = match expr {
+        Expr::Body(_, exprs) => compiler.body(exprs),
+        _ => compiler.expr(expr),
+    };
+    let result = match result {
+        CompileResult::Ok(t) => t,
+        CompileResult::Err(e) => return CompileResult::Err(e),
+    };
+    compiler.emit(OpCode::Return, expr.pos());
+    compiler.chunk.result = result;
+    CompileResult::Ok(compiler.chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing;
+    use std::time::{ Duration, Instant };
+
+    fn disassemble(src: &str) -> String {
+        match compile("<test>", &testing::checked(src)) {
+            CompileResult::Ok(chunk) => chunk.to_string(),
+            CompileResult::Err(e) => panic!("couldn't compile {:?}: {}", src, e.message),
+        }
+    }
+
+    #[test]
+    fn ints_are_converted_where_floats_meet_them() {
+        assert!(disassemble("1 + 2.5").contains("IntToFloatUnder"));
+        assert!(!disassemble("2.5 + 1").contains("IntToFloatUnder"));
+        assert!(disassemble("2.5 + 1").contains("IntToFloat"));
+        assert!(!disassemble("1 + 2").contains("IntToFloat"));
+    }
+
+    #[test]
+    fn what_the_usage_text_lists_is_unsupported() {
+        let programs = [
+            "fn f(x: int) -> int { x + 1 } f(2)", "struct P { x: int } P(1).x", "enum E { A } E::A",
+            "[1, 2]", "(1, 2)", "[1: 2]", "let x: int? = null", "println", "math",
+        ];
+        for src in programs {
+            match compile("<test>", &testing::checked(src)) {
+                CompileResult::Ok(chunk) => panic!("{:?} compiled to {}", src, chunk),
+                CompileResult::Err(e) => assert_eq!(e.code.id, "C004", "{:?}", src),
+            }
+        }
+    }
+
+    #[test]
+    fn long_chains_compile_in_linear_time() {
+        // Every operation used to check its whole subtree again
+        let elapsed = std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(|| {
+                let tree = testing::checked(&vec!["1"; 3000].join(" + "));
+                let start = Instant::now();
+                assert!(matches!(compile("<test>", &tree), CompileResult::Ok(_)));
+                start.elapsed()
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(elapsed < Duration::from_secs(2), "took {:?}", elapsed);
+    }
+}
@@ -0,0 +1,668 @@
+
+use crate::utils::{ codes, diagnostic::{ Diagnostic, Severity } };
+use super::chunk::{ Chunk, Comparison, Constant, OpCode, Span };
+use super::result::LoadResult;
+use crate::types::JType;
+
+/*
+* Layout of a .jpc file. Every number is little endian.
+*
+*   magic       4 bytes, "JPZC"
+*   version     u16
+*   length      u64 length of the whole file
+*   filename    u64 length + UTF-8 bytes
+*   result      u8 type tag
+*   constants   u64 count + (u8 tag, 8 byte value) each,
+*               except strings which are a u64 length + UTF-8 bytes
+*   code        u64 length + bytes
+*   debug       u64 count + (u64 offset, u64 index, u64 len) each
+*   checksum    u32 CRC-32 of everything before it
+*/
+
+pub const MAGIC: &[u8; 4] = b"JPZC";
+pub const VERSION: u16 = 4;
+
+// Magic, version, length and checksum
+const MIN_LEN: usize = 4 + 2 + 8 + 4;
+
+const TAG_INT: u8 = 0;
+const TAG_FLOAT: u8 = 1;
+const TAG_VOID: u8 = 2;
+const TAG_CHAR: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_BOOL: u8 = 5;
+
+/// CRC-32 (IEEE) of the given bytes.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            // Xor with the reversed polynomial if the low bit is set
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
+
+fn write_u64(out: &mut Vec<u8>, n: u64) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+/// Turns a chunk into the bytes of a .jpc file.
+pub fn serialize(chunk: &Chunk) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    // Filled in once everything else is written
+    write_u64(&mut out, 0);
+
+    write_u64(&mut out, chunk.filename.len() as u64);
+    out.extend_from_slice(chunk.filename.as_bytes());
+
+    out.push(match &chunk.result {
+        JType::Int => TAG_INT,
+        JType::Float => TAG_FLOAT,
+        JType::Void => TAG_VOID,
+        JType::Char => TAG_CHAR,
+        JType::String => TAG_STRING,
+        JType::Bool => TAG_BOOL,
+        // The compiler refuses code making values of the other types
+        t => unreachable!("the compiler never produces '{}'", t),
+    });
+
+    write_u64(&mut out, chunk.constants.len() as u64);
+    for constant in &chunk.constants {
+        match constant {
+            Constant::Int(n) => {
+                out.push(TAG_INT);
+                out.extend_from_slice(&n.to_le_bytes());
+            },
+            Constant::Float(n) => {
+                out.push(TAG_FLOAT);
+                out.extend_from_slice(&n.to_bits().to_le_bytes());
+            },
+            Constant::Char(c) => {
+                out.push(TAG_CHAR);
+                write_u64(&mut out, *c as u64);
+            },
+            Constant::Str(s) => {
+                out.push(TAG_STRING);
+                write_u64(&mut out, s.len() as u64);
+                out.extend_from_slice(s.as_bytes());
+            },
+        }
+    }
+
+    write_u64(&mut out, chunk.code.len() as u64);
+    out.extend_from_slice(&chunk.code);
+
+    write_u64(&mut out, chunk.spans.len() as u64);
+    for span in &chunk.spans {
+        write_u64(&mut out, span.offset as u64);
+        write_u64(&mut out, span.index as u64);
+        write_u64(&mut out, span.len as u64);
+    }
+
+    let len = (out.len() + 4) as u64;
+    out[6..14].copy_from_slice(&len.to_le_bytes());
+    let checksum = crc32(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+/// Reads values out of a byte slice, failing
+/// instead of panicking when it runs out.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    index: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize, what: &str) -> LoadResult<&'a [u8]> {
+        match self.index.checked_add(n) {
+            Some(end) if end <= self.bytes.len() => {
+                let slice = &self.bytes[self.index..end];
+                self.index = end;
+                LoadResult::Ok(slice)
+            },
+            _ => LoadResult::Err(Diagnostic::new(Severity::Error, codes::TRUNCATED,
+                format!("File ended while reading the {}.", what), None)),
+        }
+    }
+
+    fn u8(&mut self, what: &str) -> LoadResult<u8> {
+        match self.take(1, what) {
+            LoadResult::Ok(bytes) => LoadResult::Ok(bytes[0]),
+            LoadResult::Err(e) => LoadResult::Err(e),
+        }
+    }
+
+    fn u64(&mut self, what: &str) -> LoadResult<u64> {
+        match self.take(8, what) {
+            LoadResult::Ok(bytes) => {
+                let mut buf = [0; 8];
+                buf.copy_from_slice(bytes);
+                LoadResult::Ok(u64::from_le_bytes(buf))
+            },
+            LoadResult::Err(e) => LoadResult::Err(e),
+        }
+    }
+
+    /// Reads a u64 that's used as a length or index.
+    fn usize(&mut self, what: &str) -> LoadResult<usize> {
+        match self.u64(what) {
+            LoadResult::Ok(n) => match usize::try_from(n) {
+                Ok(n) => LoadResult::Ok(n),
+                Err(_) => LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+                    format!("The {} is too large.", what), None)),
+            },
+            LoadResult::Err(e) => LoadResult::Err(e),
+        }
+    }
+
+    /// Reads a count of items that each take at least
+    /// `item_len` bytes, so a corrupt count can't make
+    /// us allocate more than the file could hold.
+    fn count(&mut self, item_len: usize, what: &str) -> LoadResult<usize> {
+        let count = match self.usize(what) {
+            LoadResult::Ok(n) => n,
+            err => return err,
+        };
+        let remaining = self.bytes.len() - self.index;
+        if count.saturating_mul(item_len) > remaining {
+            return LoadResult::Err(Diagnostic::new(Severity::Error, codes::TRUNCATED,
+                format!("The {} is larger than the rest of the file.", what), None));
+        }
+        LoadResult::Ok(count)
+    }
+}
+
+/// What a stack slot holds while the code is being verified.
+#[derive(Clone, Copy, PartialEq)]
+enum Slot {
+    Int,
+    Float,
+    Char,
+    Str,
+    Bool,
+}
+
+impl Slot {
+    /// The slot a result type gets returned from, None for void.
+    fn of(t: &JType) -> Option<Slot> {
+        match t {
+            JType::Int => Some(Slot::Int),
+            JType::Float => Some(Slot::Float),
+            JType::Char => Some(Slot::Char),
+            JType::String => Some(Slot::Str),
+            JType::Bool => Some(Slot::Bool),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Slot::Int => "an int",
+            Slot::Float => "a float",
+            Slot::Char => "a char",
+            Slot::Str => "a string",
+            Slot::Bool => "a bool",
+        }
+    }
+}
+
+fn corrupt<T>(message: String) -> LoadResult<T> {
+    LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT, message, None))
+}
+
+/// Pops a slot that has to hold one of the given kinds.
+fn pop(stack: &mut Vec<Slot>, allowed: &[Slot]) -> Result<Slot, String> {
+    match stack.pop() {
+        Some(slot) if allowed.contains(&slot) => Ok(slot),
+        Some(slot) => Err(format!("expects {} but finds {}", allowed[0].name(), slot.name())),
+        None => Err("pops an empty stack".to_string()),
+    }
+}
+
+/// Applies an instruction to the slots on the stack,
+/// or says what's wrong with running it there.
+fn step(op: OpCode, operand: usize, stack: &mut Vec<Slot>, result: &JType) -> Result<(), String> {
+    const ANY: &[Slot] = &[Slot::Int, Slot::Float, Slot::Char, Slot::Str, Slot::Bool];
+    match op {
+        OpCode::PushInt => stack.push(Slot::Int),
+        OpCode::PushFloat => stack.push(Slot::Float),
+        OpCode::PushChar => stack.push(Slot::Char),
+        OpCode::PushStr => stack.push(Slot::Str),
+        OpCode::PushTrue | OpCode::PushFalse => stack.push(Slot::Bool),
+        OpCode::Pop => {
+            pop(stack, ANY)?;
+        },
+        OpCode::PopUnder => {
+            let top = pop(stack, ANY)?;
+            if operand > stack.len() {
+                return Err("pops an empty stack".to_string());
+            }
+            stack.truncate(stack.len() - operand);
+            stack.push(top);
+        },
+        // Variables have to be below the top of the stack,
+        // which for SetLocal is under the value it pops
+        OpCode::GetLocal => match stack.get(operand) {
+            Some(slot) => stack.push(*slot),
+            None => return Err(format!("uses an empty stack slot {}", operand)),
+        },
+        OpCode::SetLocal => {
+            let value = pop(stack, ANY)?;
+            match stack.get(operand) {
+                Some(slot) if *slot == value => {},
+                Some(slot) => return Err(format!("stores {} in a slot holding {}", value.name(), slot.name())),
+                None => return Err(format!("uses an empty stack slot {}", operand)),
+            }
+        },
+        OpCode::IntToFloat => {
+            pop(stack, &[Slot::Int])?;
+            stack.push(Slot::Float);
+        },
+        OpCode::IntToFloatUnder => {
+            let top = pop(stack, ANY)?;
+            pop(stack, &[Slot::Int])?;
+            stack.extend([Slot::Float, top]);
+        },
+        OpCode::AddI | OpCode::SubI | OpCode::MulI | OpCode::DivI => {
+            pop(stack, &[Slot::Int])?;
+            pop(stack, &[Slot::Int])?;
+            stack.push(Slot::Int);
+        },
+        OpCode::AddF | OpCode::SubF | OpCode::MulF | OpCode::DivF => {
+            pop(stack, &[Slot::Float])?;
+            pop(stack, &[Slot::Float])?;
+            stack.push(Slot::Float);
+        },
+        OpCode::NegI => {
+            pop(stack, &[Slot::Int])?;
+            stack.push(Slot::Int);
+        },
+        OpCode::NegF => {
+            pop(stack, &[Slot::Float])?;
+            stack.push(Slot::Float);
+        },
+        OpCode::Not => {
+            pop(stack, &[Slot::Bool])?;
+            stack.push(Slot::Bool);
+        },
+        OpCode::CompareI | OpCode::CompareF | OpCode::CompareS => {
+            let allowed: &[Slot] = match op {
+                OpCode::CompareI => &[Slot::Int, Slot::Char, Slot::Bool],
+                OpCode::CompareF => &[Slot::Float],
+                _ => &[Slot::Str],
+            };
+            let right = pop(stack, allowed)?;
+            pop(stack, &[right])?;
+            stack.push(Slot::Bool);
+        },
+        OpCode::Jump => {},
+        OpCode::JumpIfFalse => {
+            pop(stack, &[Slot::Bool])?;
+        },
+        // The top of the stack becomes the result
+        OpCode::Return => if let Some(slot) = Slot::of(result) {
+            pop(stack, &[slot])?;
+        },
+    }
+    Ok(())
+}
+
+/// Checks that the code can be run without the VM reading out
+/// of bounds, popping an empty stack or reading a slot as the
+/// wrong type.
+fn verify(chunk: &Chunk) -> LoadResult<()> {
+    let code = &chunk.code;
+    // The instruction starting at each offset, None in between
+    let mut ops = vec![None; code.len()];
+    let mut offset = 0;
+    let mut returns = 0;
+    let mut last = None;
+    while offset < code.len() {
+        let op = match OpCode::from_byte(code[offset]) {
+            Some(op) => op,
+            None => return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+                format!("Unknown opcode {} at offset {}.", code[offset], offset), None)),
+        };
+        if offset + op.operand_len() >= code.len() {
+            return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+                format!("Instruction at offset {} is missing its operand.", offset), None));
+        }
+        let operand = if op.operand_len() == 2 {
+            u16::from_le_bytes([code[offset + 1], code[offset + 2]])
+        } else {
+            0
+        };
+
+        // Constants have to exist and be the kind the instruction expects
+        if op.takes_constant() {
+            let valid = matches!((op, chunk.constants.get(operand as usize)),
+                (OpCode::PushInt, Some(Constant::Int(_)))
+                | (OpCode::PushFloat, Some(Constant::Float(_)))
+                | (OpCode::PushChar, Some(Constant::Char(_)))
+                | (OpCode::PushStr, Some(Constant::Str(_))));
+            if !valid {
+                return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+                    format!("Instruction at offset {} has a bad constant index {}.", offset, operand), None));
+            }
+        }
+        if op.is_comparison() && Comparison::from_operand(operand).is_none() {
+            return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+                format!("Instruction at offset {} has an unknown comparison {}.", offset, operand), None));
+        }
+
+        if op == OpCode::Return {
+            returns += 1;
+        }
+        ops[offset] = Some((op, operand as usize));
+        last = Some(op);
+        offset += 1 + op.operand_len();
+    }
+    // Ending in a Return means every instruction has one after it to fall through to
+    if returns != 1 || last != Some(OpCode::Return) {
+        return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+            "Code must end with a single Return.", None));
+    }
+
+    // Follow every path through the code, tracking what's in each
+    // stack slot. Paths that meet have to agree on the whole stack,
+    // otherwise a loop could grow it forever, a jump could skip a push
+    // or a string index could end up where an int was expected.
+    let mut stacks: Vec<Option<Vec<Slot>>> = vec![None; code.len()];
+    let mut pending: Vec<(usize, Vec<Slot>)> = vec![(0, Vec::new())];
+    while let Some((offset, mut stack)) = pending.pop() {
+        match &stacks[offset] {
+            Some(seen) if *seen == stack => continue,
+            Some(_) => return corrupt(format!("Paths reaching offset {} disagree on the stack.", offset)),
+            None => stacks[offset] = Some(stack.clone()),
+        }
+        let (op, operand) = ops[offset].expect("paths only reach instructions");
+        if let Err(reason) = step(op, operand, &mut stack, &chunk.result) {
+            return corrupt(format!("Instruction at offset {} {}.", offset, reason));
+        }
+
+        if op.is_jump() {
+            if ops.get(operand).is_none_or(|op| op.is_none()) {
+                return corrupt(format!("Instruction at offset {} jumps to {}, which is not the start of an instruction.", offset, operand));
+            }
+            pending.push((operand, stack.clone()));
+        }
+        if op != OpCode::Jump && op != OpCode::Return {
+            pending.push((offset + 1 + op.operand_len(), stack));
+        }
+    }
+
+    // Offsets in the debug table have to point into the code, in order
+    let mut last = None;
+    for span in &chunk.spans {
+        if span.offset >= code.len() || last.is_some_and(|last| span.offset <= last) {
+            return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+                format!("Debug table entry for offset {} is out of order.", span.offset), None));
+        }
+        last = Some(span.offset);
+    }
+    LoadResult::Ok(())
+}
+
+/// Loads a chunk from the bytes of a .jpc file.
+/// Rejects files that are truncated, corrupt or from
+/// another version instead of trusting them.
+pub fn deserialize(bytes: &[u8]) -> LoadResult<Chunk> {
+    if bytes.len() < MIN_LEN {
+        return LoadResult::Err(Diagnostic::new(Severity::Error, codes::TRUNCATED,
+            "File is too short to be a compiled JPizza file.", None));
+    }
+    if &bytes[0..4] != MAGIC {
+        return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+            "File is not a compiled JPizza file.", None));
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return LoadResult::Err(Diagnostic::new(Severity::Error, codes::VERSION,
+            format!("File is format version {}, but only version {} is supported.", version, VERSION), None));
+    }
+
+    // A file cut short would fail the checksum too,
+    // but this gives a much clearer error.
+    let mut len = [0; 8];
+    len.copy_from_slice(&bytes[6..14]);
+    let len = u64::from_le_bytes(len);
+    if len != bytes.len() as u64 {
+        let code = if len > bytes.len() as u64 { codes::TRUNCATED } else { codes::CORRUPT };
+        return LoadResult::Err(Diagnostic::new(Severity::Error, code,
+            format!("File is {} bytes long, but should be {} bytes.", bytes.len(), len), None));
+    }
+
+    // Check the checksum before reading anything else
+    let (body, checksum) = bytes.split_at(bytes.len() - 4);
+    let checksum = u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    if crc32(body) != checksum {
+        return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+            "Checksum does not match, the file is damaged.", None));
+    }
+
+    let mut reader = Reader { bytes: body, index: 14 };
+
+    let filename_len = match reader.count(1, "filename") {
+        LoadResult::Ok(n) => n,
+        LoadResult::Err(e) => return LoadResult::Err(e),
+    };
+    let filename = match reader.take(filename_len, "filename") {
+        LoadResult::Ok(bytes) => match std::str::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(_) => return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+                "Filename is not valid UTF-8.", None)),
+        },
+        LoadResult::Err(e) => return LoadResult::Err(e),
+    };
+    let mut chunk = Chunk::new(filename);
+
+    chunk.result = match reader.u8("result type") {
+        LoadResult::Ok(TAG_INT) => JType::Int,
+        LoadResult::Ok(TAG_FLOAT) => JType::Float,
+        LoadResult::Ok(TAG_VOID) => JType::Void,
+        LoadResult::Ok(TAG_CHAR) => JType::Char,
+        LoadResult::Ok(TAG_STRING) => JType::String,
+        LoadResult::Ok(TAG_BOOL) => JType::Bool,
+        LoadResult::Ok(tag) => return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+            format!("Unknown result type tag {}.", tag), None)),
+        LoadResult::Err(e) => return LoadResult::Err(e),
+    };
+
+    let constant_count = match reader.count(9, "constant pool") {
+        LoadResult::Ok(n) => n,
+        LoadResult::Err(e) => return LoadResult::Err(e),
+    };
+    for _ in 0..constant_count {
+        let tag = match reader.u8("constant pool") {
+            LoadResult::Ok(tag) => tag,
+            LoadResult::Err(e) => return LoadResult::Err(e),
+        };
+        // Strings start with their length instead of a value
+        let bits = match reader.u64("constant pool") {
+            LoadResult::Ok(bits) => bits,
+            LoadResult::Err(e) => return LoadResult::Err(e),
+        };
+        chunk.constants.push(match tag {
+            TAG_INT => Constant::Int(bits as i64),
+            TAG_FLOAT => Constant::Float(f64::from_bits(bits)),
+            TAG_CHAR => match u32::try_from(bits).ok().and_then(char::from_u32) {
+                Some(c) => Constant::Char(c),
+                None => return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+                    format!("Constant {} is not a valid char.", bits), None)),
+            },
+            TAG_STRING => {
+                let len = match usize::try_from(bits) {
+                    Ok(len) => len,
+                    Err(_) => return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+                        "The string constant is too large.", None)),
+                };
+                match reader.take(len, "constant pool") {
+                    LoadResult::Ok(bytes) => match std::str::from_utf8(bytes) {
+                        Ok(s) => Constant::Str(s.to_string()),
+                        Err(_) => return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+                            "String constant is not valid UTF-8.", None)),
+                    },
+                    LoadResult::Err(e) => return LoadResult::Err(e),
+                }
+            },
+            _ => return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+                format!("Unknown constant tag {}.", tag), None)),
+        });
+    }
+
+    let code_len = match reader.count(1, "code") {
+        LoadResult::Ok(n) => n,
+        LoadResult::Err(e) => return LoadResult::Err(e),
+    };
+    chunk.code = match reader.take(code_len, "code") {
+        LoadResult::Ok(code) => code.to_vec(),
+        LoadResult::Err(e) => return LoadResult::Err(e),
+    };
+
+    let span_count = match reader.count(24, "debug table") {
+        LoadResult::Ok(n) => n,
+        LoadResult::Err(e) => return LoadResult::Err(e),
+    };
+    for _ in 0..span_count {
+        let mut fields = [0; 3];
+        for field in &mut fields {
+            *field = match reader.usize("debug table") {
+                LoadResult::Ok(n) => n,
+                LoadResult::Err(e) => return LoadResult::Err(e),
+            };
+        }
+        chunk.spans.push(Span { offset: fields[0], index: fields[1], len: fields[2] });
+    }
+
+    if reader.index != body.len() {
+        return LoadResult::Err(Diagnostic::new(Severity::Error, codes::CORRUPT,
+            "Unexpected data after the debug table.", None));
+    }
+
+    match verify(&chunk) {
+        LoadResult::Ok(_) => LoadResult::Ok(chunk),
+        LoadResult::Err(e) => LoadResult::Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::{ compiler, result::CompileResult };
+    use crate::utils::testing;
+
+    /// Builds a chunk out of raw code, skipping the compiler.
+    fn chunk(result: JType, constants: Vec<Constant>, code: &[u8]) -> Chunk {
+        let mut chunk = Chunk::new("<test>");
+        chunk.result = result;
+        chunk.constants = constants;
+        chunk.code = code.to_vec();
+        chunk
+    }
+
+    fn load_error(bytes: &[u8]) -> &'static str {
+        match deserialize(bytes) {
+            LoadResult::Ok(_) => panic!("loaded a bad file"),
+            LoadResult::Err(e) => e.code.id,
+        }
+    }
+
+    fn compiled(src: &str) -> Vec<u8> {
+        match compiler::compile("<test>", &testing::checked(src)) {
+            CompileResult::Ok(chunk) => serialize(&chunk),
+            CompileResult::Err(e) => panic!("couldn't compile {:?}: {}", src, e.message),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let programs = [
+            "let s = \"hi\" let x = 1.5 for i in 0..3 { } if s < \"z\" { x * 2 } else { 0.0 }",
+            "let c = 'a' outer: while c < 'b' { for i in 0..2 { if i == 1 { continue outer } break outer } } c",
+            "1 + 2.5 < 4 && !(\"a\" == \"b\")",
+            "let n = 1 if n > 0 { n } else { -n }",
+        ];
+        for src in programs {
+            let bytes = compiled(src);
+            match deserialize(&bytes) {
+                LoadResult::Ok(chunk) => assert_eq!(serialize(&chunk), bytes),
+                LoadResult::Err(e) => panic!("couldn't load {:?}: {}", src, e.message),
+            }
+        }
+    }
+
+    #[test]
+    fn damaged_files() {
+        let bytes = compiled("1 + 2");
+        assert_eq!(load_error(&bytes[..bytes.len() - 1]), "B001");
+        assert_eq!(load_error(&bytes[..5]), "B001");
+        let mut flipped = bytes.clone();
+        flipped[20] ^= 1;
+        assert_eq!(load_error(&flipped), "B002");
+        let mut version = bytes.clone();
+        version[4] = version[4].wrapping_add(1);
+        assert_eq!(load_error(&version), "B003");
+        assert_eq!(load_error(b"NOPE0000000000000000"), "B002");
+    }
+
+    #[test]
+    fn bad_code() {
+        let pop = chunk(JType::Void, vec![], &[OpCode::Pop as u8, OpCode::Return as u8]);
+        assert_eq!(load_error(&serialize(&pop)), "B002");
+        let constant = chunk(JType::Int, vec![], &[OpCode::PushInt as u8, 0, 0, OpCode::Return as u8]);
+        assert_eq!(load_error(&serialize(&constant)), "B002");
+        let jump = chunk(JType::Void, vec![], &[OpCode::Jump as u8, 1, 0, OpCode::Return as u8]);
+        assert_eq!(load_error(&serialize(&jump)), "B002");
+        let unknown = chunk(JType::Void, vec![], &[250, OpCode::Return as u8]);
+        assert_eq!(load_error(&serialize(&unknown)), "B002");
+    }
+
+    #[test]
+    fn slots_are_checked_for_the_right_type() {
+        let int = || vec![Constant::Int(1000)];
+        // Returning an int as a string used to index past the string table
+        let result = chunk(JType::String, int(), &[OpCode::PushInt as u8, 0, 0, OpCode::Return as u8]);
+        assert_eq!(load_error(&serialize(&result)), "B002");
+        let result = chunk(JType::Char, int(), &[OpCode::PushInt as u8, 0, 0, OpCode::Return as u8]);
+        assert_eq!(load_error(&serialize(&result)), "B002");
+        let compare = chunk(JType::Bool, int(), &[
+            OpCode::PushInt as u8, 0, 0, OpCode::PushInt as u8, 0, 0,
+            OpCode::CompareS as u8, Comparison::Eq as u8, 0, OpCode::Return as u8,
+        ]);
+        assert_eq!(load_error(&serialize(&compare)), "B002");
+        let mixed = chunk(JType::Int, vec![Constant::Int(1), Constant::Float(1.0)], &[
+            OpCode::PushInt as u8, 0, 0, OpCode::PushFloat as u8, 1, 0, OpCode::AddI as u8, OpCode::Return as u8,
+        ]);
+        assert_eq!(load_error(&serialize(&mixed)), "B002");
+        let set = chunk(JType::Void, vec![Constant::Int(1), Constant::Str("a".to_string())], &[
+            OpCode::PushInt as u8, 0, 0, OpCode::PushStr as u8, 1, 0, OpCode::SetLocal as u8, 0, 0,
+            OpCode::Pop as u8, OpCode::Return as u8,
+        ]);
+        assert_eq!(load_error(&serialize(&set)), "B002");
+        let condition = chunk(JType::Void, int(), &[
+            OpCode::PushInt as u8, 0, 0, OpCode::JumpIfFalse as u8, 6, 0, OpCode::Return as u8,
+        ]);
+        assert_eq!(load_error(&serialize(&condition)), "B002");
+    }
+
+    #[test]
+    fn paths_have_to_agree_on_types() {
+        // if true { 1 } else { 1.0 }, which never type checks
+        let branches = chunk(JType::Int, vec![Constant::Int(1), Constant::Float(1.0)], &[
+            OpCode::PushTrue as u8,
+            OpCode::JumpIfFalse as u8, 10, 0,
+            OpCode::PushInt as u8, 0, 0,
+            OpCode::Jump as u8, 13, 0,
+            OpCode::PushFloat as u8, 1, 0,
+            OpCode::Return as u8,
+        ]);
+        assert_eq!(load_error(&serialize(&branches)), "B002");
+    }
+}
@@ -0,0 +1,6 @@
+
+pub mod chunk;
+pub mod compiler;
+pub mod file;
+pub mod result;
+pub mod vm;
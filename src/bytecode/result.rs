@@ -0,0 +1,20 @@
+
+use crate::utils::diagnostic::Diagnostic;
+
+/// Result that either returns a compiler error or a value.
+pub enum CompileResult<T> {
+    /// Compiler result.
+    Ok(T),
+    /// Compiler error.
+    Err(Diagnostic),
+}
+
+/// Result that either returns an error from loading
+/// a compiled file or a value. There's no source
+/// to point at, so errors don't have a position.
+pub enum LoadResult<T> {
+    /// Loader result.
+    Ok(T),
+    /// Loader error.
+    Err(Diagnostic),
+}
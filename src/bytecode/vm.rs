@@ -0,0 +1,305 @@
+
+use super::chunk::{ Chunk, Comparison, Constant, OpCode };
+use crate::interpreter::result::RunResult;
+use crate::interpreter::value::Value;
+use crate::lexer::token::Position;
+use crate::lexer::source::FileId;
+use crate::types::JType;
+use crate::utils::{ codes, diagnostic::{ Code, Diagnostic } };
+use std::rc::Rc;
+
+/*
+* The stack holds raw 64 bit slots instead of Values.
+* The compiler already picked an instruction for the exact
+* types on the stack, so the VM never has to check what it's
+* holding. Compiled files get the same guarantee from the
+* loader, which checks every instruction gets the types it
+* expects before anything runs. Ints are stored as their two's
+* complement bits and floats as their IEEE bits. Chars are their
+* code point, bools are 1 or 0 and strings are an index into the
+* VM's string table.
+*/
+
+struct VM<'a> {
+    chunk: &'a Chunk,
+    // What the chunk was compiled from, for error messages
+    file: FileId,
+    // Constant pool converted to raw slots ahead of time
+    constants: Vec<u64>,
+    // Every string the program can reach
+    strings: Vec<Rc<str>>,
+    stack: Vec<u64>,
+    ip: usize,
+}
+
+impl<'a> VM<'a> {
+    fn new(chunk: &'a Chunk, file: FileId) -> Self {
+        let mut strings = Vec::new();
+        let constants = chunk.constants.iter().map(|c| match c {
+            Constant::Int(n) => *n as u64,
+            Constant::Float(n) => n.to_bits(),
+            Constant::Char(c) => *c as u64,
+            Constant::Str(s) => {
+                strings.push(Rc::from(s.as_str()));
+                (strings.len() - 1) as u64
+            },
+        }).collect();
+        Self {
+            chunk,
+            file,
+            constants,
+            strings,
+            stack: Vec::new(),
+            ip: 0,
+        }
+    }
+
+    /// Builds a runtime error pointing at the
+    /// source of the instruction at the given offset.
+    fn error<T>(&self, offset: usize, code: Code, reason: String) -> RunResult<T> {
+        let (index, len) = match self.chunk.span_at(offset) {
+            Some(span) => (span.index, span.len),
+            None => (0, 0),
+        };
+        RunResult::Err(Diagnostic::error(code, reason,
+            Position::new(self.file, index, index + len)))
+    }
+
+    /// Reads a u16 operand and steps over it.
+    fn read_operand(&mut self) -> usize {
+        let code = &self.chunk.code;
+        let operand = u16::from_le_bytes([code[self.ip], code[self.ip + 1]]);
+        self.ip += 2;
+        operand as usize
+    }
+
+    fn push(&mut self, slot: u64) {
+        self.stack.push(slot);
+    }
+
+    fn pop(&mut self) -> u64 {
+        self.stack.pop().expect("compiler emitted unbalanced bytecode")
+    }
+
+    fn pop_int(&mut self) -> i64 {
+        self.pop() as i64
+    }
+
+    fn pop_float(&mut self) -> f64 {
+        f64::from_bits(self.pop())
+    }
+
+    /// Pops two ints, applies an overflow checked operation
+    /// and pushes the result.
+    fn int_op(&mut self, offset: usize, name: &str, op: fn(i64, i64) -> Option<i64>) -> RunResult<()> {
+        let right = self.pop_int();
+        let left = self.pop_int();
+        match op(left, right) {
+            Some(n) => {
+                self.push(n as u64);
+                RunResult::Ok(())
+            },
+            None => self.error(offset, codes::OVERFLOW,
+                format!("Integer overflow when trying to {} {} and {}.", name, left, right)),
+        }
+    }
+
+    /// Pops two floats, applies an operation and pushes the result.
+    fn float_op(&mut self, op: fn(f64, f64) -> f64) {
+        let right = self.pop_float();
+        let left = self.pop_float();
+        self.push(op(left, right).to_bits());
+    }
+
+    /// Pops two values, compares them with the comparison in
+    /// the operand and pushes the result. `order` orders the raw slots.
+    fn compare(&mut self, order: fn(&Self, u64, u64) -> Option<std::cmp::Ordering>) {
+        let operand = self.read_operand() as u16;
+        let comparison = Comparison::from_operand(operand).expect("loader checked the comparison");
+        let right = self.pop();
+        let left = self.pop();
+        let result = comparison.test(order(self, left, right));
+        self.push(result as u64);
+    }
+
+    /// Runs the chunk until it returns.
+    fn run(&mut self) -> RunResult<Value> {
+        loop {
+            let offset = self.ip;
+            let byte = self.chunk.code[offset];
+            self.ip += 1;
+            let op = match OpCode::from_byte(byte) {
+                Some(op) => op,
+                None => return self.error(offset, codes::INVALID_BYTECODE,
+                    format!("Unknown opcode {}.", byte)),
+            };
+            match op {
+                OpCode::PushInt | OpCode::PushFloat | OpCode::PushChar | OpCode::PushStr => {
+                    let index = self.read_operand();
+                    self.push(self.constants[index]);
+                },
+                OpCode::PushTrue => self.push(1),
+                OpCode::PushFalse => self.push(0),
+                OpCode::Pop => {
+                    self.pop();
+                },
+                OpCode::PopUnder => {
+                    let count = self.read_operand();
+                    let top = self.pop();
+                    self.stack.truncate(self.stack.len() - count);
+                    self.push(top);
+                },
+                OpCode::GetLocal => {
+                    let slot = self.read_operand();
+                    self.push(self.stack[slot]);
+                },
+                OpCode::SetLocal => {
+                    let slot = self.read_operand();
+                    self.stack[slot] = self.pop();
+                },
+                OpCode::IntToFloat => {
+                    let n = self.pop_int();
+                    self.push((n as f64).to_bits());
+                },
+                OpCode::IntToFloatUnder => {
+                    let under = self.stack.len() - 2;
+                    self.stack[under] = (self.stack[under] as i64 as f64).to_bits();
+                },
+                OpCode::AddI => if let RunResult::Err(e) = self.int_op(offset, "add", i64::checked_add) {
+                    return RunResult::Err(e);
+                },
+                OpCode::SubI => if let RunResult::Err(e) = self.int_op(offset, "subtract", i64::checked_sub) {
+                    return RunResult::Err(e);
+                },
+                OpCode::MulI => if let RunResult::Err(e) = self.int_op(offset, "multiply", i64::checked_mul) {
+                    return RunResult::Err(e);
+                },
+                OpCode::DivI => {
+                    // Check for zero before checked_div folds it into an overflow
+                    if self.stack.last() == Some(&0) {
+                        return self.error(offset, codes::DIVISION_BY_ZERO, "Integer division by zero.".to_string());
+                    }
+                    if let RunResult::Err(e) = self.int_op(offset, "divide", i64::checked_div) {
+                        return RunResult::Err(e);
+                    }
+                },
+                OpCode::AddF => self.float_op(|a, b| a + b),
+                OpCode::SubF => self.float_op(|a, b| a - b),
+                OpCode::MulF => self.float_op(|a, b| a * b),
+                OpCode::DivF => self.float_op(|a, b| a / b),
+                OpCode::NegI => {
+                    let n = self.pop_int();
+                    match n.checked_neg() {
+                        Some(n) => self.push(n as u64),
+                        None => return self.error(offset, codes::OVERFLOW,
+                            format!("Integer overflow when trying to negate {}.", n)),
+                    }
+                },
+                OpCode::NegF => {
+                    let n = self.pop_float();
+                    self.push((-n).to_bits());
+                },
+                OpCode::Not => {
+                    let b = self.pop();
+                    self.push((b == 0) as u64);
+                },
+                OpCode::CompareI => self.compare(|_, a, b| (a as i64).partial_cmp(&(b as i64))),
+                OpCode::CompareF => self.compare(|_, a, b| f64::from_bits(a).partial_cmp(&f64::from_bits(b))),
+                OpCode::CompareS => self.compare(|vm, a, b| vm.strings[a as usize].partial_cmp(&vm.strings[b as usize])),
+                OpCode::Jump => {
+                    self.ip = self.read_operand();
+                },
+                OpCode::JumpIfFalse => {
+                    let target = self.read_operand();
+                    if self.pop() == 0 {
+                        self.ip = target;
+                    }
+                },
+                OpCode::Return => {
+                    // The compiler knows the type of the result,
+                    // so this is the only place a Value gets built.
+                    return RunResult::Ok(match &self.chunk.result {
+                        JType::Int => Value::Int(self.pop_int()),
+                        JType::Float => Value::Float(self.pop_float()),
+                        // The loader checked the slot holds a char, and
+                        // chars only come from constants it checked too
+                        JType::Char => Value::Char(char::from_u32(self.pop() as u32)
+                            .expect("loader checked the result is a char")),
+                        JType::String => {
+                            let index = self.pop() as usize;
+                            Value::Str(self.strings[index].clone())
+                        },
+                        JType::Bool => Value::Bool(self.pop() != 0),
+                        JType::Void => Value::Void,
+                        // The compiler refuses code making values of the other types
+                        t => unreachable!("the compiler never produces '{}'", t),
+                    });
+                },
+            }
+        }
+    }
+}
+
+/// Runs a compiled chunk.
+/// The file is only used to point runtime errors at the code.
+pub fn run(chunk: &Chunk, file: FileId) -> RunResult<Value> {
+    let mut vm = VM::new(chunk, file);
+    vm.run()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::testing::run_both;
+
+    /// Programs the VM can run, which both backends have to agree on.
+    const PROGRAMS: &[&str] = &[
+        "1 + 2 * 3",
+        "(3 * 4) / 2.0",
+        "let a = 2 let b = a * 3.5 b - a",
+        "-7 / 2",
+        "1 + 2.5",
+        "2.5 - 1",
+        "let i = 3 let f = 0.5 i * f + f * i",
+        "1 < 1.5 && 2.5 > 2 && 1 == 1.0",
+        "1.0 / 0.0",
+        "let x = 'a' x < 'b'",
+        "\"abc\" < \"abd\"",
+        "\"a\" == \"a\" && !(1 > 2)",
+        "false || 2.5 >= 2",
+        "let n = 0 while n < 10 { break } n",
+        "let total = 0.0 for i in 0..10 { if i == 3 { continue } if i == 8 { break } } total",
+        "outer: for i in 0..3 { for j in 0..3 { if j == 1 { break outer } } } 1",
+        // Jumping out halfway through an expression leaves its operands behind
+        "let c = true let n = 5 while true { let x = 1 + (if c { break } else { 2 }) break } n",
+        "let c = false let n = 5 while true { let x = 1 + (if c { break } else { 2 }) n = x break } n",
+        "let n = 0 for i in 0..4 { let x = i * (if i == 2 { continue } else { 2 }) n += x } n",
+        "let x = 1 x += 2 x *= 10 x -= 1 x /= 2 x",
+        "let t = 0.0 let i = 0 while i < 5 { t += 1.5 i = i + 1 } t",
+        "if 1 < 2 { \"yes\" } else { \"no\" }",
+        "let c = 'x' c",
+        "\"a\\tb\"",
+        "true",
+    ];
+
+    #[test]
+    fn backends_agree() {
+        for src in PROGRAMS {
+            run_both(src).unwrap_or_else(|id| panic!("{:?} failed with {}", src, id));
+        }
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(run_both("9223372036854775807 + 1"), Err("R002"));
+        assert_eq!(run_both("9223372036854775807 * 2"), Err("R002"));
+        assert_eq!(run_both("-9223372036854775807 - 2"), Err("R002"));
+        assert_eq!(run_both("-(-9223372036854775807 - 1)"), Err("R002"));
+        assert_eq!(run_both("(-9223372036854775807 - 1) / -1"), Err("R002"));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(run_both("1 / 0"), Err("R001"));
+        assert_eq!(run_both("let zero = 1 - 1 5 / zero"), Err("R001"));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::lexer::lexer::DEFAULT_MAX_ERRORS;
+
+/// Text shown for --help and after usage errors.
+pub const USAGE: &str = "\
+Usage: jpizzacrust <command> [options]
+
+Commands:
+  run <file>        Run a source file, or a compiled .jpc file
+  lex <file>        Print the tokens of a file with their positions
+  parse <file>      Print the syntax tree of a file
+  check <file>      Type check a file without running it
+  compile <file>    Compile a file to bytecode, see below for what it handles
+  disasm <file>     Print the bytecode of a source or .jpc file
+  -e <code>         Run inline code and print its value
+  repl              Start an interactive session
+
+Options:
+  --vm              Run on the bytecode VM instead of the tree walker
+  -o <file>         Where compile writes to (defaults to <file>.jpc)
+  --max-errors <n>  How many errors lex reports before giving up (defaults to 20)
+  --path <dir>      Look for modules in the directory too, after the one the
+                    file is in. Can be given more than once, and JPIZZA_PATH
+                    can list more directories
+  -h, --help        Show this message
+
+The bytecode VM (--vm, compile, disasm and .jpc files) only handles numbers,
+bools, chars, strings, variables, operators, if, while and for so far.
+Functions, structs, enums, match, lists, tuples, maps, optionals, imports
+and the standard library give a C004 error there, and have to be run
+without --vm.";
+
+/// Names of every command, including the ones without a file.
+const COMMANDS: &[&str] = &["run", "lex", "parse", "check", "compile", "disasm", "repl"];
+
+/// Everything the command line can ask for.
+pub enum Command {
+    // `path` is the directories given with --path
+    Run { file: String, vm: bool, path: Vec<String> },
+    Lex { file: String, max_errors: usize },
+    Parse { file: String },
+    Check { file: String, path: Vec<String> },
+    Compile { file: String, output: Option<String>, path: Vec<String> },
+    Disasm { file: String, path: Vec<String> },
+    Eval { code: String, vm: bool, path: Vec<String> },
+    Repl { path: Vec<String> },
+    Help,
+}
+
+/// Turns the command line arguments (without the program name)
+/// into a command, or an error message explaining what's wrong.
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let mut positional: Vec<&str> = Vec::new();
+    let mut vm = false;
+    let mut output = None;
+    let mut code = None;
+    let mut max_errors = None;
+    let mut path = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--vm" => vm = true,
+            "-o" => match iter.next() {
+                Some(path) => output = Some(path.to_string()),
+                None => return Err("Expected a file after '-o'.".to_string()),
+            },
+            "--max-errors" => match iter.next().map(|n| n.parse::<usize>()) {
+                Some(Ok(n)) if n > 0 => max_errors = Some(n),
+                Some(_) => return Err("'--max-errors' takes a number above 0.".to_string()),
+                None => return Err("Expected a number after '--max-errors'.".to_string()),
+            },
+            "--path" => match iter.next() {
+                Some(dir) => path.push(dir.to_string()),
+                None => return Err("Expected a directory after '--path'.".to_string()),
+            },
+            "-e" => match iter.next() {
+                Some(src) => code = Some(src.to_string()),
+                None => return Err("Expected code after '-e'.".to_string()),
+            },
+            _ if arg.starts_with('-') && arg.len() > 1 => return Err(format!("Unknown option '{}'.", arg)),
+            _ => positional.push(arg),
+        }
+    }
+
+    // Inline code doesn't take a subcommand
+    if let Some(code) = code {
+        if !positional.is_empty() {
+            return Err("'-e' can't be combined with a command.".to_string());
+        }
+        return Ok(Command::Eval { code, vm, path });
+    }
+
+    // A file given without a command shouldn't be blamed for not having one after it
+    if let Some(command) = positional.first() {
+        if !COMMANDS.contains(command) {
+            return Err(format!("Unknown command '{}'.", command));
+        }
+    }
+    let (command, file) = match positional.as_slice() {
+        [] => return Ok(Command::Help),
+        ["repl"] => return Ok(Command::Repl { path }),
+        ["repl", extra, ..] => return Err(format!("Unexpected argument '{}'.", extra)),
+        [command, file] => (*command, file.to_string()),
+        [command] => return Err(format!("Expected a file after '{}'.", command)),
+        [_, _, extra, ..] => return Err(format!("Unexpected argument '{}'.", extra)),
+    };
+    if output.is_some() && command != "compile" {
+        return Err("'-o' only works with compile.".to_string());
+    }
+    if max_errors.is_some() && command != "lex" {
+        return Err("'--max-errors' only works with lex.".to_string());
+    }
+    // Lexing and parsing don't look at what a file imports
+    if !path.is_empty() && matches!(command, "lex" | "parse") {
+        return Err(format!("'--path' doesn't work with {}.", command));
+    }
+    match command {
+        "run" => Ok(Command::Run { file, vm, path }),
+        "lex" => Ok(Command::Lex { file, max_errors: max_errors.unwrap_or(DEFAULT_MAX_ERRORS) }),
+        "parse" => Ok(Command::Parse { file }),
+        "check" => Ok(Command::Check { file, path }),
+        "compile" => Ok(Command::Compile { file, output, path }),
+        "disasm" => Ok(Command::Disasm { file, path }),
+        _ => Err(format!("Unknown command '{}'.", command)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(args: &[&str]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        match parse_args(&args) {
+            Ok(_) => panic!("{:?} should have been rejected", args),
+            Err(reason) => reason,
+        }
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert_eq!(error(&["foo.jpizza"]), "Unknown command 'foo.jpizza'.");
+        assert_eq!(error(&["foo.jpizza", "bar.jpizza"]), "Unknown command 'foo.jpizza'.");
+        assert_eq!(error(&["nope", "a"]), "Unknown command 'nope'.");
+        assert_eq!(error(&["run"]), "Expected a file after 'run'.");
+        assert_eq!(error(&["repl", "foo.jpizza"]), "Unexpected argument 'foo.jpizza'.");
+        assert_eq!(error(&["run", "a", "b"]), "Unexpected argument 'b'.");
+        assert_eq!(error(&["run", "--nope"]), "Unknown option '--nope'.");
+        assert_eq!(error(&["run", "a", "-o", "b"]), "'-o' only works with compile.");
+        assert_eq!(error(&["-e", "1", "run"]), "'-e' can't be combined with a command.");
+        assert!(matches!(parse_args(&["check".to_string(), "a".to_string()]), Ok(Command::Check { .. })));
+    }
+}
@@ -0,0 +1,266 @@
+
+use super::args::{ self, Command };
+use super::repl;
+use crate::bytecode::{ self, chunk::Chunk, result::{ CompileResult, LoadResult } };
+use crate::interpreter::{ Interpreter, result::RunResult, value::Value };
+use crate::lexer::{ self, result::Lexed, source::{ FileId, SourceMap }, token::Token };
+use crate::modules::{ Loader, Program };
+use crate::parser::{ self, expr::Expr, result::ParseResult };
+use crate::utils::{ codes, diagnostic::{ Diagnostic, Severity }, logging };
+use std::env;
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+/// Environment variable with more directories to look for modules in.
+const PATH_VAR: &str = "JPIZZA_PATH";
+
+// Exit codes
+const SUCCESS: i32 = 0;
+const FAILURE: i32 = 1;
+const USAGE: i32 = 2;
+
+/// Builds a diagnostic for a file that couldn't be read or written.
+fn io_error(message: String) -> Diagnostic {
+    Diagnostic::new(Severity::Error, codes::IO, message, None)
+}
+
+/// Reads a source file into the map, logging an error if it can't be read.
+fn read(map: &mut SourceMap, file: &str) -> Option<FileId> {
+    match fs::read_to_string(file) {
+        Ok(src) => Some(map.add(file, src)),
+        Err(e) => {
+            logging::diagnostic(&io_error(format!("Could not read '{}': {}", file, e)), map);
+            None
+        }
+    }
+}
+
+/// Logs every diagnostic the lexer found.
+/// Returns the tokens only if none of them were errors.
+fn check_lexed<'a>(lexed: Lexed<'a>, map: &SourceMap) -> Option<Vec<Token<'a>>> {
+    for diagnostic in &lexed.diagnostics {
+        logging::diagnostic(diagnostic, map);
+    }
+    if lexed.has_errors() { None } else { Some(lexed.tokens) }
+}
+
+/// Lexes a file, logging the errors if there are any.
+fn lex(map: &SourceMap, file: FileId) -> Option<Vec<Token<'_>>> {
+    check_lexed(lexer::lexer::lex(map.get(file)), map)
+}
+
+/// Lexes and parses a file, logging the error if there is one.
+fn parse(map: &SourceMap, file: FileId) -> Option<Expr> {
+    match parser::parse(map.get(file), lex(map, file)?) {
+        ParseResult::Ok(tree) => Some(tree),
+        ParseResult::Err(e) => {
+            logging::diagnostic(&e, map);
+            None
+        }
+    }
+}
+
+/// Makes a loader that looks for modules in the directories
+/// given with --path, then the ones in JPIZZA_PATH.
+fn loader(path: Vec<String>) -> Loader {
+    let mut search: Vec<PathBuf> = path.into_iter().map(PathBuf::from).collect();
+    if let Some(dirs) = env::var_os(PATH_VAR) {
+        search.extend(env::split_paths(&dirs).filter(|dir| !dir.as_os_str().is_empty()));
+    }
+    Loader::new(search)
+}
+
+/// Lexes, parses and type checks a file along with every
+/// module it imports, logging every error if there are any.
+/// `path` is where the file came from, if it did.
+fn check(map: &mut SourceMap, loader: &mut Loader, file: FileId, path: Option<&str>) -> Option<Program> {
+    loader.load(map, file, path.map(Path::new))
+}
+
+/// Runs a chunk on the VM and returns its value,
+/// logging the error if there is one.
+fn run_chunk(map: &SourceMap, chunk: &Chunk, file: FileId) -> Option<Value> {
+    match bytecode::vm::run(chunk, file) {
+        RunResult::Ok(value) => Some(value),
+        RunResult::Err(e) => {
+            logging::diagnostic(&e, map);
+            None
+        }
+    }
+}
+
+/// Runs a file with either backend and returns its value,
+/// logging the error if there is one.
+fn run_src(map: &mut SourceMap, loader: &mut Loader, file: FileId, path: Option<&str>, vm: bool) -> Option<Value> {
+    if vm {
+        let chunk = compile(map, loader, file, path)?;
+        run_chunk(map, &chunk, file)
+    } else {
+        let program = check(map, loader, file, path)?;
+        loader.run(map, &program, &mut Interpreter::new())
+    }
+}
+
+/// Loads a compiled file, logging the error if there is one.
+fn load(map: &SourceMap, file: &str) -> Option<Chunk> {
+    let bytes = match fs::read(file) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            logging::diagnostic(&io_error(format!("Could not read '{}': {}", file, e)), map);
+            return None;
+        }
+    };
+    match bytecode::file::deserialize(&bytes) {
+        LoadResult::Ok(chunk) => Some(chunk),
+        LoadResult::Err(e) => {
+            logging::diagnostic(&e, map);
+            None
+        }
+    }
+}
+
+/// Compiles a file, logging the error if there is one.
+fn compile(map: &mut SourceMap, loader: &mut Loader, file: FileId, path: Option<&str>) -> Option<Chunk> {
+    let program = check(map, loader, file, path)?;
+    match bytecode::compiler::compile(map.get(file).name(), &program.tree) {
+        CompileResult::Ok(chunk) => Some(chunk),
+        CompileResult::Err(e) => {
+            logging::diagnostic(&e, map);
+            None
+        }
+    }
+}
+
+/// Loads a compiled file and runs it on the VM.
+fn run_compiled(map: &mut SourceMap, file: &str) -> Option<Value> {
+    let chunk = load(map, file)?;
+    // The original source is only needed to show errors nicely,
+    // so it's fine if it isn't around anymore.
+    let src = fs::read_to_string(&chunk.filename).unwrap_or_default();
+    let file = map.add(chunk.filename.as_str(), src);
+    run_chunk(map, &chunk, file)
+}
+
+/// Turns the result of a command into an exit code.
+fn exit_code<T>(result: Option<T>) -> i32 {
+    if result.is_some() { SUCCESS } else { FAILURE }
+}
+
+fn lex_command(map: &mut SourceMap, file: &str, max_errors: usize) -> i32 {
+    let file = match read(map, file) {
+        Some(file) => file,
+        None => return FAILURE,
+    };
+    let lexed = lexer::lexer::lex_with_limit(map.get(file), max_errors);
+    // Show every token, even when there are errors,
+    // so tools can still make sense of broken files.
+    for token in &lexed.tokens {
+        logging::println(&format!("{} {}", token.pos(), token));
+    }
+    exit_code(check_lexed(lexed, map))
+}
+
+fn parse_command(map: &mut SourceMap, file: &str) -> i32 {
+    let file = match read(map, file) {
+        Some(file) => file,
+        None => return FAILURE,
+    };
+    exit_code(parse(map, file).map(|tree| logging::println(&tree.to_string())))
+}
+
+fn check_command(map: &mut SourceMap, loader: &mut Loader, file: &str) -> i32 {
+    let id = match read(map, file) {
+        Some(id) => id,
+        None => return FAILURE,
+    };
+    exit_code(check(map, loader, id, Some(file)))
+}
+
+fn run_command(map: &mut SourceMap, loader: &mut Loader, file: &str, vm: bool) -> i32 {
+    if file.ends_with(".jpc") {
+        return exit_code(run_compiled(map, file));
+    }
+    let id = match read(map, file) {
+        Some(id) => id,
+        None => return FAILURE,
+    };
+    exit_code(run_src(map, loader, id, Some(file), vm))
+}
+
+/// Compiles a file to bytecode, next to it unless -o says otherwise.
+/// Only what the VM handles can be compiled, which the usage text lists.
+fn compile_command(map: &mut SourceMap, loader: &mut Loader, file: &str, output: Option<String>) -> i32 {
+    let id = match read(map, file) {
+        Some(id) => id,
+        None => return FAILURE,
+    };
+    let chunk = match compile(map, loader, id, Some(file)) {
+        Some(chunk) => chunk,
+        None => return FAILURE,
+    };
+    // Swap the extension for .jpc by default
+    let output = output.unwrap_or_else(|| match file.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => format!("{}.jpc", stem),
+        _ => format!("{}.jpc", file),
+    });
+    match fs::write(&output, bytecode::file::serialize(&chunk)) {
+        Ok(_) => SUCCESS,
+        Err(e) => {
+            logging::diagnostic(&io_error(format!("Could not write '{}': {}", output, e)), map);
+            FAILURE
+        }
+    }
+}
+
+fn disasm_command(map: &mut SourceMap, loader: &mut Loader, file: &str) -> i32 {
+    let chunk = if file.ends_with(".jpc") {
+        load(map, file)
+    } else {
+        read(map, file).and_then(|id| compile(map, loader, id, Some(file)))
+    };
+    // Disassembly already ends every line with a newline
+    exit_code(chunk.map(|chunk| logging::print(&chunk.to_string())))
+}
+
+fn eval_command(map: &mut SourceMap, loader: &mut Loader, code: &str, vm: bool) -> i32 {
+    let file = map.add("<inline>", code);
+    // Imports are looked for in the current directory
+    let value = run_src(map, loader, file, None, vm);
+    if let Some(value) = &value {
+        if !matches!(value, Value::Void) {
+            logging::println(&value.to_string());
+        }
+    }
+    exit_code(value)
+}
+
+/// Runs the command described by the command line arguments
+/// and returns the exit code.
+pub fn execute(args: &[String]) -> i32 {
+    let command = match args::parse_args(args) {
+        Ok(command) => command,
+        Err(reason) => {
+            logging::err(&format!("{}\n\n{}", reason, args::USAGE));
+            return USAGE;
+        }
+    };
+    // Every file the command loads, so errors can show their code
+    let map = &mut SourceMap::new();
+    match command {
+        Command::Run { file, vm, path } => run_command(map, &mut loader(path), &file, vm),
+        Command::Lex { file, max_errors } => lex_command(map, &file, max_errors),
+        Command::Parse { file } => parse_command(map, &file),
+        Command::Check { file, path } => check_command(map, &mut loader(path), &file),
+        Command::Compile { file, output, path } => compile_command(map, &mut loader(path), &file, output),
+        Command::Disasm { file, path } => disasm_command(map, &mut loader(path), &file),
+        Command::Eval { code, vm, path } => eval_command(map, &mut loader(path), &code, vm),
+        Command::Repl { path } => {
+            repl::start(loader(path));
+            SUCCESS
+        },
+        Command::Help => {
+            logging::println(args::USAGE);
+            SUCCESS
+        },
+    }
+}
@@ -0,0 +1,5 @@
+
+pub mod args;
+mod commands;
+mod repl;
+pub use commands::execute;
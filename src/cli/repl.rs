@@ -0,0 +1,314 @@
+
+use crate::interpreter::{ Interpreter, result::RunResult, value::Value };
+use crate::lexer::{ self, source::{ FileId, SourceMap }, token::Token };
+use crate::modules::Loader;
+use crate::parser::{ self, expr::Expr, result::ParseResult };
+use crate::types::{ Checker, JType };
+use crate::utils::{ codes, logging };
+use std::io::{ self, BufRead };
+use std::path::Path;
+
+const PROMPT: &str = ">>> ";
+const CONTINUE_PROMPT: &str = "... ";
+const FILENAME: &str = "<repl>";
+
+const HELP: &str = "\
+Enter code to run it. Variables declared with let stick around.
+Unfinished input, like an unclosed '(', continues on the next line.
+An empty line runs unfinished input anyway.
+
+Commands:
+  :type <code>      Show the type of the code without running it
+  :tokens <code>    Show the tokens of the code
+  :history          List everything entered so far
+  :again [n]        Run entry n from :history again, or the last one
+  :help             Show this message
+  :quit             Leave the REPL
+
+Lines can't be edited with the arrow keys or recalled from earlier
+sessions. Use :history and :again, or run the REPL under a line
+editor like rlwrap.";
+
+/// State kept alive between entries.
+struct Session {
+    interpreter: Interpreter,
+    // Knows every variable declared so far
+    checker: Checker,
+    history: Vec<String>,
+    // Every entry so far, so errors can point at them,
+    // along with every module they imported
+    map: SourceMap,
+    loader: Loader,
+}
+
+/// Returns true if the code ends before it's finished,
+/// meaning the REPL should ask for another line.
+fn is_incomplete(src: &str) -> bool {
+    let mut map = SourceMap::new();
+    let file = map.add(FILENAME, src);
+    let lexed = lexer::lexer::lex(map.get(file));
+    if lexed.has_errors() {
+        return lexed.diagnostics.iter().any(|d| d.code == codes::LEX_END_OF_FILE);
+    }
+    matches!(parser::parse(map.get(file), lexed.tokens),
+        ParseResult::Err(e) if e.code == codes::PARSE_END_OF_FILE)
+}
+
+/// Lexes an entry, logging every diagnostic.
+/// Returns the tokens only if there weren't any errors.
+fn lex(map: &SourceMap, file: FileId) -> Option<Vec<Token<'_>>> {
+    let lexed = lexer::lexer::lex(map.get(file));
+    for diagnostic in &lexed.diagnostics {
+        logging::diagnostic(diagnostic, map);
+    }
+    if lexed.has_errors() { None } else { Some(lexed.tokens) }
+}
+
+impl Session {
+    fn new(loader: Loader) -> Self {
+        Self {
+            interpreter: Interpreter::new(),
+            checker: Checker::new(),
+            history: Vec::new(),
+            map: SourceMap::new(),
+            loader,
+        }
+    }
+
+    /// Loads the modules an entry imports, handing them to
+    /// the checker. Imports are looked for in the current directory.
+    fn imports(&mut self, checker: &mut Checker, tree: &Expr) -> Option<Vec<usize>> {
+        self.loader.imports(&mut self.map, tree, Path::new(""), checker)
+    }
+
+    /// Type checks an entry, logging every type error.
+    /// Each entry gets its own scope, so it can
+    /// redeclare variables from earlier entries.
+    fn check(&self, checker: &mut Checker, tree: &Expr) -> Option<JType> {
+        checker.push_scope();
+        let checked = checker.check(tree);
+        for diagnostic in &checked.diagnostics {
+            logging::diagnostic(diagnostic, &self.map);
+        }
+        if checked.has_errors() { None } else { checked.t }
+    }
+
+    /// Type checks and runs an entry, returning its value and type.
+    /// Errors are logged, and leave the session the way it was.
+    fn eval(&mut self, src: &str) -> Option<(Value, JType)> {
+        let file = self.map.add(FILENAME, src);
+        let tokens = lex(&self.map, file)?;
+        let tree = match parser::parse(self.map.get(file), tokens) {
+            ParseResult::Ok(tree) => tree,
+            ParseResult::Err(e) => {
+                logging::diagnostic(&e, &self.map);
+                return None;
+            },
+        };
+        // Check against a copy so a failed entry doesn't declare anything
+        let mut checker = self.checker.clone();
+        let imports = self.imports(&mut checker, &tree)?;
+        let t = self.check(&mut checker, &tree)?;
+        self.loader.run_imports(&self.map, &imports, &mut self.interpreter)?;
+        // Gets its own scope like in the checker, which is thrown away if it fails
+        match self.interpreter.run_entry(&tree) {
+            RunResult::Ok(value) => {
+                self.checker = checker;
+                Some((value, t))
+            },
+            RunResult::Err(e) => {
+                logging::diagnostic(&e, &self.map);
+                None
+            },
+        }
+    }
+
+    /// Runs an entry, printing its value and type.
+    fn run(&mut self, src: &str) {
+        match self.eval(src) {
+            Some((Value::Void, _)) | None => {},
+            Some((value, t)) => logging::println(&format!("{}: {}", value, t)),
+        }
+    }
+
+    /// Prints the type of some code without running it.
+    fn show_type(&mut self, src: &str) {
+        let file = self.map.add(FILENAME, src);
+        let tokens = match lex(&self.map, file) {
+            Some(tokens) => tokens,
+            None => return,
+        };
+        let tree = match parser::parse(self.map.get(file), tokens) {
+            ParseResult::Ok(tree) => tree,
+            ParseResult::Err(e) => return logging::diagnostic(&e, &self.map),
+        };
+        let mut checker = self.checker.clone();
+        if self.imports(&mut checker, &tree).is_none() {
+            return;
+        }
+        if let Some(t) = self.check(&mut checker, &tree) {
+            logging::println(&t.to_string());
+        }
+    }
+
+    /// Prints the tokens of some code along with their positions.
+    fn show_tokens(&mut self, src: &str) {
+        // Broken code still has tokens worth showing
+        let file = self.map.add(FILENAME, src);
+        let lexed = lexer::lexer::lex(self.map.get(file));
+        for token in &lexed.tokens {
+            logging::println(&format!("{} {}", token.pos(), token));
+        }
+        for diagnostic in &lexed.diagnostics {
+            logging::diagnostic(diagnostic, &self.map);
+        }
+    }
+
+    /// Runs an earlier entry again.
+    /// Without a number, the last entry is used.
+    fn again(&mut self, arg: &str) {
+        let entry = if arg.is_empty() {
+            self.history.last()
+        } else {
+            // History is numbered from 1 when listed
+            arg.parse::<usize>().ok()
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|n| self.history.get(n))
+        };
+        match entry.cloned() {
+            Some(entry) => {
+                logging::println(&entry);
+                self.history.push(entry.clone());
+                self.run(&entry);
+            },
+            None => logging::err(&format!("No history entry '{}'.", arg)),
+        }
+    }
+
+    /// Handles a line starting with ':'.
+    /// Returns false if the REPL should stop.
+    fn command(&mut self, line: &str) -> bool {
+        let (command, arg) = match line.split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (line, ""),
+        };
+        match command {
+            ":type" | ":t" => self.show_type(arg),
+            ":tokens" => self.show_tokens(arg),
+            ":again" => self.again(arg),
+            ":history" => for (i, entry) in self.history.iter().enumerate() {
+                logging::println(&format!("{:>4}  {}", i + 1, entry));
+            },
+            ":help" | ":h" => logging::println(HELP),
+            ":quit" | ":q" => return false,
+            _ => logging::err(&format!("Unknown command '{}'. Try :help.", command)),
+        }
+        true
+    }
+}
+
+/// Starts the REPL, reading from stdin until it closes
+/// or the user quits.
+pub fn start(loader: Loader) {
+    let mut session = Session::new(loader);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut buffer = String::new();
+
+    logging::println("JPizza REPL. Type :help for help.");
+    loop {
+        logging::print(if buffer.is_empty() { PROMPT } else { CONTINUE_PROMPT });
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            // Closed stdin or unreadable input ends the session
+            _ => break,
+        };
+
+        if buffer.is_empty() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            // Commands aren't code, so they stay out of the history
+            if trimmed.starts_with(':') {
+                if !session.command(trimmed) {
+                    break;
+                }
+                continue;
+            }
+        } else {
+            buffer.push('\n');
+        }
+        buffer.push_str(&line);
+
+        // Wait for more lines if the entry isn't finished,
+        // unless an empty line says to run it as is.
+        if !line.trim().is_empty() && is_incomplete(&buffer) {
+            continue;
+        }
+        let entry = std::mem::take(&mut buffer);
+        session.history.push(entry.clone());
+        session.run(&entry);
+    }
+    logging::println("");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs entries one after another in the same session,
+    /// returning the value of the last one written out.
+    fn session(entries: &[&str]) -> Option<String> {
+        let mut session = Session::new(Loader::new(Vec::new()));
+        let (last, earlier) = entries.split_last().expect("at least one entry");
+        for entry in earlier {
+            session.eval(entry);
+        }
+        session.eval(last).map(|(value, _)| value.to_string())
+    }
+
+    #[test]
+    fn variables_stick_around() {
+        assert_eq!(session(&["let x = 1", "x + 1"]).as_deref(), Some("2"));
+        assert_eq!(session(&["fn f(n: int) -> int { n * 2 }", "f(3)"]).as_deref(), Some("6"));
+    }
+
+    #[test]
+    fn failed_entries_declare_nothing() {
+        // The runtime error used to leave y as "a" while the checker still said int
+        assert_eq!(session(&["let y = 1", "let y = \"a\" let z = 1 / 0", "y + 1"]).as_deref(), Some("2"));
+        assert_eq!(session(&["let y = 1 / 0", "y"]), None);
+        assert_eq!(session(&["enum E { A } 1 / 0", "enum E { B } E::B"]).as_deref(), Some("E::B"));
+    }
+
+    #[test]
+    fn fixed_modules_can_be_imported_again() {
+        let dir = std::env::temp_dir().join(format!("jpizza-repl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let module = dir.join("fixme.jpz");
+        std::fs::write(&module, "export let x = ").unwrap();
+        let mut session = Session::new(Loader::new(vec![dir.clone()]));
+        assert!(session.eval("import fixme").is_none());
+        std::fs::write(&module, "export let x = 5").unwrap();
+        let value = session.eval("import fixme\nfixme.x").map(|(value, _)| value.to_string());
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(value.as_deref(), Some("5"));
+    }
+
+    #[test]
+    fn redeclaring_doesnt_change_earlier_functions() {
+        let entries = ["let x = 1", "fn f() -> int { x }", "let x = \"s\"", "f() + 1"];
+        assert_eq!(session(&entries).as_deref(), Some("2"));
+        assert_eq!(session(&["let x = 1", "let x = \"s\"", "x"]).as_deref(), Some("\"s\""));
+    }
+
+    #[test]
+    fn continuation() {
+        assert!(is_incomplete("let x ="));
+        assert!(is_incomplete("(1 +\n2"));
+        assert!(!is_incomplete("(1 +\n2)"));
+        // Mistakes run straight away so the error shows up
+        assert!(!is_incomplete("1 + )"));
+    }
+}
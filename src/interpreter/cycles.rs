@@ -0,0 +1,213 @@
+use super::env::Env;
+use super::value::{ Class, Closure, Instance, Module, Value, Variant };
+use std::cell::{ Cell, RefCell };
+use std::collections::{ HashMap, HashSet };
+use std::rc::{ Rc, Weak };
+
+/*
+* Frees scopes that only keep each other alive, like the scope of
+* a call and the closure it returned when that's kept in the scope
+* the function was declared in. Rc can't free those by itself.
+*
+* It works like CPython's cycle collector. Every scope still alive
+* gets looked through, counting how many of the Rcs to each scope and
+* value come from the scopes and values found that way. Anything with
+* more Rcs than that is held by something else, like the interpreter
+* or a value it's in the middle of using, so it and everything it
+* leads to are still needed. The scopes left over can only be reached
+* from each other, so clearing their variables lets Rc free them.
+*
+* It runs whenever the number of scopes alive has doubled since the
+* last time, so programs that don't leave scopes behind never pay for it.
+*/
+
+/// How many scopes have to be alive before collecting at all.
+const MIN_THRESHOLD: usize = 1024;
+
+thread_local! {
+    // Every scope made on this thread, some of them already freed
+    static SCOPES: RefCell<Vec<Weak<Env>>> = const { RefCell::new(Vec::new()) };
+    static ALIVE: Cell<usize> = const { Cell::new(0) };
+    static THRESHOLD: Cell<usize> = const { Cell::new(MIN_THRESHOLD) };
+}
+
+/// Something behind an Rc that can lead to a scope.
+/// Holding one keeps it alive while the collector looks at it.
+enum Node {
+    Env(Rc<Env>),
+    Closure(Rc<Closure>),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+    Variant(Rc<Variant>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<Vec<(Value, Value)>>>),
+    Tuple(Rc<[Value]>),
+    Module(Rc<Module>),
+}
+
+impl Node {
+    /// Returns the node a value points at, if it can lead to a scope.
+    fn of(value: &Value) -> Option<Node> {
+        Some(match value {
+            Value::Fn(closure) => Node::Closure(Rc::clone(closure)),
+            Value::Class(class) => Node::Class(Rc::clone(class)),
+            Value::Instance(instance) => Node::Instance(Rc::clone(instance)),
+            Value::Variant(variant) => Node::Variant(Rc::clone(variant)),
+            Value::List(items) => Node::List(Rc::clone(items)),
+            Value::Map(entries) => Node::Map(Rc::clone(entries)),
+            Value::Tuple(items) => Node::Tuple(Rc::clone(items)),
+            Value::Module(module) => Node::Module(Rc::clone(module)),
+            _ => return None,
+        })
+    }
+
+    /// Identifies the node by where it's stored.
+    fn key(&self) -> *const () {
+        match self {
+            Node::Env(env) => Rc::as_ptr(env) as *const (),
+            Node::Closure(closure) => Rc::as_ptr(closure) as *const (),
+            Node::Class(class) => Rc::as_ptr(class) as *const (),
+            Node::Instance(instance) => Rc::as_ptr(instance) as *const (),
+            Node::Variant(variant) => Rc::as_ptr(variant) as *const (),
+            Node::List(items) => Rc::as_ptr(items) as *const (),
+            Node::Map(entries) => Rc::as_ptr(entries) as *const (),
+            Node::Tuple(items) => Rc::as_ptr(items) as *const (),
+            Node::Module(module) => Rc::as_ptr(module) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Env(env) => Rc::strong_count(env),
+            Node::Closure(closure) => Rc::strong_count(closure),
+            Node::Class(class) => Rc::strong_count(class),
+            Node::Instance(instance) => Rc::strong_count(instance),
+            Node::Variant(variant) => Rc::strong_count(variant),
+            Node::List(items) => Rc::strong_count(items),
+            Node::Map(entries) => Rc::strong_count(entries),
+            Node::Tuple(items) => Rc::strong_count(items),
+            Node::Module(module) => Rc::strong_count(module),
+        }
+    }
+
+    /// Adds every node this one holds an Rc to, once for each Rc.
+    /// Anything borrowed right now gets skipped, which only
+    /// makes what it holds look like it's needed.
+    fn children(&self, out: &mut Vec<Node>) {
+        let values = |values: &[Value], out: &mut Vec<Node>| out.extend(values.iter().filter_map(Node::of));
+        match self {
+            Node::Env(env) => {
+                out.extend(env.parent().map(|parent| Node::Env(Rc::clone(parent))));
+                env.values(|value| out.extend(Node::of(value)));
+            },
+            Node::Closure(closure) => out.push(Node::Env(Rc::clone(&closure.env))),
+            Node::Class(class) => out.push(Node::Env(Rc::clone(&class.env))),
+            Node::Instance(instance) => {
+                out.push(Node::Class(Rc::clone(&instance.class)));
+                values(&instance.fields, out);
+            },
+            Node::Variant(variant) => values(&variant.fields, out),
+            Node::List(items) => if let Ok(items) = items.try_borrow() {
+                values(&items, out);
+            },
+            Node::Map(entries) => if let Ok(entries) = entries.try_borrow() {
+                for (key, value) in entries.iter() {
+                    out.extend(Node::of(key));
+                    out.extend(Node::of(value));
+                }
+            },
+            Node::Tuple(items) => values(items, out),
+            Node::Module(module) => out.extend(module.exports.values().filter_map(Node::of)),
+        }
+    }
+}
+
+/// Remembers a new scope, collecting first if enough have piled up.
+pub fn track(env: &Rc<Env>) {
+    let alive = ALIVE.try_with(|alive| {
+        alive.set(alive.get() + 1);
+        alive.get()
+    }).unwrap_or(0);
+    // Nothing is tracked while the thread is shutting down
+    let _ = SCOPES.try_with(|scopes| {
+        let mut scopes = scopes.borrow_mut();
+        // A Weak keeps the memory of a freed scope around,
+        // so those are dropped every so often
+        if scopes.len() >= MIN_THRESHOLD.max(alive * 2) {
+            scopes.retain(|scope| scope.strong_count() > 0);
+        }
+        scopes.push(Rc::downgrade(env));
+    });
+    if alive > THRESHOLD.try_with(Cell::get).unwrap_or(usize::MAX) {
+        collect();
+    }
+}
+
+/// Counts a scope being freed.
+pub fn untrack() {
+    let _ = ALIVE.try_with(|alive| alive.set(alive.get().saturating_sub(1)));
+}
+
+/// Frees every scope that's only kept alive by other scopes.
+pub fn collect() {
+    let scopes: Vec<Rc<Env>> = SCOPES.with(|scopes| {
+        let mut scopes = scopes.borrow_mut();
+        scopes.retain(|scope| scope.strong_count() > 0);
+        scopes.iter().filter_map(Weak::upgrade).collect()
+    });
+
+    // Every node found, how many Rcs to it come from other
+    // nodes, and the nodes it holds Rcs to
+    let mut nodes: HashMap<*const (), (Node, usize, Vec<*const ()>)> = HashMap::new();
+    let mut stack = Vec::new();
+    for scope in scopes {
+        let node = Node::Env(scope);
+        stack.push(node.key());
+        nodes.insert(node.key(), (node, 0, Vec::new()));
+    }
+    let mut children = Vec::new();
+    while let Some(key) = stack.pop() {
+        nodes[&key].0.children(&mut children);
+        let mut keys = Vec::with_capacity(children.len());
+        for child in children.drain(..) {
+            let child_key = child.key();
+            keys.push(child_key);
+            nodes.entry(child_key)
+                .or_insert_with(|| {
+                    stack.push(child_key);
+                    (child, 0, Vec::new())
+                })
+                .1 += 1;
+        }
+        nodes.get_mut(&key).expect("found above").2 = keys;
+    }
+
+    // The collector holds one Rc to every node itself
+    let mut needed: Vec<*const ()> = nodes.iter()
+        .filter(|(_, (node, internal, _))| node.strong_count() > internal + 1)
+        .map(|(key, _)| *key)
+        .collect();
+    let mut reached: HashSet<*const ()> = needed.iter().copied().collect();
+    while let Some(key) = needed.pop() {
+        for child in &nodes[&key].2 {
+            if reached.insert(*child) {
+                needed.push(*child);
+            }
+        }
+    }
+
+    // Values dropped here can free more scopes, so nothing can
+    // be borrowed anymore by the time they are
+    let cleared: Vec<_> = nodes.iter()
+        .filter(|(key, _)| !reached.contains(*key))
+        .filter_map(|(_, (node, _, _))| match node {
+            Node::Env(env) => Some(env.clear()),
+            _ => None,
+        })
+        .collect();
+    drop(nodes);
+    drop(cleared);
+
+    let alive = ALIVE.with(Cell::get);
+    THRESHOLD.with(|threshold| threshold.set(MIN_THRESHOLD.max(alive * 2)));
+}
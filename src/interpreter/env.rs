@@ -0,0 +1,133 @@
+use super::cycles;
+use super::value::{ Class, Closure, Value };
+use crate::parser::expr::{ Function, StructDecl };
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/*
+* Scopes are a chain of environments, each pointing at the one
+* around it. Closures hold an Rc to the environment they were
+* made in, so the variables they use stay alive for as long as
+* the closure does, even after the scope itself has ended.
+*
+* A function or struct stored in the scope it was made in would
+* keep that scope alive while the scope keeps it alive, so neither
+* would ever get freed. The scope keeps those without the Rc back
+* to itself instead, and puts it back whenever they're looked up.
+* Closures still see the variables themselves, not copies, so
+* assigning to one is seen by every closure that uses it.
+*
+* A closure stored in a scope around the one it was made in, like
+* one returned by a function and kept next to it, still makes a
+* cycle through both scopes. Those are left to cycles.rs.
+*/
+
+/// What a scope holds for a name.
+enum Binding {
+    Value(Value),
+    // A function made in this scope, and its name if it has one
+    Function(Option<String>, Rc<Function>),
+    // A struct declared in this scope
+    Class(Rc<StructDecl>),
+}
+
+/// The variables of one scope.
+pub struct Env {
+    vars: RefCell<HashMap<String, Binding>>,
+    parent: Option<Rc<Env>>,
+}
+
+impl Env {
+    /// Makes an outermost scope.
+    pub fn new() -> Rc<Self> {
+        let env = Rc::new(Self { vars: RefCell::new(HashMap::new()), parent: None });
+        cycles::track(&env);
+        env
+    }
+
+    /// Makes a scope inside another one.
+    pub fn child(parent: &Rc<Env>) -> Rc<Self> {
+        let env = Rc::new(Self { vars: RefCell::new(HashMap::new()), parent: Some(Rc::clone(parent)) });
+        cycles::track(&env);
+        env
+    }
+
+    /// Returns the scope around this one.
+    pub fn parent(&self) -> Option<&Rc<Env>> {
+        self.parent.as_ref()
+    }
+
+    /// Calls `f` on every value this scope holds.
+    /// Does nothing if the variables are borrowed right now.
+    pub fn values(&self, mut f: impl FnMut(&Value)) {
+        if let Ok(vars) = self.vars.try_borrow() {
+            for binding in vars.values() {
+                if let Binding::Value(value) = binding {
+                    f(value);
+                }
+            }
+        }
+    }
+
+    /// Takes every variable out of the scope, which
+    /// frees them once what's returned is dropped.
+    pub fn clear(&self) -> HashMap<String, impl Sized> {
+        std::mem::take(&mut *self.vars.borrow_mut())
+    }
+
+    /// Turns a value into what this scope keeps for it,
+    /// dropping the Rc back to this scope if it has one.
+    fn bind(self: &Rc<Self>, value: Value) -> Binding {
+        match value {
+            Value::Fn(closure) if Rc::ptr_eq(&closure.env, self) =>
+                Binding::Function(closure.name.clone(), Rc::clone(&closure.function)),
+            Value::Class(class) if Rc::ptr_eq(&class.env, self) => Binding::Class(Rc::clone(&class.decl)),
+            value => Binding::Value(value),
+        }
+    }
+
+    /// Turns what this scope keeps back into a value.
+    fn value(self: &Rc<Self>, binding: &Binding) -> Value {
+        match binding {
+            Binding::Value(value) => value.clone(),
+            Binding::Function(name, function) => Value::Fn(Rc::new(Closure {
+                name: name.clone(),
+                function: Rc::clone(function),
+                env: Rc::clone(self),
+            })),
+            Binding::Class(decl) => Value::Class(Rc::new(Class { decl: Rc::clone(decl), env: Rc::clone(self) })),
+        }
+    }
+
+    /// Declares a variable in this scope.
+    pub fn define(self: &Rc<Self>, name: &str, value: Value) {
+        let binding = self.bind(value);
+        self.vars.borrow_mut().insert(name.to_string(), binding);
+    }
+
+    /// Changes the closest variable with the name, from this scope outwards.
+    /// Returns false if there isn't one.
+    pub fn assign(self: &Rc<Self>, name: &str, value: Value) -> bool {
+        if self.vars.borrow().contains_key(name) {
+            let binding = self.bind(value);
+            self.vars.borrow_mut().insert(name.to_string(), binding);
+            return true;
+        }
+        self.parent.as_ref().is_some_and(|parent| parent.assign(name, value))
+    }
+
+    /// Looks a variable up, from this scope outwards.
+    pub fn get(self: &Rc<Self>, name: &str) -> Option<Value> {
+        if let Some(binding) = self.vars.borrow().get(name) {
+            return Some(self.value(binding));
+        }
+        self.parent.as_ref().and_then(|parent| parent.get(name))
+    }
+}
+
+impl Drop for Env {
+    fn drop(&mut self) {
+        cycles::untrack();
+    }
+}
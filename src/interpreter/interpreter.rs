@@ -0,0 +1,858 @@
+
+use crate::utils::{ codes, diagnostic::Diagnostic };
+use super::env::Env;
+use super::value::{ set_entry, Class, Closure, Instance, Module, Value, Variant };
+use super::result::{ Flow, RunResult };
+use crate::parser::expr::{ Arm, EnumDecl, Expr, Function, LoopLabel, Member, Pattern };
+use crate::lexer::token::Position;
+use crate::stdlib;
+use crate::types::JType;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// How many calls can be running at once. Every call uses
+/// a lot of Rust's own stack, so this stays well below the
+/// point where the stack main gives the interpreter runs out.
+const MAX_CALL_DEPTH: usize = 2000;
+
+/// Integer and float versions of a binary operation.
+/// The integer version returns None if it overflows.
+struct Operation {
+    name: &'static str,
+    // Whether a zero on the right is an error for integers
+    zero_check: bool,
+    int: fn(i64, i64) -> Option<i64>,
+    float: fn(f64, f64) -> f64,
+}
+
+const ADD: Operation = Operation { name: "add", zero_check: false, int: i64::checked_add, float: |a, b| a + b };
+const SUB: Operation = Operation { name: "subtract", zero_check: false, int: i64::checked_sub, float: |a, b| a - b };
+const MUL: Operation = Operation { name: "multiply", zero_check: false, int: i64::checked_mul, float: |a, b| a * b };
+const DIV: Operation = Operation { name: "divide", zero_check: true, int: i64::checked_div, float: |a, b| a / b };
+
+/// A comparison, as a test on how the two sides are ordered.
+/// The ordering is None when floats can't be ordered because of NaN.
+type Comparison = fn(Option<Ordering>) -> bool;
+
+const EQ: Comparison = |o| o == Some(Ordering::Equal);
+const NE: Comparison = |o| o != Some(Ordering::Equal);
+const LT: Comparison = |o| o == Some(Ordering::Less);
+const LE: Comparison = |o| matches!(o, Some(Ordering::Less | Ordering::Equal));
+const GT: Comparison = |o| o == Some(Ordering::Greater);
+const GE: Comparison = |o| matches!(o, Some(Ordering::Greater | Ordering::Equal));
+
+/// Evaluates expression trees.
+/// Keeps its variables between calls to eval,
+/// so one interpreter can run a whole session.
+pub struct Interpreter {
+    // Variables of the scope being run, which
+    // can see the ones in every scope around it
+    env: Rc<Env>,
+    // How many calls are running
+    calls: usize,
+    // Every enum declared so far. Like in the checker
+    // these are looked up by name from anywhere.
+    enums: HashMap<String, Rc<EnumDecl>>,
+    // Modules the code being run can import, by name
+    modules: HashMap<String, Rc<Module>>,
+    // Natives and libraries from the standard library. Anything
+    // declared with the same name hides them.
+    natives: HashMap<String, Value>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self {
+            env: Env::new(),
+            calls: 0,
+            enums: HashMap::new(),
+            modules: HashMap::new(),
+            natives: natives(),
+        }
+    }
+
+    /// Makes a module that's already been run available to import.
+    pub fn provide(&mut self, module: Rc<Module>) {
+        self.modules.insert(module.name.clone(), module);
+    }
+
+    /// Applies an arithmetic operation to two values.
+    /// Follows the same promotion rules as Checker::arithmetic,
+    /// so if either side is a float the result is a float.
+    fn arithmetic(&self, pos: &Position, op: &Operation, left: Value, right: Value) -> Flow<Value> {
+        match (left, right) {
+            (Value::Int(a), Value::Int(b)) => {
+                // Dividing by zero is the only way checked_div fails
+                // other than overflow, so catch it first.
+                if op.zero_check && b == 0 {
+                    return Flow::Err(Diagnostic::error(codes::DIVISION_BY_ZERO,
+                        "Integer division by zero.", *pos));
+                }
+                match (op.int)(a, b) {
+                    Some(n) => Flow::Ok(Value::Int(n)),
+                    None => Flow::Err(Diagnostic::error(codes::OVERFLOW,
+                        format!("Integer overflow when trying to {} {} and {}.", op.name, a, b), *pos)
+                        .with_note("Integers are 64 bit. Use a float for bigger numbers.")),
+                }
+            },
+            (Value::Int(a), Value::Float(b)) => Flow::Ok(Value::Float((op.float)(a as f64, b))),
+            (Value::Float(a), Value::Int(b)) => Flow::Ok(Value::Float((op.float)(a, b as f64))),
+            (Value::Float(a), Value::Float(b)) => Flow::Ok(Value::Float((op.float)(a, b))),
+            (left, right) => Flow::Err(Diagnostic::error(codes::RUNTIME_TYPE_MISMATCH,
+                format!("You cannot {} '{}' and '{}'", op.name, left.get_type(), right.get_type()), *pos)),
+        }
+    }
+
+    /// Evaluates both sides of a binary operation and then applies it.
+    fn binary(&mut self, pos: &Position, op: &Operation, left: &Expr, right: &Expr) -> Flow<Value> {
+        let left = match self.eval(left) {
+            Flow::Ok(v) => v,
+            err => return err,
+        };
+        let right = match self.eval(right) {
+            Flow::Ok(v) => v,
+            err => return err,
+        };
+        self.arithmetic(pos, op, left, right)
+    }
+
+    /// Evaluates both sides of a comparison and compares them.
+    /// Ints get turned into floats when compared with one,
+    /// anything else has to be the same type on both sides.
+    fn comparison(&mut self, pos: &Position, test: Comparison, left: &Expr, right: &Expr) -> Flow<Value> {
+        let left = match self.eval(left) {
+            Flow::Ok(v) => v,
+            err => return err,
+        };
+        let right = match self.eval(right) {
+            Flow::Ok(v) => v,
+            err => return err,
+        };
+        let ordering = match (&left, &right) {
+            (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+            (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Str(a), Value::Str(b)) => a.partial_cmp(b),
+            (Value::Char(a), Value::Char(b)) => a.partial_cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => a.partial_cmp(b),
+            // Null is only equal to itself. The checker only lets
+            // optionals be compared with == and !=.
+            (Value::Null, Value::Null) => Some(Ordering::Equal),
+            (Value::Null, _) | (_, Value::Null) => None,
+            (left, right) => return Flow::Err(Diagnostic::error(codes::RUNTIME_TYPE_MISMATCH,
+                format!("You cannot compare '{}' and '{}'", left.get_type(), right.get_type()), *pos)),
+        };
+        Flow::Ok(Value::Bool(test(ordering)))
+    }
+
+    /// Evaluates something that has to be a bool, like an operand
+    /// of a logical operator or a condition. Anything it evaluates
+    /// to is a Value::Bool.
+    fn condition(&mut self, operand: &Expr) -> Flow<Value> {
+        match self.eval(operand) {
+            Flow::Ok(Value::Bool(b)) => Flow::Ok(Value::Bool(b)),
+            Flow::Ok(v) => Flow::Err(Diagnostic::error(codes::RUNTIME_TYPE_MISMATCH,
+                format!("Expected 'bool' but found '{}'", v.get_type()), *operand.pos())),
+            flow => flow,
+        }
+    }
+
+    /// Evaluates `&&` or `||`. The right side is only evaluated
+    /// when the left side doesn't already decide the result,
+    /// which is when it isn't equal to `short`.
+    fn logical(&mut self, short: bool, left: &Expr, right: &Expr) -> Flow<Value> {
+        match self.condition(left) {
+            Flow::Ok(Value::Bool(b)) if b == short => Flow::Ok(Value::Bool(short)),
+            Flow::Ok(_) => self.condition(right),
+            flow => flow,
+        }
+    }
+
+    /// Evaluates statements in a new scope that's
+    /// thrown away afterwards, even if one of them fails.
+    fn block(&mut self, exprs: &[Expr]) -> Flow<Value> {
+        let inner = Env::child(&self.env);
+        let outer = std::mem::replace(&mut self.env, inner);
+        let value = self.body(exprs);
+        self.env = outer;
+        value
+    }
+
+    /// Evaluates statements in the current scope.
+    /// Bodies evaluate to their last expression.
+    fn body(&mut self, exprs: &[Expr]) -> Flow<Value> {
+        // Enums can be used above where they're declared, like in the checker
+        for expr in exprs {
+            if let Expr::Enum(_, decl) = expr.unexported() {
+                self.enums.insert(decl.name.clone(), Rc::clone(decl));
+            }
+        }
+        let mut value = Value::Void;
+        for expr in exprs {
+            value = match self.eval(expr) {
+                Flow::Ok(v) => v,
+                flow => return flow,
+            };
+        }
+        Flow::Ok(value)
+    }
+
+    /// Runs the body of a loop once. Returns None if the
+    /// loop should keep going, otherwise how it ends.
+    fn iteration(&mut self, label: &Option<LoopLabel>, body: &Expr) -> Option<Flow<Value>> {
+        // Jumps without a label belong to the innermost loop
+        let ours = |name: &Option<String>| match name {
+            Some(name) => label.as_ref().is_some_and(|label| label.name == *name),
+            None => true,
+        };
+        match self.eval(body) {
+            Flow::Ok(_) => None,
+            Flow::Continue(_, name) if ours(&name) => None,
+            Flow::Break(_, name) if ours(&name) => Some(Flow::Ok(Value::Void)),
+            // Errors and jumps to outer loops keep going up
+            flow => Some(flow),
+        }
+    }
+
+    /// Evaluates an int, like the start or end of a range.
+    fn int(&mut self, expr: &Expr) -> Flow<i64> {
+        match self.eval(expr) {
+            Flow::Ok(Value::Int(n)) => Flow::Ok(n),
+            Flow::Ok(v) => Flow::Err(Diagnostic::error(codes::RUNTIME_TYPE_MISMATCH,
+                format!("Expected 'int' but found '{}'", v.get_type()), *expr.pos())),
+            Flow::Err(e) => Flow::Err(e),
+            Flow::Break(pos, label) => Flow::Break(pos, label),
+            Flow::Continue(pos, label) => Flow::Continue(pos, label),
+            Flow::Return(value) => Flow::Return(value),
+        }
+    }
+
+    /// Makes a function value that holds on to the current scope.
+    fn closure(&self, name: Option<&str>, function: &Rc<Function>) -> Value {
+        Value::Fn(Rc::new(Closure {
+            name: name.map(|name| name.to_string()),
+            function: Rc::clone(function),
+            env: Rc::clone(&self.env),
+        }))
+    }
+
+    /// Calls a function or a struct's constructor.
+    /// The callee is evaluated first, then the arguments.
+    fn call(&mut self, pos: &Position, callee: &Expr, args: &[Expr]) -> Flow<Value> {
+        let callee = match self.eval(callee) {
+            Flow::Ok(v) => v,
+            flow => return flow,
+        };
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            match self.eval(arg) {
+                Flow::Ok(v) => values.push(v),
+                flow => return flow,
+            }
+        }
+        self.apply(pos, callee, values)
+    }
+
+    /// Calls something that's already been evaluated
+    /// with arguments that have been too.
+    pub fn apply(&mut self, pos: &Position, callee: Value, values: Vec<Value>) -> Flow<Value> {
+        let expected = match &callee {
+            Value::Fn(closure) => closure.function.params.len(),
+            Value::Native(native) => match &native.t {
+                JType::Fn(params, _) => params.len(),
+                _ => 0,
+            },
+            Value::Class(class) => class.decl.fields.len(),
+            Value::Constructor(decl, index) => decl.variants[*index].fields.len(),
+            v => return Flow::Err(Diagnostic::error(codes::RUNTIME_TYPE_MISMATCH,
+                format!("You cannot call '{}'", v.get_type()), *pos)),
+        };
+        if expected != values.len() {
+            return Flow::Err(Diagnostic::error(codes::RUNTIME_TYPE_MISMATCH,
+                format!("Expected {} arguments but found {}", expected, values.len()), *pos));
+        }
+        match callee {
+            Value::Fn(closure) => self.call_closure(pos, &closure, values),
+            Value::Native(native) => (native.call)(self, pos, values),
+            Value::Class(class) => Flow::Ok(Value::Instance(Rc::new(Instance { class, fields: values }))),
+            Value::Constructor(decl, index) => Flow::Ok(Value::Variant(Rc::new(Variant { decl, index, fields: values }))),
+            _ => unreachable!("checked above"),
+        }
+    }
+
+    /// Runs the body of a function in a new scope inside the one
+    /// it was made in, with its parameters set to the arguments.
+    fn call_closure(&mut self, pos: &Position, closure: &Closure, args: Vec<Value>) -> Flow<Value> {
+        let env = Env::child(&closure.env);
+        for (param, arg) in closure.function.params.iter().zip(args) {
+            env.define(&param.name, arg);
+        }
+        if self.calls == MAX_CALL_DEPTH {
+            return Flow::Err(Diagnostic::error(codes::STACK_OVERFLOW,
+                format!("Too many calls inside each other, the limit is {}.", MAX_CALL_DEPTH), *pos)
+                .with_note("This usually means a function keeps calling itself forever."));
+        }
+        let outer = std::mem::replace(&mut self.env, env);
+        self.calls += 1;
+        let flow = self.eval(&closure.function.body);
+        self.calls -= 1;
+        self.env = outer;
+        match flow {
+            Flow::Ok(value) | Flow::Return(value) => Flow::Ok(value),
+            Flow::Err(e) => Flow::Err(e),
+            // The checker doesn't let loops be jumped out of from a function
+            Flow::Break(pos, _) | Flow::Continue(pos, _) => Flow::Err(Diagnostic::error(
+                codes::RUNTIME_MISPLACED_BREAK, "'break' and 'continue' can only be used inside a loop", pos)),
+        }
+    }
+
+    /// Gets a field of an instance, or one of its methods
+    /// with `self` already filled in.
+    fn member(&self, pos: &Position, instance: &Rc<Instance>, name: &str) -> Flow<Value> {
+        let decl = &instance.class.decl;
+        if let Some(i) = decl.fields.iter().position(|field| field.name == name) {
+            return Flow::Ok(instance.fields[i].clone());
+        }
+        match decl.methods.iter().find(|method| method.name == name) {
+            // Methods run in the scope the struct was declared
+            // in, with one more around them that has self
+            Some(method) => {
+                let env = Env::child(&instance.class.env);
+                env.define("self", Value::Instance(Rc::clone(instance)));
+                Flow::Ok(Value::Fn(Rc::new(Closure {
+                    name: Some(method.name.clone()),
+                    function: Rc::clone(&method.function),
+                    env,
+                })))
+            },
+            None => Flow::Err(Diagnostic::error(codes::RUNTIME_TYPE_MISMATCH,
+                format!("'{}' doesn't have a field called '{}'", decl.name, name), *pos)),
+        }
+    }
+
+    /// Gets an item of a tuple by its index, eg the `0` in `pair.0`.
+    fn item(&self, items: &[Value], member: &Member) -> Flow<Value> {
+        match member.name.parse::<usize>().ok().and_then(|i| items.get(i)) {
+            Some(item) => Flow::Ok(item.clone()),
+            None => Flow::Err(Diagnostic::error(codes::RUNTIME_TYPE_MISMATCH,
+                format!("A tuple of {} doesn't have a field called '{}'", items.len(), member.name), member.pos)),
+        }
+    }
+
+    /// Gets a variant of an enum. Variants that hold
+    /// something give a constructor to call instead.
+    fn variant(&self, pos: &Position, name: &str, variant: &str) -> Flow<Value> {
+        let decl = match self.enums.get(name) {
+            Some(decl) => Rc::clone(decl),
+            None => return Flow::Err(Diagnostic::error(codes::RUNTIME_UNDEFINED_VARIABLE,
+                format!("'{}' is not defined", name), *pos)),
+        };
+        match decl.variants.iter().position(|v| v.name == variant) {
+            Some(index) if decl.variants[index].fields.is_empty() =>
+                Flow::Ok(Value::Variant(Rc::new(Variant { decl, index, fields: Vec::new() }))),
+            Some(index) => Flow::Ok(Value::Constructor(decl, index)),
+            None => Flow::Err(Diagnostic::error(codes::RUNTIME_UNDEFINED_VARIABLE,
+                format!("'{}' doesn't have a variant called '{}'", name, variant), *pos)),
+        }
+    }
+
+    /// Runs the first arm whose pattern matches the value
+    /// and whose guard, if it has one, is true. Each arm
+    /// gets its own scope for the names its pattern binds.
+    fn match_expr(&mut self, pos: &Position, value: &Expr, arms: &[Arm]) -> Flow<Value> {
+        let value = match self.eval(value) {
+            Flow::Ok(v) => v,
+            flow => return flow,
+        };
+        for arm in arms {
+            let inner = Env::child(&self.env);
+            if !matches(&arm.pattern, &value, &inner) {
+                continue;
+            }
+            let outer = std::mem::replace(&mut self.env, inner);
+            let flow = match &arm.guard {
+                Some(guard) => match self.condition(guard) {
+                    Flow::Ok(Value::Bool(true)) => Some(self.eval(&arm.body)),
+                    Flow::Ok(_) => None,
+                    flow => Some(flow),
+                },
+                None => Some(self.eval(&arm.body)),
+            };
+            self.env = outer;
+            if let Some(flow) = flow {
+                return flow;
+            }
+        }
+        // The checker makes sure the arms cover everything
+        Flow::Err(Diagnostic::error(codes::NO_MATCH,
+            format!("None of the arms match {}", value), *pos))
+    }
+
+    /// Evaluates a whole program, or a line of the REPL.
+    /// The outermost body shares the interpreter's scope,
+    /// so its variables are still around afterwards.
+    pub fn run(&mut self, expr: &Expr) -> RunResult<Value> {
+        let flow = match expr {
+            Expr::Body(_, exprs) => self.body(exprs),
+            _ => self.eval(expr),
+        };
+        match flow {
+            Flow::Ok(value) => RunResult::Ok(value),
+            Flow::Err(e) => RunResult::Err(e),
+            // The checker doesn't let these get this far
+            Flow::Break(pos, _) | Flow::Continue(pos, _) => RunResult::Err(Diagnostic::error(
+                codes::RUNTIME_MISPLACED_BREAK, "'break' and 'continue' can only be used inside a loop", pos)),
+            // Neither does it let return get out of a function,
+            // but there's nothing left to stop so just use the value
+            Flow::Return(value) => RunResult::Ok(value),
+        }
+    }
+
+    /// Evaluates a REPL entry in a scope of its own inside the
+    /// earlier ones, like the checker does, so redeclaring a variable
+    /// doesn't change what functions from earlier entries see. The
+    /// scope is only kept if the entry works, otherwise everything
+    /// it declared goes away again.
+    pub fn run_entry(&mut self, expr: &Expr) -> RunResult<Value> {
+        let env = Rc::clone(&self.env);
+        let enums = self.enums.clone();
+        self.env = Env::child(&env);
+        let result = self.run(expr);
+        if let RunResult::Err(_) = result {
+            self.env = env;
+            self.enums = enums;
+        }
+        result
+    }
+
+    /// Runs a module in a scope of its own, with only the modules
+    /// it imports available, and collects what it exports.
+    /// Enums still end up shared, like they are in the checker.
+    pub fn run_module(&mut self, name: &str, tree: &Expr, imports: Vec<Rc<Module>>) -> RunResult<Rc<Module>> {
+        let imports = imports.into_iter().map(|module| (module.name.clone(), module)).collect();
+        let outer = std::mem::replace(&mut self.env, Env::new());
+        let modules = std::mem::replace(&mut self.modules, imports);
+        let result = self.run(tree);
+        let env = std::mem::replace(&mut self.env, outer);
+        self.modules = modules;
+        if let RunResult::Err(e) = result {
+            return RunResult::Err(e);
+        }
+        let decls = match tree {
+            Expr::Body(_, exprs) => exprs.as_slice(),
+            _ => std::slice::from_ref(tree),
+        };
+        // Enums are exported too, but they aren't values
+        let exports = decls.iter()
+            .filter(|expr| matches!(expr, Expr::Export(_, _)))
+            .filter_map(|expr| expr.declared_name())
+            .filter_map(|name| env.get(name).map(|value| (name.to_string(), value)))
+            .collect();
+        RunResult::Ok(Rc::new(Module { name: name.to_string(), exports }))
+    }
+
+    /// Defines what an import asks for. Everything it
+    /// uses was checked to exist before running.
+    fn import(&mut self, pos: &Position, module: &str, names: Option<&[Member]>) -> Flow<Value> {
+        let module = match self.modules.get(module) {
+            Some(module) => Rc::clone(module),
+            None => return Flow::Err(Diagnostic::error(codes::RUNTIME_UNDEFINED_VARIABLE,
+                format!("There is no module called '{}'", module), *pos)),
+        };
+        match names {
+            Some(names) => for name in names {
+                // Enums aren't values, so there's nothing to define for them
+                if let Some(value) = module.exports.get(&name.name) {
+                    self.env.define(&name.name, value.clone());
+                }
+            },
+            None => self.env.define(&module.name.clone(), Value::Module(module)),
+        }
+        Flow::Ok(Value::Void)
+    }
+
+    /// Evaluates an expression to a value.
+    fn eval(&mut self, expr: &Expr) -> Flow<Value> {
+        match expr {
+            Expr::Body(_, exprs) => self.block(exprs),
+            Expr::Let(_, name, _, value) => {
+                let value = match self.eval(value) {
+                    Flow::Ok(v) => v,
+                    err => return err,
+                };
+                self.env.define(name, value);
+                Flow::Ok(Value::Void)
+            },
+            Expr::Assign(pos, name, value) => {
+                let value = match self.eval(value) {
+                    Flow::Ok(v) => v,
+                    err => return err,
+                };
+                if !self.env.assign(name, value) {
+                    return Flow::Err(Diagnostic::error(codes::RUNTIME_UNDEFINED_VARIABLE,
+                        format!("'{}' is not defined", name), *pos));
+                }
+                Flow::Ok(Value::Void)
+            },
+            // The function goes in the scope it holds on to, so it can call itself
+            Expr::Fn(_, name, function) => {
+                self.env.define(name, self.closure(Some(name), function));
+                Flow::Ok(Value::Void)
+            },
+            Expr::Lambda(_, function) => Flow::Ok(self.closure(None, function)),
+            Expr::Call(pos, callee, args) => self.call(pos, callee, args),
+            Expr::Struct(_, decl) => {
+                let class = Class { decl: Rc::clone(decl), env: Rc::clone(&self.env) };
+                self.env.define(&decl.name, Value::Class(Rc::new(class)));
+                Flow::Ok(Value::Void)
+            },
+            // Already added by the body it's in
+            Expr::Enum(_, _) => Flow::Ok(Value::Void),
+            Expr::Variant(pos, name, variant) => self.variant(pos, name, &variant.name),
+            Expr::Match(pos, value, arms) => self.match_expr(pos, value, arms),
+            Expr::Import(pos, module, names) => self.import(pos, &module.name, names.as_deref()),
+            Expr::Export(_, decl) => self.eval(decl),
+            Expr::Field(_, object, member) => match self.eval(object) {
+                Flow::Ok(Value::Instance(instance)) => self.member(&member.pos, &instance, &member.name),
+                Flow::Ok(Value::Tuple(items)) => self.item(&items, member),
+                Flow::Ok(Value::Module(module)) => match module.exports.get(&member.name) {
+                    Some(value) => Flow::Ok(value.clone()),
+                    None => Flow::Err(Diagnostic::error(codes::RUNTIME_UNDEFINED_VARIABLE,
+                        format!("'{}' doesn't have anything called '{}'", module.name, member.name), member.pos)),
+                },
+                Flow::Ok(v) => Flow::Err(Diagnostic::error(codes::RUNTIME_TYPE_MISMATCH,
+                    format!("'{}' doesn't have fields", v.get_type()), member.pos)),
+                flow => flow,
+            },
+            Expr::Return(_, value) => match value {
+                Some(value) => match self.eval(value) {
+                    Flow::Ok(v) => Flow::Return(v),
+                    flow => flow,
+                },
+                None => Flow::Return(Value::Void),
+            },
+            Expr::If(_, condition, then, otherwise) => match (self.condition(condition), otherwise) {
+                (Flow::Ok(Value::Bool(true)), _) => match self.eval(then) {
+                    // Without an else the if is void either way
+                    Flow::Ok(_) if otherwise.is_none() => Flow::Ok(Value::Void),
+                    flow => flow,
+                },
+                (Flow::Ok(_), Some(otherwise)) => self.eval(otherwise),
+                (Flow::Ok(_), None) => Flow::Ok(Value::Void),
+                (flow, _) => flow,
+            },
+            Expr::While(_, label, condition, body) => loop {
+                match self.condition(condition) {
+                    Flow::Ok(Value::Bool(true)) => {},
+                    Flow::Ok(_) => break Flow::Ok(Value::Void),
+                    flow => break flow,
+                }
+                if let Some(flow) = self.iteration(label, body) {
+                    break flow;
+                }
+            },
+            Expr::For(_, label, name, from, to, body) => {
+                let from = match self.int(from) {
+                    Flow::Ok(n) => n,
+                    Flow::Err(e) => return Flow::Err(e),
+                    Flow::Break(pos, label) => return Flow::Break(pos, label),
+                    Flow::Continue(pos, label) => return Flow::Continue(pos, label),
+                    Flow::Return(value) => return Flow::Return(value),
+                };
+                let to = match self.int(to) {
+                    Flow::Ok(n) => n,
+                    Flow::Err(e) => return Flow::Err(e),
+                    Flow::Break(pos, label) => return Flow::Break(pos, label),
+                    Flow::Continue(pos, label) => return Flow::Continue(pos, label),
+                    Flow::Return(value) => return Flow::Return(value),
+                };
+                // The variable gets its own scope around the body's, a new
+                // one each time so closures made in the body keep their i
+                let outer = Rc::clone(&self.env);
+                let mut flow = Flow::Ok(Value::Void);
+                for i in from..to {
+                    self.env = Env::child(&outer);
+                    self.env.define(name, Value::Int(i));
+                    if let Some(end) = self.iteration(label, body) {
+                        flow = end;
+                        break;
+                    }
+                }
+                self.env = outer;
+                flow
+            },
+            Expr::Break(pos, label) => Flow::Break(*pos, label.as_ref().map(|label| label.name.clone())),
+            Expr::Continue(pos, label) => Flow::Continue(*pos, label.as_ref().map(|label| label.name.clone())),
+            Expr::Var(pos, name) => match self.env.get(name).or_else(|| self.natives.get(name).cloned()) {
+                Some(value) => Flow::Ok(value),
+                None => Flow::Err(Diagnostic::error(codes::RUNTIME_UNDEFINED_VARIABLE,
+                    format!("'{}' is not defined", name), *pos)),
+            },
+            Expr::Int(_, n) => Flow::Ok(Value::Int(*n)),
+            Expr::Float(_, n) => Flow::Ok(Value::Float(*n)),
+            Expr::Str(_, s) => Flow::Ok(Value::Str(Rc::from(s.as_str()))),
+            Expr::Char(_, c) => Flow::Ok(Value::Char(*c)),
+            Expr::Bool(_, b) => Flow::Ok(Value::Bool(*b)),
+            Expr::List(_, items) => {
+                let mut values = Vec::with_capacity(items.len());
+                for item in items {
+                    match self.eval(item) {
+                        Flow::Ok(v) => values.push(v),
+                        flow => return flow,
+                    }
+                }
+                Flow::Ok(Value::List(Rc::new(RefCell::new(values))))
+            },
+            Expr::Tuple(_, items) => {
+                let mut values = Vec::with_capacity(items.len());
+                for item in items {
+                    match self.eval(item) {
+                        Flow::Ok(v) => values.push(v),
+                        flow => return flow,
+                    }
+                }
+                Flow::Ok(Value::Tuple(Rc::from(values)))
+            },
+            Expr::Map(_, entries) => {
+                let mut values = Vec::with_capacity(entries.len());
+                for (key, value) in entries {
+                    let key = match self.eval(key) {
+                        Flow::Ok(v) => v,
+                        flow => return flow,
+                    };
+                    let value = match self.eval(value) {
+                        Flow::Ok(v) => v,
+                        flow => return flow,
+                    };
+                    // A key written twice keeps the last value
+                    set_entry(&mut values, key, value);
+                }
+                Flow::Ok(Value::Map(Rc::new(RefCell::new(values))))
+            },
+            Expr::Null(_) => Flow::Ok(Value::Null),
+            Expr::Neg(pos, operand) => {
+                match self.eval(operand) {
+                    Flow::Ok(Value::Int(n)) => match n.checked_neg() {
+                        Some(n) => Flow::Ok(Value::Int(n)),
+                        None => Flow::Err(Diagnostic::error(codes::OVERFLOW,
+                            format!("Integer overflow when trying to negate {}.", n), *pos)
+                            .with_note("Integers are 64 bit. Use a float for bigger numbers.")),
+                    },
+                    Flow::Ok(Value::Float(n)) => Flow::Ok(Value::Float(-n)),
+                    Flow::Ok(v) => Flow::Err(Diagnostic::error(codes::RUNTIME_TYPE_MISMATCH,
+                        format!("You cannot negate '{}'", v.get_type()), *pos)),
+                    err => err,
+                }
+            },
+            Expr::Add(pos, left, right) => self.binary(pos, &ADD, left, right),
+            Expr::Sub(pos, left, right) => self.binary(pos, &SUB, left, right),
+            Expr::Mul(pos, left, right) => self.binary(pos, &MUL, left, right),
+            Expr::Div(pos, left, right) => self.binary(pos, &DIV, left, right),
+            Expr::Not(_, operand) => match self.condition(operand) {
+                Flow::Ok(Value::Bool(b)) => Flow::Ok(Value::Bool(!b)),
+                flow => flow,
+            },
+            Expr::Eq(pos, left, right) => self.comparison(pos, EQ, left, right),
+            Expr::Ne(pos, left, right) => self.comparison(pos, NE, left, right),
+            Expr::Lt(pos, left, right) => self.comparison(pos, LT, left, right),
+            Expr::Le(pos, left, right) => self.comparison(pos, LE, left, right),
+            Expr::Gt(pos, left, right) => self.comparison(pos, GT, left, right),
+            Expr::Ge(pos, left, right) => self.comparison(pos, GE, left, right),
+            // && stops at the first false, || at the first true
+            Expr::And(_, left, right) => self.logical(false, left, right),
+            Expr::Or(_, left, right) => self.logical(true, left, right),
+            Expr::Coalesce(_, left, right) => match self.eval(left) {
+                Flow::Ok(Value::Null) => self.eval(right),
+                flow => flow,
+            },
+        }
+    }
+}
+
+/// Makes the values every program can use without declaring
+/// them, which are the prelude's natives and the libraries.
+fn natives() -> HashMap<String, Value> {
+    let mut natives: HashMap<String, Value> = stdlib::prelude().iter()
+        .map(|native| (native.name.to_string(), Value::Native(native)))
+        .collect();
+    for library in stdlib::libraries() {
+        let exports = library.natives.iter()
+            .map(|native| (native.name.to_string(), Value::Native(native)))
+            .collect();
+        let module = Module { name: library.name.to_string(), exports };
+        natives.insert(library.name.to_string(), Value::Module(Rc::new(module)));
+    }
+    natives
+}
+
+/// Returns true if the value matches the pattern,
+/// defining the names it binds in the scope.
+fn matches(pattern: &Pattern, value: &Value, env: &Rc<Env>) -> bool {
+    match (pattern, value) {
+        (Pattern::Wildcard(_), _) => true,
+        (Pattern::Binding(_, name), v) => {
+            env.define(name, v.clone());
+            true
+        },
+        (Pattern::Int(_, a), Value::Int(b)) => a == b,
+        (Pattern::Str(_, a), Value::Str(b)) => a.as_str() == &**b,
+        (Pattern::Char(_, a), Value::Char(b)) => a == b,
+        (Pattern::Bool(_, a), Value::Bool(b)) => a == b,
+        (Pattern::Variant(_, _, name, fields), Value::Variant(variant)) =>
+            variant.decl.variants[variant.index].name == name.name
+                && fields.iter().zip(&variant.fields).all(|(pattern, value)| matches(pattern, value, env)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Interpreter;
+    use crate::interpreter::{ cycles, value::Value };
+    use crate::utils::testing::{ parse, run, try_run };
+    use std::rc::Rc;
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(run("1 + 2 * 3"), "7");
+        assert_eq!(run("7 / 2"), "3");
+        assert_eq!(run("-7 / 2"), "-3");
+        assert_eq!(run("1.5 + 1"), "2.5");
+        assert_eq!(run("let a = 2 a * 3.5"), "7.0");
+    }
+
+    #[test]
+    fn short_circuit() {
+        assert_eq!(run("false && 1 / 0 == 1"), "false");
+        assert_eq!(run("true || 1 / 0 == 1"), "true");
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(run("if 1 > 2 { \"yes\" } else if 2 > 1 { \"maybe\" } else { \"no\" }"), "\"maybe\"");
+        assert_eq!(run("let n = 0 while n < 10 { break } n"), "0");
+        assert_eq!(run("outer: for i in 0..3 { for j in 0..3 { if j == 1 { continue outer } } } 1"), "1");
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(run("let n = [0] for i in 0..5 { lists.push(n, i) } lists.len(n)"), "6");
+        assert_eq!(run("let xs = [] outer: for i in 0..3 { for j in 0..3 { if j == 1 { continue outer } lists.push(xs, i) } } lists.len(xs)"), "3");
+    }
+
+    #[test]
+    fn closures() {
+        assert_eq!(run("fn make(n: int) { fn(x: int) -> int { x + n } } make(2)(3)"), "5");
+        assert_eq!(run("fn fact(n: int) -> int { if n < 2 { 1 } else { n * fact(n - 1) } } fact(10)"), "3628800");
+        assert_eq!(run("fn f(n: int) -> int { if n > 0 { return n } 0 } f(3) + f(-1)"), "3");
+        assert_eq!(run("fn neg(x) { -x } neg(2) + neg(-3)"), "1");
+        assert_eq!(run("let id = fn(x) { x } id(1) id(\"a\")"), "\"a\"");
+    }
+
+    #[test]
+    fn assignment() {
+        assert_eq!(run("let x = 1 x += 2 x *= 4 x"), "12");
+        assert_eq!(run("let x = 1 if true { x = 5 } x"), "5");
+        assert_eq!(run("let total = 0 for i in 0..5 { total += i } total"), "10");
+        assert_eq!(run("fn f(n: int) -> int { n -= 1 n } f(3)"), "2");
+        // Closures see the variable itself, not a copy of it
+        assert_eq!(run("let n = 1 let get = fn() { n } n = 2 get()"), "2");
+        assert_eq!(run("let n = 0 let inc = fn() { n += 1 } inc() inc() n"), "2");
+        assert_eq!(run("fn counter() { let n = 0 fn() -> int { n += 1 n } } let c = counter() c() c() c()"), "3");
+        assert_eq!(run("fn counter() { let n = 0 fn() -> int { n += 1 n } } let a = counter() a() let b = counter() a() + b()"), "3");
+    }
+
+    #[test]
+    fn scopes_are_freed() {
+        // Functions declared in a scope, and closures kept in the
+        // scope around the one they were made in, used to keep it alive forever
+        let mut interpreter = Interpreter::new();
+        let src = "fn f(n: int) -> int { if n > 0 { f(n - 1) } else { 0 } } struct P { x: int } let g = fn() { f(1) } g()\n\
+            fn make() { fn inner() -> int { 1 } inner } let h = make()";
+        assert!(matches!(interpreter.run(&parse(src)), crate::interpreter::result::RunResult::Ok(_)));
+        let global = Rc::downgrade(&interpreter.env);
+        // The scope of the call to make, which h was made in
+        let call = match interpreter.env.get("h") {
+            Some(Value::Fn(closure)) => Rc::downgrade(&closure.env),
+            _ => panic!("h should be a function"),
+        };
+        drop(interpreter);
+        cycles::collect();
+        assert!(global.upgrade().is_none());
+        assert!(call.upgrade().is_none());
+    }
+
+    #[test]
+    fn collecting_keeps_what_is_used() {
+        // Every call to leak leaves a cycle behind, enough of them to collect a few times
+        let src = "fn counter() { let n = 0 fn() -> int { n += 1 n } }\n\
+            fn leak() -> int { fn make() { fn() -> int { 1 } } let c = make() c() }\n\
+            let cs = [counter(), counter()] let total = 0\n\
+            for i in 0..5000 { total += leak() + lists.get(cs, i - i / 2 * 2)() }\n\
+            total + lists.get(cs, 0)() + lists.get(cs, 1)()";
+        assert_eq!(run(src), (5000 + 2500 * 2501 + 2501 * 2).to_string());
+    }
+
+    #[test]
+    fn structs_and_enums() {
+        assert_eq!(run("struct P { x: float, y: float fn len2(self) -> float { self.x * self.x + self.y * self.y } } P(1.0, 2.0).len2()"), "5.0");
+        let shapes = "enum Shape { Circle(float), Rect(float, float), Empty }\n\
+            fn area(s: Shape) -> float { match s { Shape::Circle(r) => 3.0 * r * r, Shape::Rect(w, h) if w == h => w * w, Shape::Rect(w, h) => w * h, Shape::Empty => 0.0 } }\n";
+        assert_eq!(run(&format!("{}area(Shape::Rect(2.0, 3.0))", shapes)), "6.0");
+        assert_eq!(run(&format!("{}area(Shape::Rect(2.0, 2.0)) + area(Shape::Empty)", shapes)), "4.0");
+        // Enums declared further down can already be used
+        assert_eq!(run("fn make() -> Shape { Shape::Empty } let s = make() enum Shape { Empty } s"), "Shape::Empty");
+    }
+
+    #[test]
+    fn tuples_maps_and_optionals() {
+        assert_eq!(run("let p = (1, \"a\") p.1"), "\"a\"");
+        assert_eq!(run("(1,)"), "(1,)");
+        assert_eq!(run("let m = [\"a\": 1, \"a\": 2] maps.set(m, \"b\", 3) m"), "[\"a\": 2, \"b\": 3]");
+        assert_eq!(run("let m = [(1, 2): 'x'] maps.get(m, (1, 2)) ?? 'y'"), "'x'");
+        assert_eq!(run("let m = [1: 'x'] maps.remove(m, 1) ?? 'y'"), "'x'");
+        assert_eq!(run("let m = [1: 'x'] maps.remove(m, 1) maps.remove(m, 1) ?? 'y'"), "'y'");
+        assert_eq!(run("let m = [1: 'x', 2: 'y'] maps.len(m) + lists.len(maps.keys(m))"), "4");
+        assert_eq!(run("let x: int? = null x == null"), "true");
+        assert_eq!(run("let x: int? = 3 x != null && x == 3"), "true");
+        assert_eq!(run("fn f(x: int?) -> int { x ?? 0 } f(4) + f(null)"), "4");
+    }
+
+    #[test]
+    fn stdlib() {
+        assert_eq!(run("strings.join(strings.split(\"a,b\", \",\"), \"-\")"), "\"a-b\"");
+        assert_eq!(run("math.pow(math.sqrt(16.0), 2.0)"), "16.0");
+        assert_eq!(try_run("lists.get([1], 5)"), Err("R009"));
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(try_run("9223372036854775807 + 1"), Err("R002"));
+        assert_eq!(try_run("9223372036854775807 * 2"), Err("R002"));
+        assert_eq!(try_run("-9223372036854775807 - 2"), Err("R002"));
+        assert_eq!(try_run("-(-9223372036854775807 - 1)"), Err("R002"));
+        assert_eq!(try_run("(-9223372036854775807 - 1) / -1"), Err("R002"));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(try_run("1 / 0"), Err("R001"));
+        assert_eq!(run("1.0 / 0.0"), "inf");
+        assert_eq!(try_run("let zero = 1 - 1 5 / zero"), Err("R001"));
+    }
+
+    #[test]
+    fn stack_overflow() {
+        // Deep enough recursion needs more than the test thread's stack before hitting the limit
+        let result = std::thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(|| try_run("fn f(n: int) -> int { f(n + 1) } f(0)"))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(result, Err("R007"));
+    }
+}
@@ -0,0 +1,8 @@
+
+pub mod value;
+pub mod result;
+mod env;
+mod cycles;
+#[allow(clippy::module_inception)]
+mod interpreter;
+pub use interpreter::{ Interpreter };
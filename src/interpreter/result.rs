@@ -0,0 +1,29 @@
+
+use crate::utils::diagnostic::Diagnostic;
+use crate::lexer::token::Position;
+use super::value::Value;
+
+/// Result that either returns a runtime error or a value.
+pub enum RunResult<T> {
+    /// Runtime result.
+    Ok(T),
+    /// Runtime error.
+    Err(Diagnostic),
+}
+
+/// How evaluating an expression ended inside the interpreter.
+/// Break and Continue skip the rest of every expression
+/// around them until they reach the loop they belong to,
+/// and Return does the same until it reaches the call.
+pub enum Flow<T> {
+    /// Evaluated to a value.
+    Ok(T),
+    /// Runtime error.
+    Err(Diagnostic),
+    /// `break`, with its position and the label it names.
+    Break(Position, Option<String>),
+    /// `continue`, with its position and the label it names.
+    Continue(Position, Option<String>),
+    /// `return`, with the value being returned.
+    Return(Value),
+}
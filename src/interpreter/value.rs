@@ -0,0 +1,224 @@
+use std::fmt::{ self, Display, Formatter };
+use crate::parser::expr::{ Annotation, EnumDecl, Function, StructDecl };
+use crate::types::JType;
+use crate::stdlib::Native;
+use super::env::Env;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A function along with the scope it was made in.
+pub struct Closure {
+    // None for lambdas
+    pub name: Option<String>,
+    pub function: Rc<Function>,
+    pub env: Rc<Env>,
+}
+
+/// A struct, which is what its name evaluates to.
+/// Calling it makes an instance.
+pub struct Class {
+    pub decl: Rc<StructDecl>,
+    // Where it was declared, for its methods to run in
+    pub env: Rc<Env>,
+}
+
+/// A value of an enum.
+pub struct Variant {
+    pub decl: Rc<EnumDecl>,
+    // Which of the enum's variants it is
+    pub index: usize,
+    pub fields: Vec<Value>,
+}
+
+/// An instance of a struct. Nothing can change
+/// a field, so instances can be shared.
+pub struct Instance {
+    pub class: Rc<Class>,
+    // In the order they're declared in
+    pub fields: Vec<Value>,
+}
+
+/// A module that's been run, which is what
+/// `import name` gives the name.
+pub struct Module {
+    pub name: String,
+    pub exports: HashMap<String, Value>,
+}
+
+/// Values produced by the interpreter at runtime.
+/// Every variant lines up with a JType.
+#[derive(Clone)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    // Strings are immutable, so copies can share them
+    Str(Rc<str>),
+    Char(char),
+    Bool(bool),
+    // Lists can be changed, and everything holding one sees the change
+    List(Rc<RefCell<Vec<Value>>>),
+    // Tuples can't be changed, so copies can share them
+    Tuple(Rc<[Value]>),
+    // Entries in the order they were added. Maps can be changed like lists.
+    Map(Rc<RefCell<Vec<(Value, Value)>>>),
+    // What an optional holds when it doesn't hold anything.
+    // Otherwise it's just the value it holds.
+    Null,
+    Fn(Rc<Closure>),
+    // Natives are built once and live for the whole program
+    Native(&'static Native),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+    // A variant that holds something, which makes
+    // a Variant when it's called with what it holds
+    Constructor(Rc<EnumDecl>, usize),
+    Variant(Rc<Variant>),
+    Module(Rc<Module>),
+    Void,
+}
+
+impl Value {
+    /// Returns the type of the value.
+    pub fn get_type(&self) -> JType {
+        match self {
+            Self::Int(_) => JType::Int,
+            Self::Float(_) => JType::Float,
+            Self::Str(_) => JType::String,
+            Self::Char(_) => JType::Char,
+            Self::Bool(_) => JType::Bool,
+            // An empty list could hold anything
+            Self::List(items) => JType::List(Box::new(items.borrow().first().map_or(JType::Var(0), Value::get_type))),
+            Self::Tuple(items) => JType::Tuple(items.iter().map(Value::get_type).collect()),
+            Self::Map(entries) => match entries.borrow().first() {
+                Some((key, value)) => JType::Map(Box::new(key.get_type()), Box::new(value.get_type())),
+                None => JType::Map(Box::new(JType::Var(0)), Box::new(JType::Var(0))),
+            },
+            Self::Null => JType::Optional(Box::new(JType::Var(0))),
+            // Inferred types aren't kept around after checking,
+            // so those are left unknown
+            Self::Fn(closure) => {
+                let function = &closure.function;
+                let known = |annotation: Option<&Annotation>|
+                    annotation.map_or(JType::Var(0), |annotation| annotation.t.clone());
+                JType::Fn(function.params.iter().map(|param| known(param.annotation.as_ref())).collect(),
+                    Box::new(known(function.ret.as_ref())))
+            },
+            Self::Native(native) => native.t.clone(),
+            Self::Class(class) => JType::Fn(class.decl.fields.iter().map(|field| field.annotation.t.clone()).collect(),
+                Box::new(JType::User(class.decl.name.clone()))),
+            Self::Instance(instance) => JType::User(instance.class.decl.name.clone()),
+            Self::Constructor(decl, index) => JType::Fn(decl.variants[*index].fields.clone(),
+                Box::new(JType::User(decl.name.clone()))),
+            Self::Variant(variant) => JType::User(variant.decl.name.clone()),
+            Self::Module(module) => JType::Module(module.name.clone()),
+            Self::Void => JType::Void,
+        }
+    }
+}
+
+impl Value {
+    /// Returns true if two values are the same key of a map.
+    /// Values the checker lets be compared with == are equal
+    /// if they hold the same thing, and anything else is only
+    /// the same key as itself.
+    pub fn is_same_key(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => a == b,
+            (Self::Float(a), Self::Float(b)) => a == b,
+            (Self::Str(a), Self::Str(b)) => a == b,
+            (Self::Char(a), Self::Char(b)) => a == b,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::Null, Self::Null) => true,
+            (Self::Tuple(a), Self::Tuple(b)) => a.len() == b.len()
+                && a.iter().zip(b.iter()).all(|(a, b)| a.is_same_key(b)),
+            (Self::Variant(a), Self::Variant(b)) => Rc::ptr_eq(&a.decl, &b.decl) && a.index == b.index
+                && a.fields.iter().zip(&b.fields).all(|(a, b)| a.is_same_key(b)),
+            (Self::List(a), Self::List(b)) => Rc::ptr_eq(a, b),
+            (Self::Map(a), Self::Map(b)) => Rc::ptr_eq(a, b),
+            (Self::Instance(a), Self::Instance(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// Sets a key in the entries of a map, replacing
+/// whatever it was set to before.
+pub fn set_entry(entries: &mut Vec<(Value, Value)>, key: Value, value: Value) {
+    match entries.iter_mut().find(|(k, _)| k.is_same_key(&key)) {
+        Some(entry) => entry.1 = value,
+        None => entries.push((key, value)),
+    }
+}
+
+impl Display for Value {
+    // Writes the value the way it would be written in source
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(n) => write!(f, "{}", n),
+            Self::Float(n) => write!(f, "{:?}", n),
+            Self::Str(s) => write!(f, "{:?}", s),
+            Self::Char(c) => write!(f, "{:?}", c),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::List(items) => {
+                write!(f, "[")?;
+                write_values(f, &items.borrow())?;
+                write!(f, "]")
+            },
+            // (1,) so it isn't mistaken for just 1 in parentheses
+            Self::Tuple(items) if items.len() == 1 => write!(f, "({},)", items[0]),
+            Self::Tuple(items) => write_fields(f, items),
+            Self::Map(entries) if entries.borrow().is_empty() => write!(f, "[:]"),
+            Self::Map(entries) => {
+                write!(f, "[")?;
+                for (i, (key, value)) in entries.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "]")
+            },
+            Self::Null => write!(f, "null"),
+            Self::Native(native) => write!(f, "<native fn {}>", native.name),
+            Self::Fn(closure) => match &closure.name {
+                Some(name) => write!(f, "<fn {}>", name),
+                None => write!(f, "<fn>"),
+            },
+            Self::Class(class) => write!(f, "<struct {}>", class.decl.name),
+            // The same as the constructor call that makes it
+            Self::Instance(instance) => {
+                write!(f, "{}", instance.class.decl.name)?;
+                write_fields(f, &instance.fields)
+            },
+            Self::Constructor(decl, index) => write!(f, "<variant {}::{}>", decl.name, decl.variants[*index].name),
+            Self::Variant(variant) => {
+                write!(f, "{}::{}", variant.decl.name, variant.decl.variants[variant.index].name)?;
+                if variant.fields.is_empty() {
+                    return Ok(());
+                }
+                write_fields(f, &variant.fields)
+            },
+            Self::Module(module) => write!(f, "<module {}>", module.name),
+            Self::Void => write!(f, "void"),
+        }
+    }
+}
+
+/// Writes values separated by commas.
+fn write_values(f: &mut Formatter<'_>, values: &[Value]) -> fmt::Result {
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", value)?;
+    }
+    Ok(())
+}
+
+/// Writes values in parentheses, separated by commas.
+fn write_fields(f: &mut Formatter<'_>, fields: &[Value]) -> fmt::Result {
+    write!(f, "(")?;
+    write_values(f, fields)?;
+    write!(f, ")")
+}
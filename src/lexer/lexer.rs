@@ -1,214 +1,796 @@
 
-use super::token::{ Token, Position };
-use super::result::LexResult;
+use crate::utils::{ codes, diagnostic::{ Diagnostic, Severity } };
+use super::token::{ MakeToken, Token, Position };
+use super::result::{ LexResult, Lexed };
+use super::source::SourceFile;
 use crate::utils::general::is_some_and;
+use std::borrow::Cow;
+
+/*
+* The lexer walks the source by byte offset and never copies it.
+* Identifiers, keywords, doc comments and strings without escapes
+* are slices of the source, and positions are byte offsets that
+* can index it directly.
+*
+* The ignored throughput test at the bottom prints how many MB/s
+* release builds lex for identifiers, strings, comments and a mix
+* of declarations, so changes here can be measured.
+*/
 
 struct Lexer<'a> {
-    filename: &'a str,
+    file: &'a SourceFile,
+    // Same as file.src(), kept around since it's used everywhere
     src: &'a str,
-    chars: Vec<char>,
+    // Byte offset of the current character
     index: usize,
     current: Option<char>,
+    // Every problem found so far
+    diagnostics: Vec<Diagnostic>,
+    // Lexing gives up once this many errors are found
+    max_errors: usize,
 }
 
-const KEYWORDS: &'static [&'static str] = &[
-    "let"
+/// How many errors lex reports before giving up.
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+
+/// Suffixes for int literals and the values they allow.
+/// Every int is 64 bit at runtime, the suffix just checks it fits.
+const INT_SUFFIXES: &[(&str, i128, i128)] = &[
+    ("i8", i8::MIN as i128, i8::MAX as i128),
+    ("i16", i16::MIN as i128, i16::MAX as i128),
+    ("i32", i32::MIN as i128, i32::MAX as i128),
+    ("i64", i64::MIN as i128, i64::MAX as i128),
+    ("u8", 0, u8::MAX as i128),
+    ("u16", 0, u16::MAX as i128),
+    ("u32", 0, u32::MAX as i128),
+];
+
+/// Suffixes that make a number a float.
+const FLOAT_SUFFIXES: &[&str] = &["f", "f32", "f64"];
+
+const KEYWORDS: &[&str] = &[
+    "let", "true", "false",
+    "if", "else", "while", "for", "in", "break", "continue",
+    "fn", "return", "struct", "class", "enum", "match",
+    "import", "from", "export", "null"
 ];
 
 impl<'a> Lexer<'a> {
-    fn new(filename: &'a str, src: &'a str) -> Self {
-        let chars: Vec<char> = src.chars().collect();
-        let current = Some(chars[0]);
+    /// Every operator and delimiter with the token it makes.
+    /// Longer operators come first so the lexer always takes
+    /// the longest match, eg `<<=` instead of `<` and `<=`.
+    const OPERATORS: &'a [(&'static str, MakeToken<'a>)] = &[
+        ("**=", Token::StarStarEq), ("<<=", Token::ShlEq), (">>=", Token::ShrEq),
+        ("**", Token::StarStar), ("==", Token::EqEq), ("!=", Token::BangEq), ("<=", Token::LessEq),
+        (">=", Token::GreaterEq), ("&&", Token::AndAnd), ("||", Token::OrOr), ("<<", Token::Shl),
+        (">>", Token::Shr), ("+=", Token::PlusEq), ("-=", Token::MinusEq), ("*=", Token::StarEq),
+        ("/=", Token::SlashEq), ("%=", Token::PercentEq), ("&=", Token::AmpEq), ("|=", Token::PipeEq),
+        ("^=", Token::CaretEq), ("->", Token::Arrow), ("=>", Token::FatArrow), ("..", Token::DotDot),
+        ("::", Token::ColonColon), ("??", Token::QuestionQuestion),
+        ("+", Token::Plus), ("-", Token::Minus), ("*", Token::Star), ("/", Token::Slash),
+        ("%", Token::Percent), ("<", Token::Less), (">", Token::Greater), ("!", Token::Bang),
+        ("&", Token::Amp), ("|", Token::Pipe), ("^", Token::Caret), ("~", Token::Tilde),
+        ("=", Token::Equals), (".", Token::Dot), (":", Token::Colon), ("?", Token::Question),
+        (",", Token::Comma), ("(", Token::LParen), (")", Token::RParen), ("[", Token::LBracket),
+        ("]", Token::RBracket), ("{", Token::LBrace), ("}", Token::RBrace), (";", Token::Semicolon),
+    ];
+
+    fn new(file: &'a SourceFile, max_errors: usize) -> Self {
         Self {
-            filename,
-            src,
-            chars,
+            file,
+            src: file.src(),
             index: 0,
-            current,
+            // Empty files don't have a first character
+            current: file.src().chars().next(),
+            diagnostics: Vec::new(),
+            max_errors,
+        }
+    }
+
+    /// Returns the character starting at a byte offset.
+    fn char_at(&self, index: usize) -> Option<char> {
+        match self.src.as_bytes().get(index) {
+            // Most code is ASCII, which doesn't need decoding
+            Some(byte) if byte.is_ascii() => Some(*byte as char),
+            Some(_) => self.src[index..].chars().next(),
+            None => None,
         }
     }
 
     /// Steps to the next character in the source code.
     fn advance(&mut self) {
-        self.index += 1;
-        self.current = if self.index < self.chars.len() {
-            Some(self.chars[self.index])
-        } else {
-            None
-        };
+        if let Some(c) = self.current {
+            self.index += c.len_utf8();
+        }
+        self.current = self.char_at(self.index);
+    }
+
+    /// Moves to a byte offset, which has to be at the
+    /// start of a character.
+    fn jump(&mut self, index: usize) {
+        self.index = index;
+        self.current = self.char_at(index);
+    }
+
+    /// Skips characters while they match and returns
+    /// the slice of source that was skipped.
+    fn take_while(&mut self, matches: fn(char) -> bool) -> &'a str {
+        let start = self.index;
+        let rest = &self.src[start..];
+        let len = rest.find(|c| !matches(c)).unwrap_or(rest.len());
+        self.jump(start + len);
+        &self.src[start..start + len]
     }
 
     /// Steps to the next character in the source code
     /// and skips whitespace
     fn skip_whitespace(&mut self) {
-        while is_some_and(&self.current, |x| x.is_whitespace()) {
+        self.take_while(char::is_whitespace);
+    }
+
+    /// Returns true if the current character starts a /// doc comment.
+    /// Four or more slashes is just a normal comment.
+    fn is_doc_comment(&self) -> bool {
+        self.current == Some('/') && self.peek(1) == Some('/')
+            && self.peek(2) == Some('/') && self.peek(3) != Some('/')
+    }
+
+    /// Skips a block comment, starting at the opening /*.
+    /// Block comments nest, so /* /* */ */ is a single comment.
+    fn block_comment(&mut self) -> LexResult<()> {
+        let index = self.index;
+        self.advance();
+        self.advance();
+        let mut depth = 1;
+        while depth > 0 {
+            match (self.current, self.peek(1)) {
+                (None, _) => return LexResult::Err(Diagnostic::error(codes::LEX_END_OF_FILE,
+                    "Expected '*/' to close the comment.", Position::new(self.file.id(), index, index + 2))),
+                (Some('/'), Some('*')) => {
+                    depth += 1;
+                    self.advance();
+                },
+                (Some('*'), Some('/')) => {
+                    depth -= 1;
+                    self.advance();
+                },
+                _ => {},
+            }
             self.advance();
         }
+        LexResult::Ok(())
     }
 
-    /// Lexes an identifier or keyword
-    fn identifier(&mut self, index: usize) -> Token<'a> {
-        let mut ident = String::new();
-        while let Some(c) = self.current {
-            if !c.is_alphanumeric() {
-                break;
+    /// Skips whitespace and comments, except doc comments
+    /// which are tokens of their own.
+    fn skip_trivia(&mut self) -> LexResult<()> {
+        loop {
+            self.skip_whitespace();
+            match (self.current, self.peek(1)) {
+                (Some('/'), Some('/')) if !self.is_doc_comment() => {
+                    self.take_while(|c| c != '\n');
+                },
+                (Some('/'), Some('*')) => if let LexResult::Err(e) = self.block_comment() {
+                    return LexResult::Err(e);
+                },
+                _ => return LexResult::Ok(()),
             }
-            // Append to identifier
-            ident.push(c);
+        }
+    }
+
+    /// Lexes a /// doc comment. The text is kept without the slashes
+    /// or the space after them, so it can be attached to whatever
+    /// it documents.
+    fn doc_comment(&mut self, index: usize) -> Token<'a> {
+        for _ in 0..3 {
             self.advance();
         }
-        if KEYWORDS.contains(&ident.as_str()) {
+        if self.current == Some(' ') {
+            self.advance();
+        }
+        let text = self.take_while(|c| c != '\n');
+        // Files with \r\n line endings leave a \r behind
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        Token::DocComment(self.pos(index), text)
+    }
+
+    /// Lexes an identifier or keyword
+    fn identifier(&mut self, index: usize) -> Token<'a> {
+        let ident = self.take_while(|c| c.is_alphanumeric() || c == '_');
+        if KEYWORDS.contains(&ident) {
             Token::Keyword(self.pos(index), ident)
         } else {
             Token::Identifier(self.pos(index), ident)
         }
     }
 
-    /// Lexes a number token
-    fn number(&mut self, index: usize) -> LexResult<'a, Token<'a>> {
-        let mut num = String::new();
-        let mut dots = 0;
-        let mut is_float = false;
-        let mut is_hex = false;
+    /// Records a problem with a number literal and returns an
+    /// Error token covering the literal, which has already been read.
+    fn bad_number(&mut self, index: usize, message: impl Into<String>, pos: Position) -> Token<'a> {
+        self.diagnostics.push(Diagnostic::error(codes::NUMBER_FORMAT, message, pos));
+        self.error_token(index)
+    }
+
+    /// Skips digits and _ separators.
+    /// Every decimal digit is read, even in binary and octal, so that
+    /// the bad ones can be reported instead of splitting the number.
+    /// Returns the first digit too big for the radix and its position.
+    fn digits(&mut self, radix: u32) -> Option<(char, Position)> {
+        let mut bad = None;
         while let Some(c) = self.current {
-            // Hellish method to get lowercase character.
-            let lowercase_c = c.to_lowercase().next().unwrap();
-            // If the number ends with 'f', then it's a float.
-            if lowercase_c == 'f' && !is_hex {
-                is_float = true;
-                self.advance();
+            if c != '_' && !(c.is_ascii_digit() || radix == 16 && c.is_ascii_hexdigit()) {
                 break;
             }
-            // If there's an x, it might be hex!
-            if lowercase_c == 'x' && !is_hex {
-                // If the number is not a 0, it's not hex
-                // Format for hex is 0xABCD so the only character preceding it should be a 0
-                if num != "0" {
-                    break;
-                }
-                is_hex = true;
-                self.advance();
-                // Reset number string because 0x causes a formatting error.
-                num = String::new();
-                continue;
+            if c != '_' && !c.is_digit(radix) && bad.is_none() {
+                bad = Some((c, Position::new(self.file.id(), self.index, self.index + 1)));
             }
-            // If it's not a number or a dot
-            // and if it's hexadecimal but not in the character range
-            // then break.
-            if (!('a'..='f').contains(&lowercase_c) || !is_hex) && !c.is_numeric() && c != '.' {
-                break;
+            self.advance();
+        }
+        bad
+    }
+
+    /// Lexes a number, eg 42, 0xFF, 0o17, 0b1010, 1_000, 1.5e-3 or 255u8.
+    /// Problems are recorded as diagnostics and an Error token is returned,
+    /// so a bad number never takes the rest of the line with it.
+    fn number(&mut self, index: usize) -> Token<'a> {
+        let (radix, base) = match (self.current, self.peek(1)) {
+            (Some('0'), Some('x' | 'X')) => (16, "Hex"),
+            (Some('0'), Some('o' | 'O')) => (8, "Octal"),
+            (Some('0'), Some('b' | 'B')) => (2, "Binary"),
+            _ => (10, "Decimal"),
+        };
+        if radix != 10 {
+            self.jump(index + 2);
+        }
+
+        let start = self.index;
+        let mut bad_digit = self.digits(radix);
+        if !self.src[start..self.index].bytes().any(|b| b != b'_') {
+            return self.bad_number(index, format!("Expected digits after the {} prefix.", base.to_lowercase()),
+                self.pos(index));
+        }
+
+        // Fraction, but only when a digit follows the dot,
+        // since 1..2 is a range and x.0 is a field.
+        let mut is_float = false;
+        if self.current == Some('.') && is_some_and(&self.peek(1), |c| c.is_ascii_digit()) {
+            let dot = self.index;
+            is_float = true;
+            self.advance();
+            bad_digit = bad_digit.or(self.digits(10));
+            if radix != 10 {
+                return self.bad_number(index, format!("{} numbers can't have a fraction.", base),
+                    Position::new(self.file.id(), dot, dot + 1));
             }
-            // Search for decimal points
-            if c == '.' {
-                // If the number is also hexadecimal, uh oh!
-                if is_hex {
-                    return LexResult::Err("NumberFormat".to_string(), 
-                        "Hex number cannot be a float.".to_string(), self.pos(self.index));
-                }
-                dots += 1;
-                // If there's a decimal point, it must be a floating point number!
+        }
+
+        // Exponent, eg e3, E-3 or e+3
+        if radix == 10 && matches!(self.current, Some('e' | 'E')) {
+            let signed = matches!(self.peek(1), Some('+' | '-'));
+            let digit = self.peek(if signed { 2 } else { 1 });
+            if is_some_and(&digit, |c| c.is_ascii_digit()) {
                 is_float = true;
-                // If there is more than 1 dot (eg 12.34.) then break
-                if dots > 1 {
-                    break;
-                }
+                self.jump(self.index + if signed { 2 } else { 1 });
+                self.digits(10);
             }
-            // Append to number
-            num.push(c);
-            self.advance();
         }
 
-        // If the last character is a dot, then that's a syntax error.
-        if is_some_and(&num.chars().last(), |x| *x == '.') {
-            return LexResult::Err("NumberFormat".to_string(), 
-                "Expected number after decimal point.".to_string(), self.pos(self.index));
+        if let Some((c, pos)) = bad_digit {
+            return self.bad_number(index, format!("'{}' is not a valid {} digit.", c, base.to_lowercase()), pos);
         }
 
-        // If there's no number then uhhh oh no!!
-        if num.len() == 0 {
-            return LexResult::Err("NumberFormat".to_string(), 
-                "Expected number.".to_string(), self.pos(self.index));
+        // Separators are only for people
+        let num = &self.src[start..self.index];
+        let num = if num.contains('_') { Cow::Owned(num.replace('_', "")) } else { Cow::Borrowed(num) };
+
+        // Anything directly after the number is a suffix
+        let suffix_start = self.index;
+        let suffix = self.take_while(|c| c.is_alphanumeric() || c == '_');
+        let suffix_pos = self.pos(suffix_start);
+
+        if is_float || FLOAT_SUFFIXES.contains(&suffix) {
+            // Fractions and exponents are already decimal only, but a
+            // suffix would get binary or octal digits read as decimal
+            if radix != 10 {
+                return self.bad_number(index, format!("{} numbers can't be floats.", base), suffix_pos);
+            }
+            return self.float(index, &num, suffix, suffix_pos);
+        }
+        let (min, max) = match INT_SUFFIXES.iter().find(|(s, _, _)| *s == suffix) {
+            Some((_, min, max)) => (*min, *max),
+            None if suffix.is_empty() => (i64::MIN as i128, i64::MAX as i128),
+            None => return self.bad_number(index, format!("Unknown number suffix '{}'.", suffix), suffix_pos),
+        };
+        // Anything too big for an i128 is too big for every suffix anyway
+        match i128::from_str_radix(&num, radix) {
+            Ok(n) if n >= min && n <= max => Token::Int(self.pos(index), n as i64),
+            _ => {
+                let what = if suffix.is_empty() { "an int".to_string() } else { format!("'{}'", suffix) };
+                let pos = self.pos(index);
+                self.diagnostics.push(Diagnostic::error(codes::NUMBER_FORMAT,
+                    format!("Number is too large for {}.", what), pos)
+                    .with_note(format!("It has to be between {} and {}.", min, max)));
+                self.error_token(index)
+            },
+        }
+    }
+
+    /// Finishes lexing a float once its digits and suffix are known.
+    fn float(&mut self, index: usize, num: &str, suffix: &str, suffix_pos: Position) -> Token<'a> {
+        if !suffix.is_empty() && !FLOAT_SUFFIXES.contains(&suffix) {
+            return self.bad_number(index, format!("Floats can't have the suffix '{}'.", suffix), suffix_pos);
+        }
+        // Only decimal numbers get here, and their digits always parse
+        let n: f64 = num.parse().unwrap();
+        // f32 floats are still stored as f64, just rounded
+        let n = if suffix == "f32" { n as f32 as f64 } else { n };
+        if n.is_infinite() {
+            let what = if suffix == "f32" { "an f32" } else { "a float" };
+            return self.bad_number(index, format!("Number is too large for {}.", what), self.pos(index));
+        }
+        Token::Float(self.pos(index), n)
+    }
+
+    /// Returns the character `offset` characters after the current one.
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.src[self.index..].chars().nth(offset)
+    }
+
+    /// Lexes an escape sequence, starting at the backslash.
+    /// Bad escapes are recorded as errors pointing at just the
+    /// escape, and None is returned.
+    fn escape(&mut self) -> Option<char> {
+        let start = self.index;
+        self.advance();
+        // The end of the file is reported by whatever literal
+        // the escape is in, since it's left unclosed too.
+        let c = self.current?;
+        self.advance();
+        match c {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '\\' => Some('\\'),
+            '"' => Some('"'),
+            '\'' => Some('\''),
+            'u' => self.unicode_escape(start),
+            _ => {
+                self.diagnostics.push(Diagnostic::error(codes::INVALID_ESCAPE,
+                    format!("Unknown escape sequence '\\{}'.", c), self.pos(start))
+                    .with_help(r#"Valid escapes are \n \t \r \0 \\ \" \' and \u{...}"#));
+                None
+            },
+        }
+    }
+
+    /// Lexes the rest of a \u{1F355} escape, after the u.
+    fn unicode_escape(&mut self, start: usize) -> Option<char> {
+        if self.current != Some('{') {
+            self.diagnostics.push(Diagnostic::error(codes::INVALID_ESCAPE,
+                "Expected '{' after '\\u'.", self.pos(start))
+                .with_help(r"Unicode escapes look like \u{1F355}"));
+            return None;
+        }
+        self.advance();
+        let mut digits = String::new();
+        while let Some(c) = self.current {
+            if !c.is_ascii_hexdigit() {
+                break;
+            }
+            digits.push(c);
+            self.advance();
+        }
+        // Leave anything else alone, it might be the closing quote
+        if self.current != Some('}') {
+            self.diagnostics.push(Diagnostic::error(codes::INVALID_ESCAPE,
+                "Expected '}' to close the unicode escape.", self.pos(start))
+                .with_help(r"Unicode escapes look like \u{1F355}"));
+            return None;
+        }
+        self.advance();
+        if digits.is_empty() || digits.len() > 6 {
+            self.diagnostics.push(Diagnostic::error(codes::INVALID_ESCAPE,
+                "Unicode escapes need 1 to 6 hex digits.", self.pos(start)));
+            return None;
         }
+        // 6 hex digits always fit in a u32
+        let code = u32::from_str_radix(&digits, 16).unwrap();
+        match char::from_u32(code) {
+            Some(c) => Some(c),
+            None => {
+                self.diagnostics.push(Diagnostic::error(codes::INVALID_ESCAPE,
+                    format!("'{:X}' is not a valid unicode character.", code), self.pos(start)));
+                None
+            },
+        }
+    }
 
-        // Parse number string as f64 if it is a float
-        // Force unwrap because if it crashes, the lexer is broken
-        LexResult::Ok(if is_float {
-            let n: f64 = num.parse().unwrap();
-            Token::Float(self.pos(index), n)
+    /// Lexes a string literal.
+    /// Bad escapes don't stop the string. They're recorded and the
+    /// whole string becomes an Error token, so every escape gets checked.
+    fn string(&mut self, index: usize) -> LexResult<Token<'a>> {
+        self.advance();
+        let start = self.index;
+        // Only strings with escapes need their own copy,
+        // the rest can just borrow the source.
+        let mut value: Option<String> = None;
+        let mut valid = true;
+        loop {
+            match self.current {
+                None => return LexResult::Err(Diagnostic::error(codes::LEX_END_OF_FILE,
+                    "Expected '\"' to close the string.", Position::new(self.file.id(), index, index + 1))),
+                Some('"') => break,
+                Some('\\') => {
+                    let copy = value.get_or_insert_with(|| self.src[start..self.index].to_string());
+                    match self.escape() {
+                        Some(c) => copy.push(c),
+                        None => valid = false,
+                    }
+                },
+                Some(c) => {
+                    if let Some(copy) = &mut value {
+                        copy.push(c);
+                    }
+                    self.advance();
+                },
+            }
+        }
+        let value = match value {
+            Some(value) => Cow::Owned(value),
+            None => Cow::Borrowed(&self.src[start..self.index]),
+        };
+        self.advance();
+        LexResult::Ok(if valid {
+            Token::Str(self.pos(index), value)
         } else {
-            // Otherwise do same with i64
-            let n: i64 = if is_hex {
-                // If it's hexadecimal, parse in base 16.
-                i64::from_str_radix(&num, 16).unwrap()
-            } else {
-                num.parse().unwrap()
-            };
-            Token::Int(self.pos(index), n)
+            self.error_token(index)
         })
     }
 
-    /// Returns a position object ranging from a given 
-    /// start index to the current character
-    fn pos(&self, index: usize) -> Position<'a> {
-        let len = if index < self.index {
-            0
-        } else {
-            self.index - index
+    /// Returns true if the current character starts a raw string,
+    /// like r"..." or r#"..."#.
+    fn is_raw_string(&self) -> bool {
+        let rest = &self.src[self.index..];
+        let after_r = rest.strip_prefix('r').unwrap_or("");
+        after_r.trim_start_matches('#').starts_with('"')
+    }
+
+    /// Lexes a raw string, which has no escapes and ends at a quote
+    /// followed by as many #s as it started with.
+    fn raw_string(&mut self, index: usize) -> LexResult<Token<'a>> {
+        self.advance();
+        let mut hashes = 0;
+        while self.current == Some('#') {
+            hashes += 1;
+            self.advance();
+        }
+        self.advance();
+        let start = self.index;
+        let closing = format!("\"{}", "#".repeat(hashes));
+        let len = match self.src[start..].find(&closing) {
+            Some(len) => len,
+            None => return LexResult::Err(Diagnostic::error(codes::LEX_END_OF_FILE,
+                format!("Expected '{}' to close the raw string.", closing),
+                Position::new(self.file.id(), index, index + hashes + 2))),
         };
-        Position::new(index, len, self.src, self.filename)
+        self.jump(start + len + closing.len());
+        LexResult::Ok(Token::Str(self.pos(index), Cow::Borrowed(&self.src[start..start + len])))
     }
 
-    /// Returns a LexResult::Ok of the token and advances to
-    /// the next character. Useful for one line token returns.
-    fn tok(&mut self, token: Token<'a>) -> LexResult<'a, Token<'a>> {
+    /// Lexes a char literal, which holds exactly one character.
+    fn char(&mut self, index: usize) -> LexResult<Token<'a>> {
         self.advance();
-        LexResult::Ok(token)
+        let mut chars = Vec::new();
+        let mut valid = true;
+        loop {
+            match self.current {
+                // Chars can't span lines, so don't look any further
+                None | Some('\n') => return LexResult::Err(Diagnostic::error(codes::INVALID_CHAR,
+                    "Expected ''' to close the char.", Position::new(self.file.id(), index, index + 1))),
+                Some('\'') => break,
+                Some('\\') => match self.escape() {
+                    Some(c) => chars.push(c),
+                    None => valid = false,
+                },
+                Some(c) => {
+                    chars.push(c);
+                    self.advance();
+                },
+            }
+        }
+        self.advance();
+        if !valid {
+            return LexResult::Ok(self.error_token(index));
+        }
+        match chars.as_slice() {
+            [c] => LexResult::Ok(Token::Char(self.pos(index), *c)),
+            [] => LexResult::Err(Diagnostic::error(codes::INVALID_CHAR,
+                "Chars can't be empty.", self.pos(index))),
+            _ => LexResult::Err(Diagnostic::error(codes::INVALID_CHAR,
+                "Chars hold exactly one character.", self.pos(index))
+                .with_help("Use double quotes for strings")),
+        }
     }
 
-    fn gather_token(&mut self) -> LexResult<'a, Token<'a>> {
+    /// Returns a position object ranging from a given 
+    /// start index to the current character
+    fn pos(&self, index: usize) -> Position {
+        Position::new(self.file.id(), index, self.index.max(index))
+    }
+
+    /// Returns an Error token covering everything from
+    /// the given start index to the current character.
+    fn error_token(&self, index: usize) -> Token<'a> {
+        Token::Error(self.pos(index), &self.src[index..self.index.max(index)])
+    }
+
+    /// Advances over `len` bytes and returns a LexResult::Ok
+    /// of the token spanning them. Useful for one line token returns.
+    fn tok(&mut self, index: usize, len: usize, token: MakeToken<'a>) -> LexResult<Token<'a>> {
+        self.jump(index + len);
+        LexResult::Ok(token(self.pos(index)))
+    }
+
+    /// Finds the longest operator starting at the current character.
+    fn operator(&self) -> Option<(&'static str, MakeToken<'a>)> {
+        let rest = &self.src.as_bytes()[self.index..];
+        // Comparing first bytes is much cheaper than trying every operator
+        Self::OPERATORS.iter()
+            .filter(|(op, _)| op.as_bytes()[0] == rest[0])
+            .find(|(op, _)| rest.starts_with(op.as_bytes()))
+            .map(|(op, token)| (*op, *token))
+    }
+
+    fn gather_token(&mut self) -> LexResult<Token<'a>> {
         let index = self.index;
         if let Some(c) = self.current {
             match c {
-                // Basic one character tokens
-                '+' => self.tok(Token::Plus(self.pos(index))),
-                '-' => self.tok(Token::Minus(self.pos(index))),
-                '*' => self.tok(Token::Star(self.pos(index))),
-                '/' => self.tok(Token::Slash(self.pos(index))),
+                '/' if self.is_doc_comment() => LexResult::Ok(self.doc_comment(index)),
+                '"' => self.string(index),
+                '\'' => self.char(index),
+                'r' if self.is_raw_string() => self.raw_string(index),
                 // If it's a number, generate number token
-                _ if c.is_numeric() => self.number(index),
+                _ if c.is_ascii_digit() => LexResult::Ok(self.number(index)),
                 // If it's a letter, generate an identifier token
-                _ if c.is_alphabetic() => LexResult::Ok(self.identifier(index)),
-                // Otherwise, unknown token
-                _ => LexResult::Err("UnknownToken".to_string(), "Unknown symbol or token".to_string(),
-                    self.pos(index)),
+                _ if c.is_alphabetic() || c == '_' => LexResult::Ok(self.identifier(index)),
+                // Operators and delimiters, otherwise unknown token
+                _ => match self.operator() {
+                    Some((op, token)) => self.tok(index, op.len(), token),
+                    None => LexResult::Err(Diagnostic::error(codes::UNKNOWN_TOKEN, "Unknown symbol or token",
+                        self.pos(index))),
+                },
             }
         } else {
-            LexResult::Err("EndOfFile".to_string(), "Unexpected end of file.".to_string(), 
-                self.pos(index))
+            LexResult::Err(Diagnostic::error(codes::LEX_END_OF_FILE, "Unexpected end of file.", 
+                self.pos(index)))
         }
     }
 
-    fn lex(&mut self) -> LexResult<'a, Vec<Token<'a>>> {
+    /// Skips the rest of some code that couldn't be lexed,
+    /// so lexing can carry on from somewhere sensible.
+    /// Stops at whitespace or a bracket, but always skips at least
+    /// one character so it can't get stuck.
+    fn recover(&mut self, index: usize) {
+        if self.index == index {
+            self.advance();
+        }
+        while let Some(c) = self.current {
+            if c.is_whitespace() || c == '(' || c == ')' {
+                break;
+            }
+            self.advance();
+        }
+    }
+
+    /// Counts the errors found so far.
+    fn errors(&self) -> usize {
+        self.diagnostics.iter().filter(|d| d.severity == Severity::Error).count()
+    }
+
+    fn lex(&mut self) -> Lexed<'a> {
         let mut tokens = Vec::new();
         // While the current character exists (not end of file)
         while self.current.is_some() {
-            self.skip_whitespace();
-            // Attempt to lex a token
-            let res = self.gather_token();
-            match res {
+            // Unclosed comments run to the end of the file,
+            // so there's nothing left to recover.
+            if let LexResult::Err(e) = self.skip_trivia() {
+                self.diagnostics.push(e);
+                break;
+            }
+            // Trailing whitespace isn't a token
+            if self.current.is_none() {
+                break;
+            }
+            let index = self.index;
+            match self.gather_token() {
                 // Append to token vector if success
                 LexResult::Ok(token) => tokens.push(token),
-                // Return error otherwise
-                LexResult::Err(head, tail, pos) => return LexResult::Err(head, tail, pos),
+                // Otherwise keep the error and cover the bad code
+                // with an Error token, so later phases still get
+                // as much of the file as possible.
+                LexResult::Err(e) => {
+                    self.diagnostics.push(e);
+                    self.recover(index);
+                    tokens.push(self.error_token(index));
+                },
             };
+            // Some tokens record errors without failing, so check every time
+            let errors = self.errors();
+            if errors >= self.max_errors {
+                self.diagnostics.push(Diagnostic::new(Severity::Note, codes::TOO_MANY_ERRORS,
+                    format!("Stopped lexing after {} errors.", errors), None));
+                break;
+            }
         }
-        LexResult::Ok(tokens)
+        Lexed { tokens, diagnostics: std::mem::take(&mut self.diagnostics) }
     }
+}
 
+/// Takes in source code and creates tokens from it.
+/// Lexing doesn't stop at errors, they're all collected
+/// alongside the tokens.
+pub fn lex(file: &SourceFile) -> Lexed<'_> {
+    lex_with_limit(file, DEFAULT_MAX_ERRORS)
 }
 
-/// Takes in source code and creates tokens from it
-pub fn lex<'a>(filename: &'a str, src: &'a str) -> LexResult<'a, Vec<Token<'a>>> {
-    let mut lexer = Lexer::new(filename, src);
+/// Same as lex, but gives up after max_errors errors
+/// instead of the default.
+pub fn lex_with_limit(file: &SourceFile, max_errors: usize) -> Lexed<'_> {
+    let mut lexer = Lexer::new(file, max_errors);
     lexer.lex()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::source::SourceMap;
+    use std::time::Instant;
+
+    /// Large inputs that each lean on one part of the lexer.
+    const INPUTS: &[(&str, &str, usize)] = &[
+        ("identifiers", "alpha beta_gamma delta123 epsilon zeta_eta theta ", 8),
+        ("strings", "\"hello world, this is a string\" \"and another one\" ", 8),
+        ("comments", "// a line comment that goes on for a while to fill space\n", 8),
+        ("mixed", "/// Adds things up\nfn add(a: int, b: int) -> int { let total = a + b * 3 /* math */ total }\n\
+            struct Point { x: float, y: float }\nlet p = Point(1.5, 2.25e3) let name = \"point\" let n = 0xFF_FF + 1_000u32\n", 28),
+    ];
+
+    /// Lexes some code, returning every token written out and the id of every error.
+    fn lexed(src: &str) -> (Vec<String>, Vec<&'static str>) {
+        let mut map = SourceMap::new();
+        let file = map.add("<test>", src);
+        let lexed = lex(map.get(file));
+        let errors = lexed.diagnostics.iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .map(|diagnostic| diagnostic.code.id)
+            .collect();
+        (lexed.tokens.iter().map(|token| token.to_string()).collect(), errors)
+    }
+
+    fn tokens(src: &str) -> Vec<String> {
+        let (tokens, errors) = lexed(src);
+        assert!(errors.is_empty(), "{:?} had errors {:?}", src, errors);
+        tokens
+    }
+
+    fn errors(src: &str) -> Vec<&'static str> {
+        lexed(src).1
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(tokens("42 0xFF 0o17 0b1010 1_000"), ["42", "255", "15", "10", "1000"]);
+        assert_eq!(tokens("1.5 1e3 2.5e-1 3f 255u8"), ["1.5f", "1000f", "0.25f", "3f", "255"]);
+    }
+
+    #[test]
+    fn ranges_and_fields_arent_floats() {
+        assert_eq!(tokens("1..2"), ["1", "..", "2"]);
+        assert_eq!(tokens("x.0"), ["x", ".", "0"]);
+    }
+
+    #[test]
+    fn bad_numbers() {
+        assert_eq!(errors("0x"), ["L003"]);
+        assert_eq!(errors("0b102"), ["L003"]);
+        assert_eq!(errors("256u8"), ["L003"]);
+        assert_eq!(errors("9223372036854775808"), ["L003"]);
+        assert_eq!(errors("1.5u8"), ["L003"]);
+        assert_eq!(errors("12abc"), ["L003"]);
+    }
+
+    #[test]
+    fn float_suffixes_need_decimal_numbers() {
+        // These used to read the digits as decimal, giving 101.0 and 17.0
+        assert_eq!(errors("0b101f"), ["L003"]);
+        assert_eq!(errors("0o17f64"), ["L003"]);
+        assert_eq!(errors("0b1f32"), ["L003"]);
+        // f is a hex digit, so this is just an int
+        assert_eq!(tokens("0x1f"), ["31"]);
+        assert_eq!(tokens("17f"), ["17f"]);
+    }
+
+    #[test]
+    fn bad_numbers_dont_take_the_line() {
+        let (tokens, errors) = lexed("0b102 + 1");
+        assert_eq!(errors, ["L003"]);
+        assert_eq!(tokens[1..], ["+", "1"]);
+    }
+
+    #[test]
+    fn strings_and_chars() {
+        assert_eq!(tokens(r#""a\tb" 'c' '\n'"#), [r#""a\tb""#, "'c'", r"'\n'"]);
+        assert_eq!(tokens(r##"r#"a "quote" \n"#"##), [r#""a \"quote\" \\n""#]);
+        assert_eq!(errors(r#""\q""#), ["L005"]);
+        assert_eq!(errors(r#""open"#), ["L002"]);
+        assert_eq!(errors("'ab'"), ["L006"]);
+    }
+
+    #[test]
+    fn operators_take_the_longest_match() {
+        assert_eq!(tokens("a**=b<<=c=>d::e"), ["a", "**=", "b", "<<=", "c", "=>", "d", "::", "e"]);
+        assert_eq!(tokens("a??b?c"), ["a", "??", "b", "?", "c"]);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(tokens("1 // two\n/* three /* nested */ */ 4"), ["1", "4"]);
+        assert_eq!(tokens("/// docs\nlet"), ["DOC:docs", "KEYWORD:let"]);
+        assert_eq!(errors("/* open /* nested */"), ["L002"]);
+    }
+
+    #[test]
+    fn text_is_borrowed() {
+        let src = "let size = \"größe\" \"tab\\t\"";
+        let mut map = SourceMap::new();
+        let file = map.add("<test>", src);
+        let lexed = lex(map.get(file));
+        assert!(lexed.diagnostics.is_empty());
+        match &lexed.tokens[..] {
+            [_, Token::Identifier(_, name), _, Token::Str(_, plain), Token::Str(_, escaped)] => {
+                assert_eq!(*name, "size");
+                assert!(matches!(plain, Cow::Borrowed("größe")));
+                assert!(matches!(escaped, Cow::Owned(_)));
+            },
+            tokens => panic!("unexpected tokens {:?}", tokens.iter().map(|token| token.to_string()).collect::<Vec<_>>()),
+        }
+    }
+
+    #[test]
+    fn errors_dont_stop_lexing() {
+        let (tokens, errors) = lexed("1 $ 2 $");
+        assert_eq!(errors, ["L001", "L001"]);
+        assert_eq!(tokens, ["1", "ERROR:$", "2", "ERROR:$"]);
+    }
+
+    #[test]
+    fn errors_are_limited() {
+        let mut map = SourceMap::new();
+        let file = map.add("<test>", "$ ".repeat(10));
+        let lexed = lex_with_limit(map.get(file), 3);
+        assert_eq!(lexed.diagnostics.iter().filter(|diagnostic| diagnostic.severity == Severity::Error).count(), 3);
+        assert_eq!(lexed.diagnostics.last().map(|diagnostic| diagnostic.code.id), Some("L004"));
+    }
+
+    /// Prints how fast each of the inputs lexes, which is where the
+    /// numbers at the top of the file come from. Run it with
+    /// cargo test --release throughput -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn throughput() {
+        for (name, unit, mb) in INPUTS {
+            let mut map = SourceMap::new();
+            let file = map.add(*name, unit.repeat(mb * 1_000_000 / unit.len()));
+            // Best of a few runs, so a slow one doesn't count
+            let best = (0..3).map(|_| {
+                let start = Instant::now();
+                let lexed = lex_with_limit(map.get(file), usize::MAX);
+                assert!(!lexed.has_errors());
+                start.elapsed()
+            }).min().unwrap();
+            let mb = map.get(file).src().len() as f64 / 1e6;
+            println!("{:<12} {:>5.1} MB {:>6.0} MB/s", name, mb, mb / best.as_secs_f64());
+        }
+    }
+}
@@ -1,4 +1,6 @@
 
 pub mod token;
+#[allow(clippy::module_inception)]
 pub mod lexer;
 pub mod result;
+pub mod source;
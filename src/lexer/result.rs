@@ -1,26 +1,26 @@
 
-use super::token::Position;
-use std::fmt::{ self, Display, Formatter };
-use crate::utils::general as utils;
+use crate::utils::diagnostic::{ Diagnostic, Severity };
+use super::token::Token;
 
 /// Result that either returns a lexer error or a value.
-pub enum LexResult<'a, T> {
+pub enum LexResult<T> {
     /// Lexer result.
     Ok(T),
     /// Lexer error.
-    Err(String, String, Position<'a>),
+    Err(Diagnostic),
 }
 
-impl<'a, T> Display for LexResult<'a, T> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Ok(_) => write!(f, "Ok!"),
-            // If it's an error, unpack it.
-            Self::Err(name, reason, pos,) => {
-                // Now format it to make it look pretty :D
-                write!(f, "{} Error: {}\nFile {}, line {}\n{}", name, reason, pos.filename(), 
-                    utils::line_of(pos.src(), pos.index()) + 1, utils::underline_selection(pos))
-            }
-        }
+/// Everything the lexer found in some source code.
+/// The tokens are always there, even for broken code,
+/// with Error tokens covering whatever couldn't be lexed.
+pub struct Lexed<'a> {
+    pub tokens: Vec<Token<'a>>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Lexed<'a> {
+    /// Returns true if any of the diagnostics is an error.
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|d| d.severity == Severity::Error)
     }
 }
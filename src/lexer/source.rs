@@ -0,0 +1,179 @@
+
+/// How many columns a tab moves to the right, at most.
+/// Tabs line up to the next multiple of this.
+pub const TAB_WIDTH: usize = 4;
+
+/// Identifies a file in a SourceMap.
+/// Positions store this instead of borrowing the source,
+/// so they're small and don't tie anything to its lifetime.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FileId(usize);
+
+/// Owns every file loaded so far.
+/// Files are never removed, so their ids stay valid.
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file to the map and returns its id.
+    pub fn add(&mut self, name: impl Into<String>, src: impl Into<String>) -> FileId {
+        let id = FileId(self.files.len());
+        self.files.push(SourceFile::new(id, name, src));
+        id
+    }
+
+    /// Returns the file with the given id.
+    /// Ids only come from add, so the file always exists.
+    pub fn get(&self, id: FileId) -> &SourceFile {
+        &self.files[id.0]
+    }
+}
+
+/// A file of source code along with the byte offset
+/// every line starts at, so finding the line and column
+/// of a position doesn't have to rescan the file.
+pub struct SourceFile {
+    id: FileId,
+    name: String,
+    src: String,
+    // Always starts with 0, then one entry after every newline
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    fn new(id: FileId, name: impl Into<String>, src: impl Into<String>) -> Self {
+        let src = src.into();
+        let mut line_starts = vec![0];
+        line_starts.extend(src.match_indices('\n').map(|(i, _)| i + 1));
+        Self {
+            id,
+            name: name.into(),
+            src,
+            line_starts,
+        }
+    }
+
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn src(&self) -> &str {
+        &self.src
+    }
+
+    /// Moves an offset back to the start of the character it's in,
+    /// and into the file. Offsets from compiled files might not
+    /// match the source anymore if it changed since.
+    pub fn clamp(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.src.len());
+        while !self.src.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+
+    /// Returns the line (counting from 0) that the byte at
+    /// the given offset is on. A newline belongs to the line it ends.
+    pub fn line_index(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            // Always at least 1, since the first line starts at 0
+            Err(line) => line - 1,
+        }
+    }
+
+    /// Returns the byte offset a line (counting from 0) starts at.
+    pub fn line_start(&self, line: usize) -> usize {
+        self.line_starts[line]
+    }
+
+    /// Returns the text of a line (counting from 0)
+    /// without the newline at the end.
+    pub fn line(&self, line: usize) -> &str {
+        let start = self.line_starts[line];
+        let end = match self.line_starts.get(line + 1) {
+            Some(next) => next - 1,
+            None => self.src.len(),
+        };
+        self.src[start..end].strip_suffix('\r').unwrap_or(&self.src[start..end])
+    }
+
+    /// Returns the line and column (both counting from 1)
+    /// of the character at the given byte offset.
+    /// Columns count characters rather than bytes, and tabs
+    /// move to the next multiple of TAB_WIDTH.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = self.clamp(offset);
+        let line = self.line_index(offset);
+        let start = self.line_starts[line];
+        (line + 1, column(&self.src[start..offset]) + 1)
+    }
+}
+
+/// Replaces the tabs in a line with spaces, so that it
+/// lines up with the columns given by `column`.
+pub fn expand_tabs(line: &str) -> String {
+    let mut s = String::new();
+    for c in line.chars() {
+        match c {
+            '\t' => s.push_str(&" ".repeat(TAB_WIDTH - s.chars().count() % TAB_WIDTH)),
+            _ => s.push(c),
+        }
+    }
+    s
+}
+
+/// Returns how many columns wide some text on a single line is.
+pub fn column(text: &str) -> usize {
+    text.chars().fold(0, |col, c| match c {
+        '\t' => (col / TAB_WIDTH + 1) * TAB_WIDTH,
+        _ => col + 1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_and_columns() {
+        let mut map = SourceMap::new();
+        let id = map.add("<test>", "one\r\ntwo\n\tdö x");
+        let file = map.get(id);
+        assert_eq!(file.line_col(0), (1, 1));
+        assert_eq!(file.line_col(5), (2, 1));
+        assert_eq!(file.line(0), "one");
+        assert_eq!(file.line(1), "two");
+        // Tabs line up to TAB_WIDTH and ö is two bytes but one column
+        assert_eq!(file.line_col(file.src().len() - 1), (3, 8));
+        // Offsets in the middle of a character or past the end get clamped
+        assert_eq!(file.line_col(12), (3, 6));
+        assert_eq!(file.line_col(100), (3, 9));
+    }
+
+    #[test]
+    fn files_keep_their_ids() {
+        let mut map = SourceMap::new();
+        let a = map.add("a", "1");
+        let b = map.add("b", "2");
+        assert_ne!(a, b);
+        assert_eq!(map.get(a).name(), "a");
+        assert_eq!(map.get(b).id(), b);
+    }
+
+    #[test]
+    fn tabs() {
+        assert_eq!(expand_tabs("a\tb\t\tc"), "a   b       c");
+        assert_eq!(column("a\tb\t\tc"), 13);
+    }
+}
@@ -1,67 +1,201 @@
+use std::borrow::Cow;
 use std::fmt::{ self, Display, Formatter };
 use std::cmp::{ min, max };
+use super::source::FileId;
 
-/// Stores the position of something in 
-/// a string (src, filename).
-/// Index indicates the starting character's index,
-/// and len indicates how many characters
-/// the selection spans.
-pub struct Position<'a> {
-    index: usize,
-    len: usize,
-    src: &'a str,
-    filename: &'a str,
+/// Stores the position of something in a file
+/// from a SourceMap. Start and end are byte offsets,
+/// with end being one past the last byte of the selection.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Position {
+    file: FileId,
+    start: usize,
+    end: usize,
 }
 
-impl<'a> Position<'a> {
-    pub fn new(index: usize, len: usize, src: &'a str, filename: &'a str) -> Self {
-        Position { index, len, src, filename }
+impl Position {
+    pub fn new(file: FileId, start: usize, end: usize) -> Self {
+        Position { file, start, end }
     }
 
     /// Allows you to add two positions together to get
-    /// the area covered by both
-    pub fn extend(&self, other: &Position<'a>) -> Position<'a> {
-        let index = min(other.index, self.index);
-        let len = max(self.index + self.len, other.index + other.len) - index;
-        Position::new(index, len, self.src, self.filename)
+    /// the area covered by both.
+    /// Both have to be in the same file.
+    pub fn extend(&self, other: &Position) -> Position {
+        Position::new(self.file, min(self.start, other.start), max(self.end, other.end))
     }
 
     // Some basic getters for each property.
-    pub fn index(&self) -> usize {
-        self.index
+    pub fn file(&self) -> FileId {
+        self.file
     }
 
-    pub fn len(&self) -> usize {
-        self.len
+    pub fn start(&self) -> usize {
+        self.start
     }
 
-    pub fn src(&self) -> &str {
-        self.src
+    pub fn end(&self) -> usize {
+        self.end
     }
 
-    pub fn filename(&self) -> &str {
-        self.filename
+    pub fn len(&self) -> usize {
+        self.end - self.start
     }
 }
 
-impl<'a> fmt::Display for Position<'a> {
-    // Writes position as {index:len} for to_string
+impl fmt::Display for Position {
+    // Writes position as {start:len} for to_string
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{{{}:{}}}", self.index, self.len)
+        write!(f, "{{{}:{}}}", self.start, self.len())
     }
 }
 
+/// Builds a token from its position, eg Token::Plus
+pub type MakeToken<'a> = fn(Position) -> Token<'a>;
+
 /// Tokens produced by the lexer.
 /// Every token stores at least a position.
 pub enum Token<'a> {
-    Plus(Position<'a>),
-    Minus(Position<'a>),
-    Star(Position<'a>),
-    Slash(Position<'a>),
-    Int(Position<'a>, i64),
-    Float(Position<'a>, f64),
-    Identifier(Position<'a>, String),
-    Keyword(Position<'a>, String),
+    // Arithmetic
+    Plus(Position),
+    Minus(Position),
+    Star(Position),
+    Slash(Position),
+    Percent(Position),
+    StarStar(Position),
+
+    // Comparison
+    EqEq(Position),
+    BangEq(Position),
+    Less(Position),
+    LessEq(Position),
+    Greater(Position),
+    GreaterEq(Position),
+
+    // Logical
+    AndAnd(Position),
+    OrOr(Position),
+    Bang(Position),
+
+    // Bitwise
+    Amp(Position),
+    Pipe(Position),
+    Caret(Position),
+    Tilde(Position),
+    Shl(Position),
+    Shr(Position),
+
+    // Assignment
+    Equals(Position),
+    PlusEq(Position),
+    MinusEq(Position),
+    StarEq(Position),
+    SlashEq(Position),
+    PercentEq(Position),
+    StarStarEq(Position),
+    AmpEq(Position),
+    PipeEq(Position),
+    CaretEq(Position),
+    ShlEq(Position),
+    ShrEq(Position),
+
+    // Punctuation
+    Arrow(Position),
+    FatArrow(Position),
+    Dot(Position),
+    DotDot(Position),
+    Colon(Position),
+    ColonColon(Position),
+    Question(Position),
+    QuestionQuestion(Position),
+    Comma(Position),
+
+    // Delimiters
+    LParen(Position),
+    RParen(Position),
+    LBracket(Position),
+    RBracket(Position),
+    LBrace(Position),
+    RBrace(Position),
+    Semicolon(Position),
+
+    // Literals
+    Int(Position, i64),
+    Float(Position, f64),
+    // Borrowed from the source unless it had escapes
+    Str(Position, Cow<'a, str>),
+    Char(Position, char),
+    Identifier(Position, &'a str),
+    Keyword(Position, &'a str),
+    // Text of a /// comment
+    DocComment(Position, &'a str),
+    // Stands in for code that couldn't be lexed
+    Error(Position, &'a str),
+}
+
+impl<'a> Token<'a> {
+    /// Returns the position of the token.
+    pub fn pos(&self) -> &Position {
+        match self {
+            Self::Plus(pos) => pos,
+            Self::Minus(pos) => pos,
+            Self::Star(pos) => pos,
+            Self::Slash(pos) => pos,
+            Self::Percent(pos) => pos,
+            Self::StarStar(pos) => pos,
+            Self::EqEq(pos) => pos,
+            Self::BangEq(pos) => pos,
+            Self::Less(pos) => pos,
+            Self::LessEq(pos) => pos,
+            Self::Greater(pos) => pos,
+            Self::GreaterEq(pos) => pos,
+            Self::AndAnd(pos) => pos,
+            Self::OrOr(pos) => pos,
+            Self::Bang(pos) => pos,
+            Self::Amp(pos) => pos,
+            Self::Pipe(pos) => pos,
+            Self::Caret(pos) => pos,
+            Self::Tilde(pos) => pos,
+            Self::Shl(pos) => pos,
+            Self::Shr(pos) => pos,
+            Self::Equals(pos) => pos,
+            Self::PlusEq(pos) => pos,
+            Self::MinusEq(pos) => pos,
+            Self::StarEq(pos) => pos,
+            Self::SlashEq(pos) => pos,
+            Self::PercentEq(pos) => pos,
+            Self::StarStarEq(pos) => pos,
+            Self::AmpEq(pos) => pos,
+            Self::PipeEq(pos) => pos,
+            Self::CaretEq(pos) => pos,
+            Self::ShlEq(pos) => pos,
+            Self::ShrEq(pos) => pos,
+            Self::Arrow(pos) => pos,
+            Self::FatArrow(pos) => pos,
+            Self::Dot(pos) => pos,
+            Self::DotDot(pos) => pos,
+            Self::Colon(pos) => pos,
+            Self::ColonColon(pos) => pos,
+            Self::Question(pos) => pos,
+            Self::QuestionQuestion(pos) => pos,
+            Self::Comma(pos) => pos,
+            Self::LParen(pos) => pos,
+            Self::RParen(pos) => pos,
+            Self::LBracket(pos) => pos,
+            Self::RBracket(pos) => pos,
+            Self::LBrace(pos) => pos,
+            Self::RBrace(pos) => pos,
+            Self::Semicolon(pos) => pos,
+            Self::Int(pos, _) => pos,
+            Self::Float(pos, _) => pos,
+            Self::Str(pos, _) => pos,
+            Self::Char(pos, _) => pos,
+            Self::Identifier(pos, _) => pos,
+            Self::Keyword(pos, _) => pos,
+            Self::DocComment(pos, _) => pos,
+            Self::Error(pos, _) => pos,
+        }
+    }
 }
 
 impl<'a> Display for Token<'a> {
@@ -72,10 +206,60 @@ impl<'a> Display for Token<'a> {
             Self::Minus(_) => "-".to_string(),
             Self::Star(_) => "*".to_string(),
             Self::Slash(_) => "/".to_string(),
+            Self::Percent(_) => "%".to_string(),
+            Self::StarStar(_) => "**".to_string(),
+            Self::EqEq(_) => "==".to_string(),
+            Self::BangEq(_) => "!=".to_string(),
+            Self::Less(_) => "<".to_string(),
+            Self::LessEq(_) => "<=".to_string(),
+            Self::Greater(_) => ">".to_string(),
+            Self::GreaterEq(_) => ">=".to_string(),
+            Self::AndAnd(_) => "&&".to_string(),
+            Self::OrOr(_) => "||".to_string(),
+            Self::Bang(_) => "!".to_string(),
+            Self::Amp(_) => "&".to_string(),
+            Self::Pipe(_) => "|".to_string(),
+            Self::Caret(_) => "^".to_string(),
+            Self::Tilde(_) => "~".to_string(),
+            Self::Shl(_) => "<<".to_string(),
+            Self::Shr(_) => ">>".to_string(),
+            Self::Equals(_) => "=".to_string(),
+            Self::PlusEq(_) => "+=".to_string(),
+            Self::MinusEq(_) => "-=".to_string(),
+            Self::StarEq(_) => "*=".to_string(),
+            Self::SlashEq(_) => "/=".to_string(),
+            Self::PercentEq(_) => "%=".to_string(),
+            Self::StarStarEq(_) => "**=".to_string(),
+            Self::AmpEq(_) => "&=".to_string(),
+            Self::PipeEq(_) => "|=".to_string(),
+            Self::CaretEq(_) => "^=".to_string(),
+            Self::ShlEq(_) => "<<=".to_string(),
+            Self::ShrEq(_) => ">>=".to_string(),
+            Self::Arrow(_) => "->".to_string(),
+            Self::FatArrow(_) => "=>".to_string(),
+            Self::Dot(_) => ".".to_string(),
+            Self::DotDot(_) => "..".to_string(),
+            Self::Colon(_) => ":".to_string(),
+            Self::ColonColon(_) => "::".to_string(),
+            Self::Question(_) => "?".to_string(),
+            Self::QuestionQuestion(_) => "??".to_string(),
+            Self::Comma(_) => ",".to_string(),
+            Self::LParen(_) => "(".to_string(),
+            Self::RParen(_) => ")".to_string(),
+            Self::LBracket(_) => "[".to_string(),
+            Self::RBracket(_) => "]".to_string(),
+            Self::LBrace(_) => "{".to_string(),
+            Self::RBrace(_) => "}".to_string(),
+            Self::Semicolon(_) => ";".to_string(),
             Self::Int(_, n) => n.to_string(),
             Self::Float(_, n) => format!("{}f", n),
+            // Quoted and escaped, like in source
+            Self::Str(_, s) => format!("{:?}", s),
+            Self::Char(_, c) => format!("{:?}", c),
             Self::Identifier(_, ident) => ident.to_string(),
             Self::Keyword(_, keyword) => format!("KEYWORD:{}", keyword),
+            Self::DocComment(_, text) => format!("DOC:{}", text),
+            Self::Error(_, text) => format!("ERROR:{}", text),
         })
     }
 }
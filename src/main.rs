@@ -1,25 +1,24 @@
+mod bytecode;
+mod cli;
+mod interpreter;
 mod lexer;
+mod modules;
 mod parser;
+mod stdlib;
 mod types;
 mod utils;
-use utils::logging;
+
+// The interpreter recurses on the Rust stack for every call in
+// the program, so it gets a much bigger one than the main thread's
+const STACK_SIZE: usize = 256 * 1024 * 1024;
 
 fn main() {
-    let demo_code = "let test 0xABCD";
-    let result = lexer::lexer::lex("demo_code", demo_code);
-    logging::println(&format!("Demo code: {}", demo_code));
-    if let lexer::result::LexResult::Ok(tokens) = result {
-        let mut s = "[ ".to_string();
-        for token in tokens {
-            let token_str = token.to_string();
-            s.push_str(&token_str);
-            s.push_str(", ");
-        }
-        s.pop();
-        s.pop();
-        s.push_str(" ]");
-        logging::println(&format!("Tokens: {}", s));
-    } else {
-        logging::err(&format!("{}", result));
-    }
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let code = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || cli::execute(&args))
+        .expect("couldn't start the main thread")
+        .join()
+        .unwrap_or(101);
+    std::process::exit(code);
 }
@@ -0,0 +1,424 @@
+use crate::interpreter::{ Interpreter, result::RunResult, value };
+use crate::lexer::{ self, source::{ FileId, SourceMap }, token::Position };
+use crate::parser::{ self, expr::{ Expr, Member }, result::ParseResult };
+use crate::stdlib;
+use crate::types::{ Checker, Exports };
+use crate::utils::{ codes, diagnostic::Diagnostic, logging };
+use std::collections::{ HashMap, HashSet };
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::rc::Rc;
+
+/*
+* `import math` looks for math.jpz next to the file doing the
+* importing first, then in each directory of the search path.
+*
+* Every module is lexed, parsed and checked once, the first time
+* something imports it, no matter how many files import it after
+* that. Modules with errors aren't kept, so importing one again
+* later, like from the next REPL entry once the file is fixed,
+* loads it from scratch. Its imports get loaded before it's checked, so loading is
+* depth first and the modules being loaded at any point form a
+* chain of imports. Finding a module already in that chain means
+* the imports go round in a circle, which can never be checked
+* since each module would need the other one checked first.
+*
+* Running works the same way, each module runs once the first
+* time something that imports it runs.
+*/
+
+/// The extension module files have.
+const EXTENSION: &str = "jpz";
+
+/// A module that's been loaded and checked.
+struct Module {
+    name: String,
+    // Its canonical path, to forget it by if it fails to run
+    path: PathBuf,
+    tree: Expr,
+    exports: Exports,
+    // Indexes of the modules it imports
+    imports: Vec<usize>,
+    // Set once it's been run
+    value: Option<Rc<value::Module>>,
+}
+
+/// A file that's been checked along with every module
+/// it imports, ready to run.
+pub struct Program {
+    pub tree: Expr,
+    imports: Vec<usize>,
+}
+
+/// A module in the middle of being loaded.
+struct Loading {
+    name: String,
+    path: PathBuf,
+    // The import that started loading it, if it wasn't the file being run
+    import: Option<Position>,
+}
+
+/// Finds, checks and runs modules, keeping each one around
+/// so it only happens once.
+pub struct Loader {
+    // Where to look for modules that aren't next to the file importing them
+    search: Vec<PathBuf>,
+    modules: Vec<Module>,
+    // Each module that loaded without errors by its canonical path
+    by_path: HashMap<PathBuf, usize>,
+    // Modules that had errors while loading the current file or
+    // entry, which have already been reported
+    failed: HashSet<PathBuf>,
+    // The chain of imports being loaded, outermost first
+    loading: Vec<Loading>,
+}
+
+/// Lexes and parses a file, logging every error.
+fn parse(map: &SourceMap, file: FileId) -> Option<Expr> {
+    let lexed = lexer::lexer::lex(map.get(file));
+    for diagnostic in &lexed.diagnostics {
+        logging::diagnostic(diagnostic, map);
+    }
+    if lexed.has_errors() {
+        return None;
+    }
+    match parser::parse(map.get(file), lexed.tokens) {
+        ParseResult::Ok(tree) => Some(tree),
+        ParseResult::Err(e) => {
+            logging::diagnostic(&e, map);
+            None
+        }
+    }
+}
+
+/// Returns the imports at the top of a tree, which is the only place they can be.
+fn imports_in(tree: &Expr) -> impl Iterator<Item = (&Position, &Member)> {
+    let exprs = match tree {
+        Expr::Body(_, exprs) => exprs.as_slice(),
+        _ => std::slice::from_ref(tree),
+    };
+    exprs.iter().filter_map(|expr| match expr {
+        Expr::Import(pos, module, _) => Some((pos, module)),
+        _ => None,
+    })
+}
+
+impl Loader {
+    pub fn new(search: Vec<PathBuf>) -> Self {
+        Self {
+            search,
+            modules: Vec::new(),
+            by_path: HashMap::new(),
+            failed: HashSet::new(),
+            loading: Vec::new(),
+        }
+    }
+
+    /// Finds the file a module is in, looking next
+    /// to the importing file before the search path.
+    fn find(&self, dir: &Path, name: &str) -> Option<PathBuf> {
+        let file = format!("{}.{}", name, EXTENSION);
+        std::iter::once(dir).chain(self.search.iter().map(|dir| dir.as_path()))
+            .map(|dir| dir.join(&file))
+            .find(|path| path.is_file())
+    }
+
+    /// Reports an import that ends up importing the module it's in.
+    /// `start` is where the module is in the chain being loaded.
+    fn cycle(&self, start: usize, pos: &Position, name: &str) -> Diagnostic {
+        let chain = &self.loading[start..];
+        if chain.len() == 1 {
+            return Diagnostic::error(codes::IMPORT_CYCLE, format!("'{}' imports itself", name), *pos)
+                .with_help("Remove the import");
+        }
+        let names: Vec<&str> = chain.iter().map(|loading| loading.name.as_str()).chain([name]).collect();
+        let mut error = Diagnostic::error(codes::IMPORT_CYCLE,
+            format!("These imports go round in a circle: {}", names.join(" -> ")), *pos);
+        for (importer, loading) in chain.iter().zip(&chain[1..]) {
+            if let Some(import) = loading.import {
+                error = error.with_label(import, format!("'{}' imports '{}' here", importer.name, loading.name));
+            }
+        }
+        error.with_help("Move what they share into a module that neither of them imports")
+    }
+
+    /// Loads, parses and checks a module along with everything
+    /// it imports, returning its index. `dir` is the directory
+    /// of the file importing it.
+    fn load_module(&mut self, map: &mut SourceMap, pos: &Position, module: &Member, dir: &Path) -> Option<usize> {
+        let found = match self.find(dir, &module.name) {
+            Some(found) => found,
+            None => {
+                let mut error = Diagnostic::error(codes::MODULE_NOT_FOUND,
+                    format!("There is no module called '{}'", module.name), module.pos)
+                    .with_note(format!("Looked for '{}.{}' next to this file", module.name, EXTENSION));
+                if !self.search.is_empty() {
+                    let dirs: Vec<String> = self.search.iter().map(|dir| format!("'{}'", dir.display())).collect();
+                    error = error.with_note(format!("And in {}", dirs.join(", ")));
+                }
+                if stdlib::library(&module.name).is_some() {
+                    error = error.with_help(format!("'{}' is part of the standard library, so it can be used without importing it", module.name));
+                }
+                logging::diagnostic(&error, map);
+                return None;
+            },
+        };
+        // The same file can be reached through different paths
+        let path = fs::canonicalize(&found).unwrap_or_else(|_| found.clone());
+        if let Some(index) = self.by_path.get(&path) {
+            return Some(*index);
+        }
+        if self.failed.contains(&path) {
+            return None;
+        }
+        if let Some(start) = self.loading.iter().position(|loading| loading.path == path) {
+            logging::diagnostic(&self.cycle(start, pos, &module.name), map);
+            return None;
+        }
+        let src = match fs::read_to_string(&found) {
+            Ok(src) => src,
+            Err(e) => {
+                logging::diagnostic(&Diagnostic::error(codes::IO,
+                    format!("Could not read '{}': {}", found.display(), e), module.pos), map);
+                return None;
+            },
+        };
+        let file = map.add(found.display().to_string(), src);
+        self.loading.push(Loading { name: module.name.clone(), path: path.clone(), import: Some(*pos) });
+        let loaded = self.check_module(map, file, &found, path.clone(), &module.name);
+        self.loading.pop();
+        match loaded {
+            Some(module) => {
+                self.modules.push(module);
+                self.by_path.insert(path, self.modules.len() - 1);
+                Some(self.modules.len() - 1)
+            },
+            None => {
+                self.failed.insert(path);
+                None
+            },
+        }
+    }
+
+    /// Parses and checks a module that's about to be loaded.
+    /// `canonical` is its path once links and such are resolved.
+    fn check_module(&mut self, map: &mut SourceMap, file: FileId, path: &Path, canonical: PathBuf, name: &str) -> Option<Module> {
+        let tree = parse(map, file)?;
+        let mut checker = Checker::new();
+        let imports = self.imports(map, &tree, path.parent().unwrap_or(Path::new("")), &mut checker)?;
+        let checked = checker.check(&tree);
+        for diagnostic in &checked.diagnostics {
+            logging::diagnostic(diagnostic, map);
+        }
+        if checked.has_errors() {
+            return None;
+        }
+        Some(Module { name: name.to_string(), path: canonical, tree, exports: checker.exports(), imports, value: None })
+    }
+
+    /// Loads every module a tree imports and hands what
+    /// they export to the checker that'll check the tree.
+    /// Returns the indexes of the modules, or None if any
+    /// of them had errors.
+    pub fn imports(&mut self, map: &mut SourceMap, tree: &Expr, dir: &Path, checker: &mut Checker) -> Option<Vec<usize>> {
+        // Broken modules get another go with each new file or entry
+        if self.loading.is_empty() {
+            self.failed.clear();
+        }
+        let mut indexes = Vec::new();
+        let mut ok = true;
+        // Keep going after a broken module, so every one gets reported
+        for (pos, module) in imports_in(tree) {
+            match self.load_module(map, pos, module, dir) {
+                Some(index) => {
+                    checker.provide(&module.name, self.modules[index].exports.clone());
+                    indexes.push(index);
+                },
+                None => ok = false,
+            }
+        }
+        ok.then_some(indexes)
+    }
+
+    /// Parses and checks the file being run, along with every
+    /// module it imports. `path` is where the file is, if it
+    /// came from one. Otherwise imports are looked for in the
+    /// current directory.
+    pub fn load(&mut self, map: &mut SourceMap, file: FileId, path: Option<&Path>) -> Option<Program> {
+        let tree = parse(map, file)?;
+        self.failed.clear();
+        let mut checker = Checker::new();
+        let dir = path.and_then(|path| path.parent()).unwrap_or(Path::new(""));
+        // Modules importing the file being run make a cycle too
+        if let Some(path) = path {
+            let name = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+            let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+            self.loading.push(Loading { name, path, import: None });
+        }
+        let imports = self.imports(map, &tree, dir, &mut checker);
+        self.loading.clear();
+        let imports = imports?;
+        let checked = checker.check(&tree);
+        for diagnostic in &checked.diagnostics {
+            logging::diagnostic(diagnostic, map);
+        }
+        if checked.has_errors() { None } else { Some(Program { tree, imports }) }
+    }
+
+    /// Runs a module, after the modules it imports,
+    /// unless it's already been run.
+    fn run_module(&mut self, map: &SourceMap, index: usize, interpreter: &mut Interpreter) -> Option<Rc<value::Module>> {
+        if let Some(value) = &self.modules[index].value {
+            return Some(Rc::clone(value));
+        }
+        let mut imports = Vec::new();
+        for import in self.modules[index].imports.clone() {
+            match self.run_module(map, import, interpreter) {
+                Some(import) => imports.push(import),
+                None => {
+                    self.forget(index);
+                    return None;
+                },
+            }
+        }
+        let module = &self.modules[index];
+        match interpreter.run_module(&module.name, &module.tree, imports) {
+            RunResult::Ok(value) => {
+                self.modules[index].value = Some(Rc::clone(&value));
+                Some(value)
+            },
+            RunResult::Err(e) => {
+                logging::diagnostic(&e, map);
+                self.forget(index);
+                None
+            },
+        }
+    }
+
+    /// Forgets a module that failed to run, so importing
+    /// it again loads it from the file again.
+    fn forget(&mut self, index: usize) {
+        let path = &self.modules[index].path;
+        if self.by_path.get(path) == Some(&index) {
+            self.by_path.remove(path);
+        }
+    }
+
+    /// Runs every module imported by something that's about to
+    /// run, and hands them to the interpreter that'll run it.
+    pub fn run_imports(&mut self, map: &SourceMap, imports: &[usize], interpreter: &mut Interpreter) -> Option<()> {
+        for &index in imports {
+            let module = self.run_module(map, index, interpreter)?;
+            interpreter.provide(module);
+        }
+        Some(())
+    }
+
+    /// Runs a program after everything it imports,
+    /// logging the error if there is one.
+    pub fn run(&mut self, map: &SourceMap, program: &Program, interpreter: &mut Interpreter) -> Option<value::Value> {
+        self.run_imports(map, &program.imports, interpreter)?;
+        match interpreter.run(&program.tree) {
+            RunResult::Ok(value) => Some(value),
+            RunResult::Err(e) => {
+                logging::diagnostic(&e, map);
+                None
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of module files that's removed at the end of the test.
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir = std::env::temp_dir().join(format!("jpizza-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            for (file, src) in files {
+                fs::write(dir.join(format!("{}.{}", file, EXTENSION)), src).unwrap();
+            }
+            Self(dir)
+        }
+
+        fn path(&self, file: &str) -> PathBuf {
+            self.0.join(format!("{}.{}", file, EXTENSION))
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Loads and runs one of the files, returning its value written out.
+    fn run(loader: &mut Loader, dir: &Dir, file: &str) -> Option<String> {
+        let mut map = SourceMap::new();
+        let path = dir.path(file);
+        let id = map.add(path.display().to_string(), fs::read_to_string(&path).unwrap());
+        let program = loader.load(&mut map, id, Some(&path))?;
+        loader.run(&map, &program, &mut Interpreter::new()).map(|value| value.to_string())
+    }
+
+    #[test]
+    fn imports() {
+        let dir = Dir::new("imports", &[
+            ("main", "import shapes\nfrom shapes import double\nshapes.area(2.0) + double(1.0)"),
+            ("shapes", "import util\nexport fn area(r: float) -> float { util.pi * r * r }\nexport fn double(x: float) -> float { x * 2.0 }"),
+            ("util", "export let pi = 3.0"),
+        ]);
+        assert_eq!(run(&mut Loader::new(Vec::new()), &dir, "main").as_deref(), Some("14.0"));
+    }
+
+    #[test]
+    fn modules_run_once() {
+        let dir = Dir::new("once", &[
+            ("main", "import a\nimport b\na.xs == b.xs"),
+            ("a", "import counter\nlists.push(counter.xs, 1)\nexport let xs = lists.len(counter.xs)"),
+            ("b", "import counter\nlists.push(counter.xs, 1)\nexport let xs = lists.len(counter.xs)"),
+            ("counter", "export let xs = [0]"),
+        ]);
+        // Both see the same list, so the second push makes it longer
+        assert_eq!(run(&mut Loader::new(Vec::new()), &dir, "main").as_deref(), Some("false"));
+    }
+
+    #[test]
+    fn import_cycles() {
+        let dir = Dir::new("cycles", &[
+            ("main", "import a\n1"),
+            ("a", "import b\nexport let x = 1"),
+            ("b", "import a\nexport let y = 2"),
+            ("own", "import own\n1"),
+            ("runner", "import back\n1"),
+            ("back", "import runner\nexport let z = 1"),
+        ]);
+        let mut loader = Loader::new(Vec::new());
+        assert_eq!(run(&mut loader, &dir, "main"), None);
+        assert_eq!(run(&mut loader, &dir, "own"), None);
+        assert_eq!(run(&mut loader, &dir, "runner"), None);
+    }
+
+    #[test]
+    fn broken_modules_load_again() {
+        let dir = Dir::new("again", &[("main", "import lib\nlib.x"), ("lib", "export let x: int = \"a\"")]);
+        let mut loader = Loader::new(Vec::new());
+        assert_eq!(run(&mut loader, &dir, "main"), None);
+        fs::write(dir.path("lib"), "export let x = 1 / 0").unwrap();
+        assert_eq!(run(&mut loader, &dir, "main"), None);
+        fs::write(dir.path("lib"), "export let x = 2").unwrap();
+        assert_eq!(run(&mut loader, &dir, "main").as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn missing_modules() {
+        let dir = Dir::new("missing", &[("main", "import nope\n1"), ("lib", "import math\n1")]);
+        let mut loader = Loader::new(Vec::new());
+        assert_eq!(run(&mut loader, &dir, "main"), None);
+        assert_eq!(run(&mut loader, &dir, "lib"), None);
+    }
+}
@@ -0,0 +1,2 @@
+mod loader;
+pub use loader::{ Loader, Program };
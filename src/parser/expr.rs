@@ -1,164 +1,484 @@
-use crate::{types::{JType, utils::is_numeric, TypeResult}, lexer::token::Position};
+use crate::lexer::token::Position;
+use crate::types::JType;
+use std::fmt::{ self, Display, Formatter };
+use std::rc::Rc;
 
-// Enum for each expression in the tree
-pub enum Expr<'a> {
-    // Statements
-    Body(Position<'a>, Vec<Expr<'a>>),
+/// A type written out in the code, eg the `int` in `let x: int = 1`.
+pub struct Annotation {
+    pub t: JType,
+    pub pos: Position,
+}
 
-    // Literals
-    Int(Position<'a>, i64),
-    Float(Position<'a>, f64),
+/// The name of a loop, eg the `outer` in `outer: while true { break outer }`.
+pub struct LoopLabel {
+    pub name: String,
+    pub pos: Position,
+}
 
-    // Binary operations
-    Add(Position<'a>, Box<Expr<'a>>, Box<Expr<'a>>),
-    Sub(Position<'a>, Box<Expr<'a>>, Box<Expr<'a>>),
-    Mul(Position<'a>, Box<Expr<'a>>, Box<Expr<'a>>),
-    Div(Position<'a>, Box<Expr<'a>>, Box<Expr<'a>>),
-}
-
-impl<'a> Expr<'a> {
-    /// Gets the type of the expression.
-    /// In the result of a TypeError, a TypeResult::Err
-    /// will be returned.
-    fn get_type(&self) -> TypeResult<'a, '_> {
-        /*
-        * Note:
-        * You'll see a lot of the following snippet:
-        * 
-        * match res {
-        *   TypeResult::Ok(t) => t,
-        *   err => return err,
-        * };
-        * 
-        * This checks if the typeresult is ok, and if not,
-        * returns the error. Simple as that.
-        */
+/// A parameter of a function, eg the `x: int` in `fn(x: int)`.
+/// Parameters without a type get theirs inferred.
+pub struct Param {
+    pub name: String,
+    pub annotation: Option<Annotation>,
+    pub pos: Position,
+}
+
+/// The parts of a function that declarations and lambdas share.
+pub struct Function {
+    pub params: Vec<Param>,
+    // Inferred from the body if it's left out
+    pub ret: Option<Annotation>,
+    pub body: Expr,
+}
+
+/// A field of a struct, eg the `x: float` in `struct Point { x: float }`.
+pub struct FieldDecl {
+    pub name: String,
+    pub annotation: Annotation,
+    pub pos: Position,
+}
+
+/// A method of a struct. `self` isn't one of the function's
+/// parameters, it gets filled in when the method is looked up.
+pub struct Method {
+    pub name: String,
+    pub function: Rc<Function>,
+    // Just `fn name`, like function declarations
+    pub pos: Position,
+}
+
+/// A struct declaration. `class` is another way to write `struct`.
+pub struct StructDecl {
+    pub name: String,
+    // In the order the constructor takes them
+    pub fields: Vec<FieldDecl>,
+    pub methods: Vec<Method>,
+}
+
+/// A name after a '.', eg the `x` in `point.x`.
+pub struct Member {
+    pub name: String,
+    pub pos: Position,
+}
+
+/// A variant of an enum, eg the `Rect(float, float)` in
+/// `enum Shape { Rect(float, float) }`.
+pub struct VariantDecl {
+    pub name: String,
+    // Types of what it holds, empty if it doesn't hold anything
+    pub fields: Vec<JType>,
+    pub pos: Position,
+}
+
+/// An enum declaration.
+pub struct EnumDecl {
+    pub name: String,
+    pub variants: Vec<VariantDecl>,
+}
+
+/// What a match arm compares the value against.
+pub enum Pattern {
+    // `_`, which matches anything
+    Wildcard(Position),
+    // Matches anything and gives it a name
+    Binding(Position, String),
+    Int(Position, i64),
+    Str(Position, String),
+    Char(Position, char),
+    Bool(Position, bool),
+    // Enum name, variant and patterns for what it holds,
+    // eg `Shape::Rect(w, _)`
+    Variant(Position, String, Member, Vec<Pattern>),
+}
+
+impl Pattern {
+    pub fn pos(&self) -> &Position {
         match self {
-            Self::Body(_, _) => TypeResult::Ok(JType::Void),
-            Self::Int(_, _) => TypeResult::Ok(JType::Int),
-            Self::Float(_, _) => TypeResult::Ok(JType::Float),
-            Self::Add(pos, left, right) => {
-                let left_type = match left.get_type() {
-                    TypeResult::Ok(t) => t,
-                    err => return err,
-                };
-                let right_type = match right.get_type() {
-                    TypeResult::Ok(t) => t,
-                    err => return err,
-                };
-                // If they're both numbers
-                if is_numeric(&left_type) && is_numeric(&right_type) {
-                    // If one is a float, the outcome will be a float. 
-                    // Otherwise it will be an integer.
-                    return if matches!(left_type, JType::Float) || matches!(right_type, JType::Float) {
-                        TypeResult::Ok(JType::Float)
-                    } else {
-                        TypeResult::Ok(JType::Int)
-                    };
-                }
-                // If it's not a found expression, crash!
-                TypeResult::Err(
-                    "TypeMismatch".to_string(), 
-                    format!("You cannot add '{}' and '{}'", left_type, right_type), 
-                    pos
-                )
-            },
-            Self::Sub(pos, left, right) => {
-                let left_type = match left.get_type() {
-                    TypeResult::Ok(t) => t,
-                    err => return err,
-                };
-                let right_type = match right.get_type() {
-                    TypeResult::Ok(t) => t,
-                    err => return err,
-                };
-                // If they're both numbers
-                if is_numeric(&left_type) && is_numeric(&right_type) {
-                    // If one is a float, the outcome will be a float. 
-                    // Otherwise it will be an integer.
-                    return if matches!(left_type, JType::Float) || matches!(right_type, JType::Float) {
-                        TypeResult::Ok(JType::Float)
-                    } else {
-                        TypeResult::Ok(JType::Int)
-                    };
-                }
-                // If it's not a found expression, crash!
-                TypeResult::Err(
-                    "TypeMismatch".to_string(), 
-                    format!("You cannot add '{}' and '{}'", left_type, right_type), 
-                    pos
-                )
-            },
-            Self::Mul(pos, left, right) => {
-                let left_type = match left.get_type() {
-                    TypeResult::Ok(t) => t,
-                    err => return err,
-                };
-                let right_type = match right.get_type() {
-                    TypeResult::Ok(t) => t,
-                    err => return err,
-                };
-                // If they're both numbers
-                if is_numeric(&left_type) && is_numeric(&right_type) {
-                    // If one is a float, the outcome will be a float. 
-                    // Otherwise it will be an integer.
-                    return if matches!(left_type, JType::Float) || matches!(right_type, JType::Float) {
-                        TypeResult::Ok(JType::Float)
-                    } else {
-                        TypeResult::Ok(JType::Int)
-                    };
-                }
-                // If it's not a found expression, crash!
-                TypeResult::Err(
-                    "TypeMismatch".to_string(), 
-                    format!("You cannot add '{}' and '{}'", left_type, right_type), 
-                    pos
-                )
-            },
-            Self::Div(pos, left, right) => {
-                let left_type = match left.get_type() {
-                    TypeResult::Ok(t) => t,
-                    err => return err,
-                };
-                let right_type = match right.get_type() {
-                    TypeResult::Ok(t) => t,
-                    err => return err,
-                };
-                // If they're both numbers
-                if is_numeric(&left_type) && is_numeric(&right_type) {
-                    // If one is a float, the outcome will be a float. 
-                    // Otherwise it will be an integer.
-                    return if matches!(left_type, JType::Float) || matches!(right_type, JType::Float) {
-                        TypeResult::Ok(JType::Float)
-                    } else {
-                        TypeResult::Ok(JType::Int)
-                    };
+            Self::Wildcard(pos) => pos,
+            Self::Binding(pos, _) => pos,
+            Self::Int(pos, _) => pos,
+            Self::Str(pos, _) => pos,
+            Self::Char(pos, _) => pos,
+            Self::Bool(pos, _) => pos,
+            Self::Variant(pos, _, _, _) => pos,
+        }
+    }
+}
+
+impl Display for Pattern {
+    // Written the way it is in the code
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wildcard(_) => write!(f, "_"),
+            Self::Binding(_, name) => write!(f, "{}", name),
+            Self::Int(_, n) => write!(f, "{}", n),
+            Self::Str(_, s) => write!(f, "{:?}", s),
+            Self::Char(_, c) => write!(f, "{:?}", c),
+            Self::Bool(_, b) => write!(f, "{}", b),
+            Self::Variant(_, name, variant, fields) => {
+                write!(f, "{}::{}", name, variant.name)?;
+                if !fields.is_empty() {
+                    write!(f, "(")?;
+                    for (i, field) in fields.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}", field)?;
+                    }
+                    write!(f, ")")?;
                 }
-                // If it's not a found expression, crash!
-                TypeResult::Err(
-                    "TypeMismatch".to_string(), 
-                    format!("You cannot add '{}' and '{}'", left_type, right_type), 
-                    pos
-                )
+                Ok(())
             },
         }
     }
+}
+
+/// One arm of a match, eg `Shape::Circle(r) if r > 0.0 => r * r`.
+pub struct Arm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Expr,
+}
+
+// Enum for each expression in the tree
+pub enum Expr {
+    // Statements
+    Body(Position, Vec<Expr>),
+    Let(Position, String, Option<Annotation>, Box<Expr>),
+    // Variable and its new value, `x += 1` is stored as `x = x + 1`
+    Assign(Position, String, Box<Expr>),
+
+    // Control flow
+    // Condition, then branch and the optional else branch
+    If(Position, Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+    // Condition and body
+    While(Position, Option<LoopLabel>, Box<Expr>, Box<Expr>),
+    // Variable, start and end of the range, and body
+    For(Position, Option<LoopLabel>, String, Box<Expr>, Box<Expr>, Box<Expr>),
+    Break(Position, Option<LoopLabel>),
+    Continue(Position, Option<LoopLabel>),
+
+    // Functions
+    // Shared with Rc so closures can hold on to them while running
+    Fn(Position, String, Rc<Function>),
+    Lambda(Position, Rc<Function>),
+    Call(Position, Box<Expr>, Vec<Expr>),
+    Return(Position, Option<Box<Expr>>),
+
+    // Structs
+    Struct(Position, Rc<StructDecl>),
+    Field(Position, Box<Expr>, Member),
+
+    // Enums
+    Enum(Position, Rc<EnumDecl>),
+    // Enum name and variant, eg `Shape::Circle`
+    Variant(Position, String, Member),
+    // The value being matched and the arms, in order
+    Match(Position, Box<Expr>, Vec<Arm>),
+
+    // Modules
+    // The module, and the names taken out of it for `from m import a, b`
+    Import(Position, Member, Option<Vec<Member>>),
+    // A declaration other modules can import
+    Export(Position, Box<Expr>),
+
+    // Literals
+    Int(Position, i64),
+    Float(Position, f64),
+    Str(Position, String),
+    Char(Position, char),
+    Bool(Position, bool),
+    Null(Position),
+    List(Position, Vec<Expr>),
+    Tuple(Position, Vec<Expr>),
+    // Keys and values in the order they're written
+    Map(Position, Vec<(Expr, Expr)>),
+
+    // Variables
+    Var(Position, String),
+
+    // Unary operations
+    Neg(Position, Box<Expr>),
+    Not(Position, Box<Expr>),
+
+    // Binary operations
+    Add(Position, Box<Expr>, Box<Expr>),
+    Sub(Position, Box<Expr>, Box<Expr>),
+    Mul(Position, Box<Expr>, Box<Expr>),
+    Div(Position, Box<Expr>, Box<Expr>),
 
+    // Comparisons
+    Eq(Position, Box<Expr>, Box<Expr>),
+    Ne(Position, Box<Expr>, Box<Expr>),
+    Lt(Position, Box<Expr>, Box<Expr>),
+    Le(Position, Box<Expr>, Box<Expr>),
+    Gt(Position, Box<Expr>, Box<Expr>),
+    Ge(Position, Box<Expr>, Box<Expr>),
+
+    // Logical operations, the right side only runs if it's needed
+    And(Position, Box<Expr>, Box<Expr>),
+    Or(Position, Box<Expr>, Box<Expr>),
+    // `a ?? b`, the right side only runs if the left is null
+    Coalesce(Position, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
     /// Returns the position of the expression.
-    fn pos(&self) -> &Position<'a> {
+    pub fn pos(&self) -> &Position {
         // Literally just arms for every variant to get the position
         match self {
             Self::Body(pos, _) => pos,
+            Self::Let(pos, _, _, _) => pos,
+            Self::Assign(pos, _, _) => pos,
+            Self::If(pos, _, _, _) => pos,
+            Self::While(pos, _, _, _) => pos,
+            Self::For(pos, _, _, _, _, _) => pos,
+            Self::Break(pos, _) => pos,
+            Self::Continue(pos, _) => pos,
+            Self::Fn(pos, _, _) => pos,
+            Self::Lambda(pos, _) => pos,
+            Self::Call(pos, _, _) => pos,
+            Self::Return(pos, _) => pos,
+            Self::Struct(pos, _) => pos,
+            Self::Field(pos, _, _) => pos,
+            Self::Enum(pos, _) => pos,
+            Self::Variant(pos, _, _) => pos,
+            Self::Match(pos, _, _) => pos,
+            Self::Import(pos, _, _) => pos,
+            Self::Export(pos, _) => pos,
 
             // Literals
             Self::Float(pos, _) => pos,
             Self::Int(pos, _) => pos,
+            Self::Str(pos, _) => pos,
+            Self::Char(pos, _) => pos,
+            Self::Bool(pos, _) => pos,
+            Self::Null(pos) => pos,
+            Self::List(pos, _) => pos,
+            Self::Tuple(pos, _) => pos,
+            Self::Map(pos, _) => pos,
+            Self::Var(pos, _) => pos,
 
             // Operations
+            Self::Neg(pos, _) => pos,
+            Self::Not(pos, _) => pos,
             Self::Add(pos, _, _) => pos,
             Self::Sub(pos, _, _) => pos,
             Self::Mul(pos, _, _) => pos,
             Self::Div(pos, _, _) => pos,
+            Self::Eq(pos, _, _) => pos,
+            Self::Ne(pos, _, _) => pos,
+            Self::Lt(pos, _, _) => pos,
+            Self::Le(pos, _, _) => pos,
+            Self::Gt(pos, _, _) => pos,
+            Self::Ge(pos, _, _) => pos,
+            Self::And(pos, _, _) => pos,
+            Self::Or(pos, _, _) => pos,
+            Self::Coalesce(pos, _, _) => pos,
+        }
+    }
+}
+
+/// Writes `name: ` in front of a labelled loop.
+fn write_label(f: &mut Formatter<'_>, label: &Option<LoopLabel>) -> fmt::Result {
+    match label {
+        Some(label) => write!(f, "{}: ", label.name),
+        None => Ok(()),
+    }
+}
+
+impl Expr {
+    /// Returns true if running the expression always jumps
+    /// somewhere else instead of finishing, like a return.
+    /// Branches like that don't need to give a value.
+    pub fn diverges(&self) -> bool {
+        match self {
+            Self::Return(_, _) | Self::Break(_, _) | Self::Continue(_, _) => true,
+            Self::Body(_, exprs) => exprs.iter().any(|e| e.diverges()),
+            Self::If(_, _, then, Some(otherwise)) => then.diverges() && otherwise.diverges(),
+            Self::Let(_, _, _, value) | Self::Assign(_, _, value) => value.diverges(),
+            Self::Match(_, _, arms) => !arms.is_empty() && arms.iter().all(|arm| arm.body.diverges()),
+            _ => false,
+        }
+    }
+
+    /// Returns what an export exports, or the expression itself otherwise.
+    pub fn unexported(&self) -> &Expr {
+        match self {
+            Self::Export(_, decl) => decl,
+            _ => self,
+        }
+    }
+
+    /// Returns the name a declaration declares,
+    /// or None if the expression isn't one.
+    pub fn declared_name(&self) -> Option<&str> {
+        match self {
+            Self::Let(_, name, _, _) | Self::Fn(_, name, _) => Some(name),
+            Self::Struct(_, decl) => Some(&decl.name),
+            Self::Enum(_, decl) => Some(&decl.name),
+            Self::Export(_, decl) => decl.declared_name(),
+            _ => None,
+        }
+    }
+}
+
+/// Writes a function's parameters, return type and body.
+fn write_function(f: &mut Formatter<'_>, function: &Function) -> fmt::Result {
+    write!(f, "(")?;
+    for (i, param) in function.params.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        match &param.annotation {
+            Some(annotation) => write!(f, "{}: {}", param.name, annotation.t)?,
+            None => write!(f, "{}", param.name)?,
+        }
+    }
+    write!(f, ")")?;
+    if let Some(ret) = &function.ret {
+        write!(f, " -> {}", ret.t)?;
+    }
+    write!(f, " {})", function.body)
+}
+
+impl Display for Expr {
+    // Writes the tree as an s-expression, eg (+ 1 (* 2 3))
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Body(_, exprs) => {
+                write!(f, "(body")?;
+                for expr in exprs {
+                    write!(f, " {}", expr)?;
+                }
+                write!(f, ")")
+            },
+            Self::Let(_, name, Some(annotation), value) => write!(f, "(let {}: {} {})", name, annotation.t, value),
+            Self::Let(_, name, None, value) => write!(f, "(let {} {})", name, value),
+            Self::Assign(_, name, value) => write!(f, "(= {} {})", name, value),
+            Self::If(_, condition, then, Some(otherwise)) => write!(f, "(if {} {} {})", condition, then, otherwise),
+            Self::If(_, condition, then, None) => write!(f, "(if {} {})", condition, then),
+            Self::While(_, label, condition, body) => {
+                write!(f, "(")?;
+                write_label(f, label)?;
+                write!(f, "while {} {})", condition, body)
+            },
+            Self::For(_, label, name, start, end, body) => {
+                write!(f, "(")?;
+                write_label(f, label)?;
+                write!(f, "for {} {} {} {})", name, start, end, body)
+            },
+            Self::Break(_, Some(label)) => write!(f, "(break {})", label.name),
+            Self::Break(_, None) => write!(f, "(break)"),
+            Self::Continue(_, Some(label)) => write!(f, "(continue {})", label.name),
+            Self::Continue(_, None) => write!(f, "(continue)"),
+            Self::Fn(_, name, function) => {
+                write!(f, "(fn {} ", name)?;
+                write_function(f, function)
+            },
+            Self::Lambda(_, function) => {
+                write!(f, "(fn ")?;
+                write_function(f, function)
+            },
+            Self::Call(_, callee, args) => {
+                write!(f, "(call {}", callee)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, ")")
+            },
+            Self::Return(_, Some(value)) => write!(f, "(return {})", value),
+            Self::Return(_, None) => write!(f, "(return)"),
+            Self::Struct(_, decl) => {
+                write!(f, "(struct {}", decl.name)?;
+                for field in &decl.fields {
+                    write!(f, " ({}: {})", field.name, field.annotation.t)?;
+                }
+                for method in &decl.methods {
+                    write!(f, " (fn {} ", method.name)?;
+                    write_function(f, &method.function)?;
+                }
+                write!(f, ")")
+            },
+            Self::Field(_, object, member) => write!(f, "(. {} {})", object, member.name),
+            Self::Enum(_, decl) => {
+                write!(f, "(enum {}", decl.name)?;
+                for variant in &decl.variants {
+                    if variant.fields.is_empty() {
+                        write!(f, " {}", variant.name)?;
+                        continue;
+                    }
+                    write!(f, " ({}", variant.name)?;
+                    for field in &variant.fields {
+                        write!(f, " {}", field)?;
+                    }
+                    write!(f, ")")?;
+                }
+                write!(f, ")")
+            },
+            Self::Variant(_, name, variant) => write!(f, "{}::{}", name, variant.name),
+            Self::Match(_, value, arms) => {
+                write!(f, "(match {}", value)?;
+                for arm in arms {
+                    match &arm.guard {
+                        Some(guard) => write!(f, " ({} if {} {})", arm.pattern, guard, arm.body)?,
+                        None => write!(f, " ({} {})", arm.pattern, arm.body)?,
+                    }
+                }
+                write!(f, ")")
+            },
+            Self::Import(_, module, None) => write!(f, "(import {})", module.name),
+            Self::Import(_, module, Some(names)) => {
+                write!(f, "(from {} import", module.name)?;
+                for name in names {
+                    write!(f, " {}", name.name)?;
+                }
+                write!(f, ")")
+            },
+            Self::Export(_, decl) => write!(f, "(export {})", decl),
+            Self::Int(_, n) => write!(f, "{}", n),
+            Self::Var(_, name) => write!(f, "{}", name),
+            Self::Float(_, n) => write!(f, "{}f", n),
+            Self::Str(_, s) => write!(f, "{:?}", s),
+            Self::Char(_, c) => write!(f, "{:?}", c),
+            Self::Bool(_, b) => write!(f, "{}", b),
+            Self::Null(_) => write!(f, "null"),
+            Self::List(_, items) => {
+                write!(f, "(list")?;
+                for item in items {
+                    write!(f, " {}", item)?;
+                }
+                write!(f, ")")
+            },
+            Self::Tuple(_, items) => {
+                write!(f, "(tuple")?;
+                for item in items {
+                    write!(f, " {}", item)?;
+                }
+                write!(f, ")")
+            },
+            Self::Map(_, entries) => {
+                write!(f, "(map")?;
+                for (key, value) in entries {
+                    write!(f, " ({} {})", key, value)?;
+                }
+                write!(f, ")")
+            },
+            Self::Neg(_, operand) => write!(f, "(- {})", operand),
+            Self::Not(_, operand) => write!(f, "(! {})", operand),
+            Self::Add(_, left, right) => write!(f, "(+ {} {})", left, right),
+            Self::Sub(_, left, right) => write!(f, "(- {} {})", left, right),
+            Self::Mul(_, left, right) => write!(f, "(* {} {})", left, right),
+            Self::Div(_, left, right) => write!(f, "(/ {} {})", left, right),
+            Self::Eq(_, left, right) => write!(f, "(== {} {})", left, right),
+            Self::Ne(_, left, right) => write!(f, "(!= {} {})", left, right),
+            Self::Lt(_, left, right) => write!(f, "(< {} {})", left, right),
+            Self::Le(_, left, right) => write!(f, "(<= {} {})", left, right),
+            Self::Gt(_, left, right) => write!(f, "(> {} {})", left, right),
+            Self::Ge(_, left, right) => write!(f, "(>= {} {})", left, right),
+            Self::And(_, left, right) => write!(f, "(&& {} {})", left, right),
+            Self::Or(_, left, right) => write!(f, "(|| {} {})", left, right),
+            Self::Coalesce(_, left, right) => write!(f, "(?? {} {})", left, right),
         }
     }
 }
@@ -1,2 +1,6 @@
 
 pub mod expr;
+pub mod result;
+#[allow(clippy::module_inception)]
+mod parser;
+pub use parser::parse;
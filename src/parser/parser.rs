@@ -0,0 +1,1393 @@
+
+use crate::utils::{ codes, diagnostic::Diagnostic };
+use super::expr::{ Annotation, Arm, EnumDecl, Expr, FieldDecl, Function, LoopLabel, Member, Method, Param, Pattern, StructDecl, VariantDecl };
+use crate::types::JType;
+use super::result::ParseResult;
+use crate::lexer::token::{ MakeToken, Token, Position };
+use crate::lexer::source::{ FileId, SourceFile };
+use std::rc::Rc;
+
+/// Binding power of prefix operators.
+/// Higher than every binary operator so that
+/// `-a * b` parses as `(-a) * b`.
+const PREFIX_POWER: u8 = 30;
+
+/// Binding power of calls and field access, which bind
+/// tighter than anything else so `-f(x)` is `-(f(x))`.
+const CALL_POWER: u8 = 40;
+
+/// Binding power of assignments, which bind looser than
+/// anything else so `x = a || b` assigns `a || b`.
+const ASSIGN_POWER: u8 = 1;
+
+/// Builds a binary operation from its position and operands, eg Expr::Add.
+type MakeBinary = fn(Position, Box<Expr>, Box<Expr>) -> Expr;
+
+struct Parser<'a> {
+    file: FileId,
+    // Only needed to tell which line a token is on
+    source: &'a SourceFile,
+    tokens: Vec<Token<'a>>,
+    index: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a SourceFile, tokens: Vec<Token<'a>>) -> Self {
+        Self {
+            file: source.id(),
+            source,
+            tokens,
+            index: 0,
+        }
+    }
+
+    /// Returns the current token without consuming it.
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.index)
+    }
+
+    /// Steps to the next token.
+    fn advance(&mut self) {
+        self.index += 1;
+    }
+
+    /// Returns an empty position right after the last token.
+    /// Used for errors where the input ends too early.
+    fn end_pos(&self) -> Position {
+        match self.tokens.last() {
+            Some(token) => Position::new(self.file, token.pos().end(), token.pos().end()),
+            None => Position::new(self.file, 0, 0),
+        }
+    }
+
+    /// Returns the left and right binding power of a binary operator,
+    /// or None if the token isn't one.
+    /// A right power higher than the left power makes the operator
+    /// left associative, so `1 - 2 - 3` is `(1 - 2) - 3`.
+    fn infix_power(token: &Token<'a>) -> Option<(u8, u8)> {
+        match token {
+            Token::OrOr(_) => Some((2, 3)),
+            Token::AndAnd(_) => Some((4, 5)),
+            Token::EqEq(_) | Token::BangEq(_) => Some((6, 7)),
+            Token::Less(_) | Token::LessEq(_) | Token::Greater(_) | Token::GreaterEq(_) => Some((8, 9)),
+            // Equal powers make it right associative, so `a ?? b ?? c` is `a ?? (b ?? c)`
+            Token::QuestionQuestion(_) => Some((9, 9)),
+            Token::Plus(_) | Token::Minus(_) => Some((10, 11)),
+            Token::Star(_) | Token::Slash(_) => Some((20, 21)),
+            _ => None,
+        }
+    }
+
+    /// Parses a literal, a prefix operation or a parenthesized expression.
+    fn atom(&mut self) -> ParseResult<Expr> {
+        let token = match self.peek() {
+            Some(token) => token,
+            None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                "Expected an expression.", self.end_pos())),
+        };
+        match *token {
+            Token::Int(pos, n) => {
+                self.advance();
+                ParseResult::Ok(Expr::Int(pos, n))
+            },
+            Token::Float(pos, n) => {
+                self.advance();
+                ParseResult::Ok(Expr::Float(pos, n))
+            },
+            Token::Str(pos, ref s) => {
+                let s = s.to_string();
+                self.advance();
+                ParseResult::Ok(Expr::Str(pos, s))
+            },
+            Token::Char(pos, c) => {
+                self.advance();
+                ParseResult::Ok(Expr::Char(pos, c))
+            },
+            Token::Keyword(pos, keyword @ ("true" | "false")) => {
+                self.advance();
+                ParseResult::Ok(Expr::Bool(pos, keyword == "true"))
+            },
+            Token::Keyword(pos, "null") => {
+                self.advance();
+                ParseResult::Ok(Expr::Null(pos))
+            },
+            Token::Keyword(pos, "if") => self.if_expr(pos),
+            Token::Keyword(pos, "match") => self.match_expr(pos),
+            Token::Keyword(pos, "while") => self.while_loop(pos, None),
+            Token::Keyword(pos, "for") => self.for_loop(pos, None),
+            Token::Keyword(pos, keyword @ ("break" | "continue")) => self.jump(pos, keyword == "break"),
+            Token::Keyword(pos, "fn") => {
+                self.advance();
+                match self.function() {
+                    ParseResult::Ok(function) => ParseResult::Ok(Expr::Lambda(pos.extend(function.body.pos()), Rc::new(function))),
+                    ParseResult::Err(e) => ParseResult::Err(e),
+                }
+            },
+            Token::Keyword(pos, "return") => {
+                self.advance();
+                // A return at the end of a block or before
+                // a declaration doesn't have a value
+                match self.peek() {
+                    None | Some(Token::RBrace(_)) | Some(Token::Keyword(_, "let")) => ParseResult::Ok(Expr::Return(pos, None)),
+                    Some(_) => match self.expr(0) {
+                        ParseResult::Ok(value) => ParseResult::Ok(Expr::Return(pos.extend(value.pos()), Some(Box::new(value)))),
+                        err => err,
+                    },
+                }
+            },
+            // The top level handles these before getting here
+            Token::Keyword(pos, "import" | "from") => ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                "Imports can only be at the top level of a file.", pos)),
+            Token::Keyword(pos, "export") => ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                "Only top level declarations can be exported.", pos)),
+            // A labelled loop, eg `outer: while ...`
+            Token::Identifier(pos, name) if matches!((self.tokens.get(self.index + 1), self.tokens.get(self.index + 2)),
+                (Some(Token::Colon(_)), Some(Token::Keyword(_, "while" | "for")))) => {
+                let label = LoopLabel { name: name.to_string(), pos };
+                self.advance();
+                self.advance();
+                match self.peek() {
+                    Some(Token::Keyword(_, "while")) => self.while_loop(pos, Some(label)),
+                    _ => self.for_loop(pos, Some(label)),
+                }
+            },
+            Token::Identifier(_, _) if matches!(self.tokens.get(self.index + 1), Some(Token::ColonColon(_))) => {
+                match self.path() {
+                    ParseResult::Ok((pos, name, variant)) => ParseResult::Ok(Expr::Variant(pos, name, variant)),
+                    ParseResult::Err(e) => ParseResult::Err(e),
+                }
+            },
+            Token::Identifier(pos, ref name) => {
+                let name = name.to_string();
+                self.advance();
+                ParseResult::Ok(Expr::Var(pos, name))
+            },
+            Token::Minus(pos) | Token::Bang(pos) => {
+                let make = if let Token::Minus(_) = token { Expr::Neg } else { Expr::Not };
+                self.advance();
+                let operand = match self.expr(PREFIX_POWER) {
+                    ParseResult::Ok(e) => e,
+                    err => return err,
+                };
+                ParseResult::Ok(make(pos.extend(operand.pos()), Box::new(operand)))
+            },
+            Token::LBracket(pos) => self.list(pos),
+            Token::LParen(pos) => self.parenthesized(pos),
+            _ => ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Expected an expression but found '{}'.", token), *token.pos())),
+        }
+    }
+
+    /// Parses statements in braces, eg the body of a loop.
+    fn block(&mut self) -> ParseResult<Expr> {
+        let start = match self.consume("{", |t| matches!(t, Token::LBrace(_))) {
+            ParseResult::Ok(pos) => pos,
+            ParseResult::Err(e) => return ParseResult::Err(e),
+        };
+        let mut exprs = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::RBrace(end)) => {
+                    let pos = start.extend(end);
+                    self.advance();
+                    return ParseResult::Ok(Expr::Body(pos, exprs));
+                },
+                Some(Token::DocComment(_, _)) => self.advance(),
+                Some(_) => match self.statement() {
+                    ParseResult::Ok(e) => exprs.push(e),
+                    ParseResult::Err(e) => return ParseResult::Err(e),
+                },
+                None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                    "Expected '}' to close '{'.", start)),
+            }
+        }
+    }
+
+    /// Parses an if expression, eg `if a < b { a } else { b }`.
+    /// Assumes the current token is the `if` keyword.
+    fn if_expr(&mut self, start: Position) -> ParseResult<Expr> {
+        self.advance();
+        let condition = match self.expr(0) {
+            ParseResult::Ok(e) => e,
+            err => return err,
+        };
+        let then = match self.block() {
+            ParseResult::Ok(e) => e,
+            err => return err,
+        };
+        let otherwise = match self.peek() {
+            Some(Token::Keyword(_, "else")) => {
+                self.advance();
+                // `else if` chains another if instead of needing braces around it
+                let otherwise = match self.peek() {
+                    Some(Token::Keyword(pos, "if")) => {
+                        let pos = *pos;
+                        self.if_expr(pos)
+                    },
+                    _ => self.block(),
+                };
+                match otherwise {
+                    ParseResult::Ok(e) => Some(Box::new(e)),
+                    err => return err,
+                }
+            },
+            _ => None,
+        };
+        let end = match &otherwise {
+            Some(otherwise) => *otherwise.pos(),
+            None => *then.pos(),
+        };
+        ParseResult::Ok(Expr::If(start.extend(&end), Box::new(condition), Box::new(then), otherwise))
+    }
+
+    /// Parses a while loop, eg `while x < 10 { ... }`.
+    /// Assumes the current token is the `while` keyword.
+    fn while_loop(&mut self, start: Position, label: Option<LoopLabel>) -> ParseResult<Expr> {
+        self.advance();
+        let condition = match self.expr(0) {
+            ParseResult::Ok(e) => e,
+            err => return err,
+        };
+        let body = match self.block() {
+            ParseResult::Ok(e) => e,
+            err => return err,
+        };
+        ParseResult::Ok(Expr::While(start.extend(body.pos()), label, Box::new(condition), Box::new(body)))
+    }
+
+    /// Parses a for loop over a range, eg `for i in 0..10 { ... }`.
+    /// The range includes its start but not its end.
+    /// Assumes the current token is the `for` keyword.
+    fn for_loop(&mut self, start: Position, label: Option<LoopLabel>) -> ParseResult<Expr> {
+        self.advance();
+        let name = match self.peek() {
+            Some(Token::Identifier(_, name)) => name.to_string(),
+            Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Expected a variable name but found '{}'.", token), *token.pos())),
+            None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                "Expected a variable name.", self.end_pos())),
+        };
+        self.advance();
+        if let ParseResult::Err(e) = self.consume("in", |t| matches!(t, Token::Keyword(_, "in"))) {
+            return ParseResult::Err(e);
+        }
+        let from = match self.expr(0) {
+            ParseResult::Ok(e) => e,
+            err => return err,
+        };
+        if let ParseResult::Err(e) = self.consume("..", |t| matches!(t, Token::DotDot(_))) {
+            return ParseResult::Err(e.with_help("Loops go over a range of ints, eg 'for i in 0..10'."));
+        }
+        let to = match self.expr(0) {
+            ParseResult::Ok(e) => e,
+            err => return err,
+        };
+        let body = match self.block() {
+            ParseResult::Ok(e) => e,
+            err => return err,
+        };
+        ParseResult::Ok(Expr::For(start.extend(body.pos()), label, name, Box::new(from), Box::new(to), Box::new(body)))
+    }
+
+    /// Parses an expression in parentheses or a tuple, eg `(1 + 2)`,
+    /// `(1, 'a')` or `()`. Only a comma makes a tuple, so a tuple
+    /// of one is written `(1,)`.
+    /// Assumes the current token is the '('.
+    fn parenthesized(&mut self, open: Position) -> ParseResult<Expr> {
+        self.advance();
+        let mut items = Vec::new();
+        let mut tuple = false;
+        loop {
+            if let Some(Token::RParen(end)) = self.peek() {
+                let pos = open.extend(end);
+                self.advance();
+                return ParseResult::Ok(if tuple || items.is_empty() { Expr::Tuple(pos, items) } else { items.remove(0) });
+            }
+            match self.expr(0) {
+                ParseResult::Ok(item) => items.push(item),
+                err => return err,
+            }
+            match self.peek() {
+                Some(Token::Comma(_)) => {
+                    tuple = true;
+                    self.advance();
+                },
+                Some(Token::RParen(_)) => {},
+                Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                    format!("Expected ',' or ')' but found '{}'.", token), *token.pos())
+                    .with_label(open, "This '(' is never closed")),
+                None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                    "Expected ')' to close '('.", open)),
+            }
+        }
+    }
+
+    /// Parses a list or a map, eg `[1, 2, 3]` or `["a": 1, "b": 2]`.
+    /// A ':' after the first item makes it a map, and `[:]` is an empty one.
+    /// Assumes the current token is the '['.
+    fn list(&mut self, open: Position) -> ParseResult<Expr> {
+        self.advance();
+        if let (Some(Token::Colon(_)), Some(Token::RBracket(end))) = (self.peek(), self.tokens.get(self.index + 1)) {
+            let pos = open.extend(end);
+            self.advance();
+            self.advance();
+            return ParseResult::Ok(Expr::Map(pos, Vec::new()));
+        }
+        let mut items = Vec::new();
+        // Values of a map, which go with the items as keys
+        let mut values = Vec::new();
+        let mut is_map = false;
+        loop {
+            if let Some(Token::RBracket(end)) = self.peek() {
+                let pos = open.extend(end);
+                self.advance();
+                return ParseResult::Ok(if is_map {
+                    Expr::Map(pos, items.into_iter().zip(values).collect())
+                } else {
+                    Expr::List(pos, items)
+                });
+            }
+            match self.expr(0) {
+                ParseResult::Ok(item) => items.push(item),
+                err => return err,
+            }
+            if items.len() == 1 {
+                is_map = matches!(self.peek(), Some(Token::Colon(_)));
+            }
+            if is_map {
+                if let ParseResult::Err(e) = self.consume(":", |t| matches!(t, Token::Colon(_))) {
+                    return ParseResult::Err(e.with_label(open, "Every key in this map needs a value"));
+                }
+                match self.expr(0) {
+                    ParseResult::Ok(value) => values.push(value),
+                    err => return err,
+                }
+            }
+            match self.peek() {
+                Some(Token::Comma(_)) => self.advance(),
+                Some(Token::RBracket(_)) => {},
+                Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                    format!("Expected ',' or ']' but found '{}'.", token), *token.pos())
+                    .with_label(open, "This '[' is never closed")),
+                None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                    "Expected ']' to close '['.", open)),
+            }
+        }
+    }
+
+    /// Parses the arguments of a call to the given expression.
+    /// Assumes the current token is the '('.
+    fn call(&mut self, callee: Expr) -> ParseResult<Expr> {
+        let open = *self.peek().expect("called on a '('").pos();
+        self.advance();
+        let mut args = Vec::new();
+        loop {
+            if let Some(Token::RParen(end)) = self.peek() {
+                let pos = callee.pos().extend(end);
+                self.advance();
+                return ParseResult::Ok(Expr::Call(pos, Box::new(callee), args));
+            }
+            match self.expr(0) {
+                ParseResult::Ok(arg) => args.push(arg),
+                err => return err,
+            }
+            match self.peek() {
+                Some(Token::Comma(_)) => self.advance(),
+                Some(Token::RParen(_)) => {},
+                Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                    format!("Expected ',' or ')' but found '{}'.", token), *token.pos())
+                    .with_label(open, "This '(' is never closed")),
+                None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                    "Expected ')' to close '('.", open)),
+            }
+        }
+    }
+
+    /// Parses the name after a '.'.
+    /// Assumes the current token is the '.'.
+    fn field(&mut self, object: Expr) -> ParseResult<Expr> {
+        self.advance();
+        let member = match self.peek() {
+            Some(Token::Identifier(pos, name)) => Member { name: name.to_string(), pos: *pos },
+            // Tuples are indexed by number, eg `pair.0`
+            Some(Token::Int(pos, n)) => Member { name: n.to_string(), pos: *pos },
+            // `pair.0.1` lexes the `0.1` as a float
+            Some(Token::Float(pos, _)) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                "Expected a field name after '.' but found a float.", *pos)
+                .with_help("Put the first part in parentheses, eg '(pair.0).1'.")),
+            Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Expected a field name after '.' but found '{}'.", token), *token.pos())),
+            None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                "Expected a field name after '.'.", self.end_pos())),
+        };
+        self.advance();
+        ParseResult::Ok(Expr::Field(object.pos().extend(&member.pos), Box::new(object), member))
+    }
+
+    /// Parses a function's parameters, return type and body,
+    /// eg `(a: int, b) -> int { a + b }`.
+    fn function(&mut self) -> ParseResult<Function> {
+        let open = match self.consume("(", |t| matches!(t, Token::LParen(_))) {
+            ParseResult::Ok(pos) => pos,
+            ParseResult::Err(e) => return ParseResult::Err(e),
+        };
+        let mut params = Vec::new();
+        loop {
+            let (pos, name) = match self.peek() {
+                Some(Token::RParen(_)) => {
+                    self.advance();
+                    break;
+                },
+                Some(Token::Identifier(pos, name)) => (*pos, name.to_string()),
+                Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                    format!("Expected a parameter name but found '{}'.", token), *token.pos())),
+                None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                    "Expected ')' to close '('.", open)),
+            };
+            self.advance();
+            // Like variables, the type can be left out to have it inferred
+            let annotation = match self.peek() {
+                Some(Token::Colon(_)) => {
+                    self.advance();
+                    match self.annotation() {
+                        ParseResult::Ok(annotation) => Some(annotation),
+                        ParseResult::Err(e) => return ParseResult::Err(e),
+                    }
+                },
+                _ => None,
+            };
+            params.push(Param { name, annotation, pos });
+            match self.peek() {
+                Some(Token::Comma(_)) => self.advance(),
+                Some(Token::RParen(_)) => {},
+                Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                    format!("Expected ',' or ')' but found '{}'.", token), *token.pos())
+                    .with_label(open, "This '(' is never closed")),
+                None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                    "Expected ')' to close '('.", open)),
+            }
+        }
+        let ret = match self.peek() {
+            Some(Token::Arrow(_)) => {
+                self.advance();
+                match self.annotation() {
+                    ParseResult::Ok(annotation) => Some(annotation),
+                    ParseResult::Err(e) => return ParseResult::Err(e),
+                }
+            },
+            _ => None,
+        };
+        match self.block() {
+            ParseResult::Ok(body) => ParseResult::Ok(Function { params, ret, body }),
+            ParseResult::Err(e) => ParseResult::Err(e),
+        }
+    }
+
+    /// Parses a function declaration, eg `fn add(a: int, b: int) -> int { a + b }`.
+    /// Assumes the current token is the `fn` keyword.
+    fn fn_declaration(&mut self, start: Position) -> ParseResult<Expr> {
+        self.advance();
+        let (pos, name) = match self.peek() {
+            Some(Token::Identifier(pos, name)) => (start.extend(pos), name.to_string()),
+            _ => unreachable!("statement checks for the name"),
+        };
+        self.advance();
+        // Only `fn name` is kept as the position, since
+        // errors pointing at the whole function would be huge
+        match self.function() {
+            ParseResult::Ok(function) => ParseResult::Ok(Expr::Fn(pos, name, Rc::new(function))),
+            ParseResult::Err(e) => ParseResult::Err(e),
+        }
+    }
+
+    /// Parses a method inside a struct. The first parameter
+    /// has to be `self`, which gets taken out of the parameters.
+    /// Assumes the current token is the `fn` keyword.
+    fn method(&mut self, start: Position) -> ParseResult<Method> {
+        self.advance();
+        let (pos, name) = match self.peek() {
+            Some(Token::Identifier(pos, name)) => (start.extend(pos), name.to_string()),
+            Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Expected a method name but found '{}'.", token), *token.pos())),
+            None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                "Expected a method name.", self.end_pos())),
+        };
+        self.advance();
+        let mut function = match self.function() {
+            ParseResult::Ok(function) => function,
+            ParseResult::Err(e) => return ParseResult::Err(e),
+        };
+        match function.params.first() {
+            Some(param) if param.name == "self" && param.annotation.is_none() => {
+                function.params.remove(0);
+                ParseResult::Ok(Method { name, function: Rc::new(function), pos })
+            },
+            Some(param) if param.name == "self" => ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                "'self' can't have a type written out.", param.pos)
+                .with_help("It's always the struct the method is in.")),
+            _ => ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Method '{}' needs 'self' as its first parameter.", name), pos)
+                .with_help("Functions that don't need an instance can be declared outside the struct.")),
+        }
+    }
+
+    /// Parses a struct declaration, eg
+    /// `struct Point { x: float y: float fn len(self) -> float { ... } }`.
+    /// Fields can be separated by commas, but don't have to be.
+    /// Assumes the current token is the `struct` or `class` keyword.
+    fn struct_declaration(&mut self, start: Position) -> ParseResult<Expr> {
+        self.advance();
+        let (pos, name) = match self.peek() {
+            Some(Token::Identifier(pos, name)) => (start.extend(pos), name.to_string()),
+            Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Expected a struct name but found '{}'.", token), *token.pos())),
+            None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                "Expected a struct name.", self.end_pos())),
+        };
+        // Types are told apart from other names by the capital
+        if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+            return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Struct names have to start with a capital letter, but found '{}'.", name), pos));
+        }
+        self.advance();
+        let open = match self.consume("{", |t| matches!(t, Token::LBrace(_))) {
+            ParseResult::Ok(pos) => pos,
+            ParseResult::Err(e) => return ParseResult::Err(e),
+        };
+        let mut decl = StructDecl { name, fields: Vec::new(), methods: Vec::new() };
+        loop {
+            match self.peek() {
+                Some(Token::RBrace(_)) => {
+                    self.advance();
+                    break;
+                },
+                // Nothing keeps doc comments yet
+                Some(Token::DocComment(_, _)) | Some(Token::Comma(_)) => self.advance(),
+                Some(Token::Keyword(pos, "fn")) => {
+                    let pos = *pos;
+                    match self.method(pos) {
+                        ParseResult::Ok(method) => decl.methods.push(method),
+                        ParseResult::Err(e) => return ParseResult::Err(e),
+                    }
+                },
+                Some(Token::Identifier(pos, name)) => {
+                    let (pos, name) = (*pos, name.to_string());
+                    self.advance();
+                    if let ParseResult::Err(e) = self.consume(":", |t| matches!(t, Token::Colon(_))) {
+                        return ParseResult::Err(e.with_help("Fields need a type, eg 'x: float'."));
+                    }
+                    match self.annotation() {
+                        ParseResult::Ok(annotation) => decl.fields.push(FieldDecl { name, annotation, pos }),
+                        ParseResult::Err(e) => return ParseResult::Err(e),
+                    }
+                },
+                Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                    format!("Expected a field or a method but found '{}'.", token), *token.pos())),
+                None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                    "Expected '}' to close '{'.", open)),
+            }
+        }
+        ParseResult::Ok(Expr::Struct(pos, Rc::new(decl)))
+    }
+
+    /// Parses a variant of an enum, eg `Shape::Circle`.
+    /// Assumes the current token is the enum's name and the next is '::'.
+    fn path(&mut self) -> ParseResult<(Position, String, Member)> {
+        let (start, name) = match self.peek() {
+            Some(Token::Identifier(pos, name)) => (*pos, name.to_string()),
+            _ => unreachable!("only called on a name"),
+        };
+        self.advance();
+        self.advance();
+        match self.peek() {
+            Some(Token::Identifier(pos, variant)) => {
+                let variant = Member { name: variant.to_string(), pos: *pos };
+                self.advance();
+                ParseResult::Ok((start.extend(&variant.pos), name, variant))
+            },
+            Some(token) => ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Expected a variant of '{}' after '::' but found '{}'.", name, token), *token.pos())),
+            None => ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                format!("Expected a variant of '{}' after '::'.", name), self.end_pos())),
+        }
+    }
+
+    /// Parses a pattern of a match arm, eg `Shape::Rect(w, _)`.
+    fn pattern(&mut self) -> ParseResult<Pattern> {
+        let token = match self.peek() {
+            Some(token) => token,
+            None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                "Expected a pattern.", self.end_pos())),
+        };
+        let pattern = match *token {
+            Token::Int(pos, n) => Pattern::Int(pos, n),
+            // Negative numbers are only one pattern, not an operation
+            Token::Minus(pos) => match self.tokens.get(self.index + 1) {
+                Some(Token::Int(end, n)) => {
+                    let pattern = Pattern::Int(pos.extend(end), -n);
+                    self.advance();
+                    pattern
+                },
+                _ => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                    "Expected an int after '-' in a pattern.", pos)),
+            },
+            Token::Str(pos, ref s) => Pattern::Str(pos, s.to_string()),
+            Token::Char(pos, c) => Pattern::Char(pos, c),
+            Token::Keyword(pos, keyword @ ("true" | "false")) => Pattern::Bool(pos, keyword == "true"),
+            Token::Identifier(pos, "_") => Pattern::Wildcard(pos),
+            Token::Identifier(_, _) if matches!(self.tokens.get(self.index + 1), Some(Token::ColonColon(_))) =>
+                return self.variant_pattern(),
+            Token::Identifier(pos, name) => Pattern::Binding(pos, name.to_string()),
+            _ => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Expected a pattern but found '{}'.", token), *token.pos())
+                .with_help("Patterns can be literals, names, '_' or variants like 'Shape::Circle(r)'.")),
+        };
+        self.advance();
+        ParseResult::Ok(pattern)
+    }
+
+    /// Parses a variant pattern along with the
+    /// patterns for what it holds, if it has any.
+    fn variant_pattern(&mut self) -> ParseResult<Pattern> {
+        let (mut pos, name, variant) = match self.path() {
+            ParseResult::Ok(path) => path,
+            ParseResult::Err(e) => return ParseResult::Err(e),
+        };
+        let mut fields = Vec::new();
+        let open = match self.peek() {
+            Some(Token::LParen(open)) => *open,
+            _ => return ParseResult::Ok(Pattern::Variant(pos, name, variant, fields)),
+        };
+        self.advance();
+        loop {
+            if let Some(Token::RParen(end)) = self.peek() {
+                pos = pos.extend(end);
+                self.advance();
+                return ParseResult::Ok(Pattern::Variant(pos, name, variant, fields));
+            }
+            match self.pattern() {
+                ParseResult::Ok(field) => fields.push(field),
+                err => return err,
+            }
+            match self.peek() {
+                Some(Token::Comma(_)) => self.advance(),
+                Some(Token::RParen(_)) => {},
+                Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                    format!("Expected ',' or ')' but found '{}'.", token), *token.pos())
+                    .with_label(open, "This '(' is never closed")),
+                None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                    "Expected ')' to close '('.", open)),
+            }
+        }
+    }
+
+    /// Parses a match expression, eg
+    /// `match shape { Shape::Circle(r) => r * r, _ => 0.0 }`.
+    /// Arms can be separated by commas, but don't have to be.
+    fn match_expr(&mut self, start: Position) -> ParseResult<Expr> {
+        self.advance();
+        let value = match self.expr(0) {
+            ParseResult::Ok(e) => e,
+            err => return err,
+        };
+        let open = match self.consume("{", |t| matches!(t, Token::LBrace(_))) {
+            ParseResult::Ok(pos) => pos,
+            ParseResult::Err(e) => return ParseResult::Err(e),
+        };
+        let mut arms = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::RBrace(end)) => {
+                    let pos = start.extend(end);
+                    self.advance();
+                    return ParseResult::Ok(Expr::Match(pos, Box::new(value), arms));
+                },
+                Some(Token::Comma(_)) => {
+                    self.advance();
+                    continue;
+                },
+                Some(_) => {},
+                None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                    "Expected '}' to close '{'.", open)),
+            }
+            let pattern = match self.pattern() {
+                ParseResult::Ok(pattern) => pattern,
+                ParseResult::Err(e) => return ParseResult::Err(e),
+            };
+            let guard = match self.peek() {
+                Some(Token::Keyword(_, "if")) => {
+                    self.advance();
+                    match self.expr(0) {
+                        ParseResult::Ok(e) => Some(e),
+                        err => return err,
+                    }
+                },
+                _ => None,
+            };
+            if let ParseResult::Err(e) = self.consume("=>", |t| matches!(t, Token::FatArrow(_))) {
+                return ParseResult::Err(e.with_help("Arms are written 'pattern => value'."));
+            }
+            let body = match self.peek() {
+                Some(Token::LBrace(_)) => self.block(),
+                _ => self.expr(0),
+            };
+            match body {
+                ParseResult::Ok(body) => arms.push(Arm { pattern, guard, body }),
+                err => return err,
+            }
+        }
+    }
+
+    /// Parses an enum declaration, eg `enum Shape { Circle(float), Rect(float, float) }`.
+    /// Variants can be separated by commas, but don't have to be.
+    /// Assumes the current token is the `enum` keyword.
+    fn enum_declaration(&mut self, start: Position) -> ParseResult<Expr> {
+        self.advance();
+        let (pos, name) = match self.peek() {
+            Some(Token::Identifier(pos, name)) => (start.extend(pos), name.to_string()),
+            Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Expected an enum name but found '{}'.", token), *token.pos())),
+            None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                "Expected an enum name.", self.end_pos())),
+        };
+        // Like structs, enums are types so they need the capital
+        if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+            return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Enum names have to start with a capital letter, but found '{}'.", name), pos));
+        }
+        self.advance();
+        let open = match self.consume("{", |t| matches!(t, Token::LBrace(_))) {
+            ParseResult::Ok(pos) => pos,
+            ParseResult::Err(e) => return ParseResult::Err(e),
+        };
+        let mut decl = EnumDecl { name, variants: Vec::new() };
+        loop {
+            let (pos, name) = match self.peek() {
+                Some(Token::RBrace(_)) => {
+                    self.advance();
+                    break;
+                },
+                Some(Token::DocComment(_, _)) | Some(Token::Comma(_)) => {
+                    self.advance();
+                    continue;
+                },
+                Some(Token::Identifier(pos, name)) => (*pos, name.to_string()),
+                Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                    format!("Expected a variant but found '{}'.", token), *token.pos())),
+                None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                    "Expected '}' to close '{'.", open)),
+            };
+            self.advance();
+            let fields = match self.peek() {
+                Some(Token::LParen(_)) => {
+                    self.advance();
+                    match self.type_list(")", |t| matches!(t, Token::RParen(_))) {
+                        ParseResult::Ok((fields, _, _)) => fields,
+                        ParseResult::Err(e) => return ParseResult::Err(e),
+                    }
+                },
+                _ => Vec::new(),
+            };
+            decl.variants.push(VariantDecl { name, fields, pos });
+        }
+        ParseResult::Ok(Expr::Enum(pos, Rc::new(decl)))
+    }
+
+    /// Parses `break` or `continue` with an optional label after it.
+    /// The label has to be on the same line, otherwise it's the
+    /// next expression, like the `x` in `if done { break }\nx`.
+    fn jump(&mut self, start: Position, is_break: bool) -> ParseResult<Expr> {
+        self.advance();
+        let line = self.source.line_index(start.start());
+        let label = match self.peek() {
+            Some(Token::Identifier(pos, name)) if self.source.line_index(pos.start()) == line => {
+                let label = LoopLabel { name: name.to_string(), pos: *pos };
+                self.advance();
+                Some(label)
+            },
+            _ => None,
+        };
+        let pos = match &label {
+            Some(label) => start.extend(&label.pos),
+            None => start,
+        };
+        ParseResult::Ok(if is_break { Expr::Break(pos, label) } else { Expr::Continue(pos, label) })
+    }
+
+    /// Parses an expression whose operators all bind
+    /// tighter than the given minimum power.
+    fn expr(&mut self, min_power: u8) -> ParseResult<Expr> {
+        let mut left = match self.atom() {
+            ParseResult::Ok(e) => e,
+            err => return err,
+        };
+
+        while let Some(op) = self.peek() {
+            if let Token::LParen(_) | Token::Dot(_) = op {
+                if CALL_POWER < min_power {
+                    break;
+                }
+                // Same as labels, only on the line the left side ends on.
+                // Otherwise it's the next expression, like the tuple in `let x = 1\n(2, 3)`.
+                let line = self.source.line_index(self.tokens[self.index - 1].pos().end());
+                if self.source.line_index(op.pos().start()) != line {
+                    break;
+                }
+                let postfix = if let Token::LParen(_) = op { self.call(left) } else { self.field(left) };
+                left = match postfix {
+                    ParseResult::Ok(e) => e,
+                    err => return err,
+                };
+                continue;
+            }
+            if Self::is_assignment(op) {
+                if ASSIGN_POWER < min_power {
+                    break;
+                }
+                left = match self.assignment(left) {
+                    ParseResult::Ok(e) => e,
+                    err => return err,
+                };
+                continue;
+            }
+            let (left_power, right_power) = match Self::infix_power(op) {
+                Some(powers) => powers,
+                None => break,
+            };
+            // Let the caller take the operator if it binds looser than us
+            if left_power < min_power {
+                break;
+            }
+            // Tokens aren't Clone, so remember which operator this is
+            // before stepping over it.
+            let make: MakeBinary = match op {
+                Token::Plus(_) => Expr::Add,
+                Token::Minus(_) => Expr::Sub,
+                Token::Star(_) => Expr::Mul,
+                Token::Slash(_) => Expr::Div,
+                Token::EqEq(_) => Expr::Eq,
+                Token::BangEq(_) => Expr::Ne,
+                Token::Less(_) => Expr::Lt,
+                Token::LessEq(_) => Expr::Le,
+                Token::Greater(_) => Expr::Gt,
+                Token::GreaterEq(_) => Expr::Ge,
+                Token::AndAnd(_) => Expr::And,
+                Token::QuestionQuestion(_) => Expr::Coalesce,
+                _ => Expr::Or,
+            };
+            self.advance();
+
+            let right = match self.expr(right_power) {
+                ParseResult::Ok(e) => e,
+                err => return err,
+            };
+            let pos = left.pos().extend(right.pos());
+            left = make(pos, Box::new(left), Box::new(right));
+        }
+        ParseResult::Ok(left)
+    }
+
+    /// Returns true if the token is `=` or a compound assignment like `+=`.
+    fn is_assignment(token: &Token<'a>) -> bool {
+        matches!(token, Token::Equals(_) | Token::PlusEq(_) | Token::MinusEq(_) | Token::StarEq(_)
+            | Token::SlashEq(_) | Token::PercentEq(_) | Token::StarStarEq(_) | Token::AmpEq(_)
+            | Token::PipeEq(_) | Token::CaretEq(_) | Token::ShlEq(_) | Token::ShrEq(_))
+    }
+
+    /// Parses the rest of an assignment to the target, eg the `+= 1` in `x += 1`.
+    /// Compound assignments are turned into plain ones, so `x += 1` is `x = x + 1`.
+    fn assignment(&mut self, target: Expr) -> ParseResult<Expr> {
+        let (op_pos, make): (Position, Option<MakeBinary>) = match self.peek() {
+            Some(Token::Equals(pos)) => (*pos, None),
+            Some(Token::PlusEq(pos)) => (*pos, Some(Expr::Add)),
+            Some(Token::MinusEq(pos)) => (*pos, Some(Expr::Sub)),
+            Some(Token::StarEq(pos)) => (*pos, Some(Expr::Mul)),
+            Some(Token::SlashEq(pos)) => (*pos, Some(Expr::Div)),
+            // The operators they stand for don't exist yet
+            Some(token) => {
+                let op = token.to_string();
+                return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                    format!("'{}' can't be used yet.", op), *token.pos())
+                    .with_help(format!("Only '=', '+=', '-=', '*=' and '/=' exist, write out what '{}' should do with those", op)));
+            },
+            None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                "Expected '='.", self.end_pos())),
+        };
+        let (target_pos, name) = match target {
+            Expr::Var(pos, name) => (pos, name),
+            target => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                "Only variables can be assigned to.", op_pos.extend(target.pos()))),
+        };
+        self.advance();
+        let value = match self.expr(ASSIGN_POWER) {
+            ParseResult::Ok(e) => e,
+            err => return err,
+        };
+        let pos = target_pos.extend(value.pos());
+        let value = match make {
+            Some(make) => make(pos, Box::new(Expr::Var(target_pos, name.clone())), Box::new(value)),
+            None => value,
+        };
+        ParseResult::Ok(Expr::Assign(pos, name, Box::new(value)))
+    }
+
+    /// Parses a declaration, eg `let x = 1 + 2` or `let x: float = 1.5`.
+    /// Assumes the current token is the `let` keyword.
+    fn declaration(&mut self, start: Position) -> ParseResult<Expr> {
+        self.advance();
+        let name = match self.peek() {
+            Some(Token::Identifier(_, name)) => name.to_string(),
+            Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Expected a variable name but found '{}'.", token), *token.pos())),
+            None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                "Expected a variable name.", self.end_pos())),
+        };
+        self.advance();
+        // The type is optional, it gets inferred from the value otherwise
+        let annotation = match self.peek() {
+            Some(Token::Colon(_)) => {
+                self.advance();
+                match self.annotation() {
+                    ParseResult::Ok(annotation) => Some(annotation),
+                    ParseResult::Err(e) => return ParseResult::Err(e),
+                }
+            },
+            _ => None,
+        };
+        match self.peek() {
+            Some(Token::Equals(_)) => self.advance(),
+            Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Expected '=' but found '{}'.", token), *token.pos())),
+            None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                "Expected '='.", self.end_pos())),
+        }
+        let value = match self.expr(0) {
+            ParseResult::Ok(e) => e,
+            err => return err,
+        };
+        ParseResult::Ok(Expr::Let(start.extend(value.pos()), name, annotation, Box::new(value)))
+    }
+
+    /// Steps over a token, or fails if it isn't the expected one.
+    /// Returns the position of the token.
+    fn consume(&mut self, expected: &str, is: fn(&Token) -> bool) -> ParseResult<Position> {
+        match self.peek() {
+            Some(token) if is(token) => {
+                let pos = *token.pos();
+                self.advance();
+                ParseResult::Ok(pos)
+            },
+            Some(token) => ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Expected '{}' but found '{}'.", expected, token), *token.pos())),
+            None => ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                format!("Expected '{}'.", expected), self.end_pos())),
+        }
+    }
+
+    /// Steps over a '>' closing a list of type arguments.
+    /// The lexer turns `>>` into a single token, so `List<List<int>>`
+    /// needs the token split in two, leaving the second half for
+    /// the outer list. Same goes for `>=` and `>>=`.
+    fn close_angle(&mut self) -> ParseResult<Position> {
+        let (pos, rest): (Position, Option<MakeToken<'a>>) = match self.peek() {
+            Some(Token::Greater(pos)) => (*pos, None),
+            Some(Token::Shr(pos)) => (*pos, Some(Token::Greater)),
+            Some(Token::GreaterEq(pos)) => (*pos, Some(Token::Equals)),
+            Some(Token::ShrEq(pos)) => (*pos, Some(Token::GreaterEq)),
+            _ => return self.consume(">", |_| false),
+        };
+        let first = Position::new(pos.file(), pos.start(), pos.start() + 1);
+        match rest {
+            Some(make) => self.tokens[self.index] = make(Position::new(pos.file(), pos.start() + 1, pos.end())),
+            None => self.advance(),
+        }
+        ParseResult::Ok(first)
+    }
+
+    /// Parses types separated by commas up to a closing token,
+    /// which it steps over. Returns the types, whether there was
+    /// a trailing comma, and the position of the closing token.
+    fn type_list(&mut self, close: &str, is_close: fn(&Token) -> bool) -> ParseResult<(Vec<JType>, bool, Position)> {
+        let mut types = Vec::new();
+        let mut trailing = false;
+        loop {
+            if let Some(token) = self.peek() {
+                if is_close(token) {
+                    break;
+                }
+            }
+            match self.annotation() {
+                ParseResult::Ok(annotation) => types.push(annotation.t),
+                ParseResult::Err(e) => return ParseResult::Err(e),
+            }
+            trailing = matches!(self.peek(), Some(Token::Comma(_)));
+            if !trailing {
+                break;
+            }
+            self.advance();
+        }
+        // `>` has to be split off of `>>`, everything else is a plain token
+        let end = if close == ">" { self.close_angle() } else { self.consume(close, is_close) };
+        match end {
+            ParseResult::Ok(end) => ParseResult::Ok((types, trailing, end)),
+            ParseResult::Err(e) => ParseResult::Err(e),
+        }
+    }
+
+    /// Parses a type that takes type arguments, eg `List<int>`.
+    /// Assumes the name was already stepped over.
+    fn generic(&mut self, name: &str, pos: Position, arity: usize) -> ParseResult<(Vec<JType>, Position)> {
+        if let ParseResult::Err(e) = self.consume("<", |t| matches!(t, Token::Less(_))) {
+            return ParseResult::Err(e.with_help(format!("'{}' needs types to go with it, eg '{}'.", name,
+                if arity == 1 { "List<int>" } else { "Map<string, int>" })));
+        }
+        let (args, _, end) = match self.type_list(">", |t| matches!(t, Token::Greater(_))) {
+            ParseResult::Ok(list) => list,
+            ParseResult::Err(e) => return ParseResult::Err(e),
+        };
+        if args.len() != arity {
+            return ParseResult::Err(Diagnostic::error(codes::TYPE_ARGUMENTS,
+                format!("'{}' takes {} type{} but was given {}.", name, arity,
+                    if arity == 1 { "" } else { "s" }, args.len()), pos.extend(&end)));
+        }
+        ParseResult::Ok((args, pos.extend(&end)))
+    }
+
+    /// Parses a type without any '?' after it.
+    fn type_atom(&mut self) -> ParseResult<Annotation> {
+        let (pos, name) = match self.peek() {
+            Some(Token::Identifier(pos, name)) | Some(Token::Keyword(pos, name)) => (*pos, *name),
+            // Either a tuple or a type in parentheses
+            Some(Token::LParen(pos)) => {
+                let start = *pos;
+                self.advance();
+                let (mut types, trailing, end) = match self.type_list(")", |t| matches!(t, Token::RParen(_))) {
+                    ParseResult::Ok(list) => list,
+                    ParseResult::Err(e) => return ParseResult::Err(e.with_label(start, "This '(' is never closed")),
+                };
+                let t = if types.len() == 1 && !trailing { types.remove(0) } else { JType::Tuple(types) };
+                return ParseResult::Ok(Annotation { t, pos: start.extend(&end) });
+            },
+            Some(token) => return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Expected a type but found '{}'.", token), *token.pos())),
+            None => return ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                "Expected a type.", self.end_pos())),
+        };
+        self.advance();
+        match name {
+            "List" => match self.generic(name, pos, 1) {
+                ParseResult::Ok((mut args, pos)) => ParseResult::Ok(Annotation { t: JType::List(Box::new(args.remove(0))), pos }),
+                ParseResult::Err(e) => ParseResult::Err(e),
+            },
+            "Map" => match self.generic(name, pos, 2) {
+                ParseResult::Ok((mut args, pos)) => {
+                    let value = args.remove(1);
+                    let key = args.remove(0);
+                    ParseResult::Ok(Annotation { t: JType::Map(Box::new(key), Box::new(value)), pos })
+                },
+                ParseResult::Err(e) => ParseResult::Err(e),
+            },
+            "fn" => {
+                if let ParseResult::Err(e) = self.consume("(", |t| matches!(t, Token::LParen(_))) {
+                    return ParseResult::Err(e);
+                }
+                let (params, _, _) = match self.type_list(")", |t| matches!(t, Token::RParen(_))) {
+                    ParseResult::Ok(list) => list,
+                    ParseResult::Err(e) => return ParseResult::Err(e),
+                };
+                if let ParseResult::Err(e) = self.consume("->", |t| matches!(t, Token::Arrow(_))) {
+                    return ParseResult::Err(e.with_help("Functions that don't return anything return 'void'."));
+                }
+                match self.annotation() {
+                    ParseResult::Ok(ret) => ParseResult::Ok(Annotation {
+                        pos: pos.extend(&ret.pos),
+                        t: JType::Fn(params, Box::new(ret.t)),
+                    }),
+                    err => err,
+                }
+            },
+            _ => match JType::from_name(name) {
+                Some(t) => ParseResult::Ok(Annotation { t, pos }),
+                // The checker makes sure the struct exists,
+                // since it could be declared further down
+                None if name.starts_with(|c: char| c.is_ascii_uppercase()) =>
+                    ParseResult::Ok(Annotation { t: JType::User(name.to_string()), pos }),
+                None => ParseResult::Err(Diagnostic::error(codes::UNKNOWN_TYPE,
+                    format!("Unknown type '{}'.", name), pos)
+                    .with_help("The basic types are int, float, bool, string, char and void.")),
+            },
+        }
+    }
+
+    /// Parses a type, eg the `int` in `let x: int = 1`.
+    /// Types can be `List<T>`, `Map<K, V>`, tuples like `(int, char)`,
+    /// functions like `fn(int) -> float`, and optionals like `string?`.
+    fn annotation(&mut self) -> ParseResult<Annotation> {
+        let mut annotation = match self.type_atom() {
+            ParseResult::Ok(annotation) => annotation,
+            err => return err,
+        };
+        // Every '?' wraps whatever comes before it
+        while let Some(Token::Question(pos)) = self.peek() {
+            annotation = Annotation {
+                pos: annotation.pos.extend(pos),
+                t: JType::Optional(Box::new(annotation.t)),
+            };
+            self.advance();
+        }
+        ParseResult::Ok(annotation)
+    }
+
+    /// Parses a declaration or an expression.
+    fn statement(&mut self) -> ParseResult<Expr> {
+        match self.peek() {
+            Some(Token::Keyword(pos, keyword)) if *keyword == "let" => {
+                let pos = *pos;
+                self.declaration(pos)
+            },
+            // `fn` followed by a name declares a function,
+            // otherwise it's a lambda
+            Some(Token::Keyword(pos, "fn")) if matches!(self.tokens.get(self.index + 1), Some(Token::Identifier(_, _))) => {
+                let pos = *pos;
+                self.fn_declaration(pos)
+            },
+            Some(Token::Keyword(pos, "struct" | "class")) => {
+                let pos = *pos;
+                self.struct_declaration(pos)
+            },
+            Some(Token::Keyword(pos, "enum")) => {
+                let pos = *pos;
+                self.enum_declaration(pos)
+            },
+            _ => self.expr(0),
+        }
+    }
+
+    /// Parses a name that isn't part of an expression,
+    /// eg the module in `import math`.
+    fn name(&mut self, expected: &str) -> ParseResult<Member> {
+        match self.peek() {
+            Some(Token::Identifier(pos, name)) => {
+                let member = Member { name: name.to_string(), pos: *pos };
+                self.advance();
+                ParseResult::Ok(member)
+            },
+            Some(token) => ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                format!("Expected {} but found '{}'.", expected, token), *token.pos())),
+            None => ParseResult::Err(Diagnostic::error(codes::PARSE_END_OF_FILE,
+                format!("Expected {}.", expected), self.end_pos())),
+        }
+    }
+
+    /// Parses `import module` or `from module import a, b`.
+    /// Assumes the current token is the `import` or `from` keyword.
+    fn import(&mut self, start: Position, from: bool) -> ParseResult<Expr> {
+        self.advance();
+        let module = match self.name("a module name") {
+            ParseResult::Ok(module) => module,
+            ParseResult::Err(e) => return ParseResult::Err(e),
+        };
+        if !from {
+            return ParseResult::Ok(Expr::Import(start.extend(&module.pos), module, None));
+        }
+        if let ParseResult::Err(e) = self.consume("import", |t| matches!(t, Token::Keyword(_, "import"))) {
+            return ParseResult::Err(e);
+        }
+        let mut names = Vec::new();
+        loop {
+            match self.name("a name to import") {
+                ParseResult::Ok(name) => names.push(name),
+                ParseResult::Err(e) => return ParseResult::Err(e),
+            }
+            match self.peek() {
+                Some(Token::Comma(_)) => self.advance(),
+                _ => break,
+            }
+        }
+        let pos = start.extend(&names[names.len() - 1].pos);
+        ParseResult::Ok(Expr::Import(pos, module, Some(names)))
+    }
+
+    /// Parses a statement at the top level of a file,
+    /// the only place imports and exports can go.
+    fn top_level(&mut self) -> ParseResult<Expr> {
+        match self.peek() {
+            Some(Token::Keyword(pos, keyword @ ("import" | "from"))) => {
+                let (pos, from) = (*pos, *keyword == "from");
+                self.import(pos, from)
+            },
+            Some(Token::Keyword(pos, "export")) => {
+                let pos = *pos;
+                self.advance();
+                let decl = match self.statement() {
+                    ParseResult::Ok(decl) => decl,
+                    err => return err,
+                };
+                if decl.declared_name().is_none() {
+                    return ParseResult::Err(Diagnostic::error(codes::UNEXPECTED_TOKEN,
+                        "Only declarations can be exported.", *decl.pos())
+                        .with_label(pos, "Exported here")
+                        .with_help("Put 'export' in front of a let, fn, struct or enum"));
+                }
+                ParseResult::Ok(Expr::Export(pos.extend(decl.pos()), Box::new(decl)))
+            },
+            _ => self.statement(),
+        }
+    }
+
+    /// Parses every statement until the end of the tokens.
+    fn body(&mut self) -> ParseResult<Expr> {
+        let mut exprs = Vec::new();
+        while let Some(token) = self.peek() {
+            // Nothing keeps doc comments yet, so skip past them
+            if let Token::DocComment(_, _) = token {
+                self.advance();
+                continue;
+            }
+            match self.top_level() {
+                ParseResult::Ok(e) => exprs.push(e),
+                ParseResult::Err(e) => return ParseResult::Err(e),
+            }
+        }
+        // The body covers everything from the first to the last expression
+        let pos = match (exprs.first(), exprs.last()) {
+            (Some(first), Some(last)) => first.pos().extend(last.pos()),
+            _ => self.end_pos(),
+        };
+        ParseResult::Ok(Expr::Body(pos, exprs))
+    }
+}
+
+/// Takes in the tokens of a file and builds an expression tree from them
+pub fn parse<'a>(source: &'a SourceFile, tokens: Vec<Token<'a>>) -> ParseResult<Expr> {
+    let mut parser = Parser::new(source, tokens);
+    parser.body()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::expr::Expr;
+    use crate::parser::result::ParseResult;
+    use crate::types::JType;
+    use crate::utils::testing;
+
+    /// Parses a single expression and writes it out as an s-expression.
+    fn tree(src: &str) -> String {
+        let tree = testing::parse(src).to_string();
+        tree.strip_prefix("(body ").and_then(|tree| tree.strip_suffix(')')).unwrap_or(&tree).to_string()
+    }
+
+    fn error(src: &str) -> &'static str {
+        match testing::try_parse(src) {
+            ParseResult::Ok(tree) => panic!("{:?} parsed as {}", src, tree),
+            ParseResult::Err(e) => e.code.id,
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(tree("1 + 2 * 3"), "(+ 1 (* 2 3))");
+        assert_eq!(tree("1 * 2 + 3"), "(+ (* 1 2) 3)");
+        assert_eq!(tree("1 - 2 - 3"), "(- (- 1 2) 3)");
+        assert_eq!(tree("1 / 2 / 3"), "(/ (/ 1 2) 3)");
+        assert_eq!(tree("(1 + 2) * 3"), "(* (+ 1 2) 3)");
+        assert_eq!(tree("1 + 2 < 3 * 4"), "(< (+ 1 2) (* 3 4))");
+        assert_eq!(tree("a < b == c < d"), "(== (< a b) (< c d))");
+        assert_eq!(tree("a || b && c"), "(|| a (&& b c))");
+        assert_eq!(tree("a && b || c && d"), "(|| (&& a b) (&& c d))");
+    }
+
+    #[test]
+    fn prefix_and_postfix() {
+        assert_eq!(tree("-a * b"), "(* (- a) b)");
+        assert_eq!(tree("!a && b"), "(&& (! a) b)");
+        assert_eq!(tree("-f(x)(y)"), "(- (call (call f x) y))");
+        assert_eq!(tree("-f(x).y"), "(- (. (call f x) y))");
+        assert_eq!(tree("--1"), "(- (- 1))");
+    }
+
+    #[test]
+    fn statements() {
+        assert_eq!(tree("let x = 1"), "(let x 1)");
+        assert_eq!(tree("let x: int = 1"), "(let x: int 1)");
+        assert_eq!(tree("let f: fn(int, List<float>) -> (int, bool)? = g"), "(let f: fn(int, List<float>) -> (int, bool)? g)");
+        assert_eq!(tree("if a { 1 } else { 2 }"), "(if a (body 1) (body 2))");
+        assert_eq!(tree("outer: while a { break outer }"), "(outer: while a (body (break outer)))");
+        // A name on the next line isn't a label
+        assert_eq!(tree("while a { break\nb }"), "(while a (body (break) b))");
+        // Neither is a call or a field on the next line
+        assert_eq!(tree("let x = 1\n(2, 3)"), "(let x 1) (tuple 2 3)");
+        assert_eq!(tree("f\n(x)"), "f x");
+        assert_eq!(tree("f(\nx\n)(y)"), "(call (call f x) y)");
+    }
+
+    #[test]
+    fn assignment() {
+        assert_eq!(tree("x = a || b"), "(= x (|| a b))");
+        assert_eq!(tree("x += 1"), "(= x (+ x 1))");
+        assert_eq!(tree("x /= y - 1"), "(= x (/ x (- y 1)))");
+        assert_eq!(tree("f(x = 1)"), "(call f (= x 1))");
+        assert_eq!(error("f() = 1"), "P001");
+        assert_eq!(error("x %= 2"), "P001");
+    }
+
+    #[test]
+    fn types_round_trip() {
+        let optional = |t: JType| JType::Optional(Box::new(t));
+        let function = |params: Vec<JType>, ret: JType| JType::Fn(params, Box::new(ret));
+        let types = [
+            JType::Int, JType::Float, JType::Bool, JType::String, JType::Char, JType::Void,
+            JType::List(Box::new(JType::Int)),
+            JType::Map(Box::new(JType::String), Box::new(optional(JType::Int))),
+            JType::Tuple(vec![]),
+            JType::Tuple(vec![JType::Int]),
+            JType::Tuple(vec![JType::Int, optional(JType::String)]),
+            function(vec![], JType::Void),
+            function(vec![JType::Int, JType::Char], optional(JType::Int)),
+            function(vec![function(vec![JType::Int], JType::Int)], function(vec![], JType::Bool)),
+            optional(JType::Int),
+            optional(optional(JType::Int)),
+            optional(optional(optional(JType::Int))),
+            optional(function(vec![JType::Int], JType::Int)),
+            optional(JType::Tuple(vec![JType::Int, JType::Int])),
+            optional(JType::User("Point".to_string())),
+            JType::List(Box::new(optional(optional(JType::Int)))),
+            JType::User("Point".to_string()),
+        ];
+        for t in types {
+            let written = t.to_string();
+            let parsed = match testing::parse(&format!("let x: {} = y", written)) {
+                Expr::Body(_, mut exprs) => match exprs.pop() {
+                    Some(Expr::Let(_, _, Some(annotation), _)) => annotation.t,
+                    _ => panic!("{} didn't parse as an annotation", written),
+                },
+                _ => unreachable!("the parser always gives a body"),
+            };
+            assert!(parsed == t, "{} parsed as {}", written, parsed);
+        }
+    }
+
+    #[test]
+    fn tuples_maps_and_optionals() {
+        assert_eq!(tree("(1, 'a')"), "(tuple 1 'a')");
+        assert_eq!(tree("(1,)"), "(tuple 1)");
+        assert_eq!(tree("()"), "(tuple)");
+        assert_eq!(tree("pair.0"), "(. pair 0)");
+        assert_eq!(tree("[\"a\": 1, \"b\": 2]"), "(map (\"a\" 1) (\"b\" 2))");
+        assert_eq!(tree("[:]"), "(map)");
+        assert_eq!(tree("a ?? b ?? c"), "(?? a (?? b c))");
+        assert_eq!(tree("a ?? 1 + 2 == null"), "(== (?? a (+ 1 2)) null)");
+        assert_eq!(error("[1: 2, 3]"), "P001");
+        assert_eq!(error("pair.0.1"), "P001");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("1 +"), "P002");
+        assert_eq!(error("let = 1"), "P001");
+        assert_eq!(error("let x: nope = 1"), "P003");
+        assert_eq!(error("let x: List<int, int> = y"), "P004");
+        assert_eq!(error("(1 + 2"), "P002");
+        assert_eq!(error("1 + )"), "P001");
+    }
+}
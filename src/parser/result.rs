@@ -0,0 +1,10 @@
+
+use crate::utils::diagnostic::Diagnostic;
+
+/// Result that either returns a parser error or a value.
+pub enum ParseResult<T> {
+    /// Parser result.
+    Ok(T),
+    /// Parser error.
+    Err(Diagnostic),
+}
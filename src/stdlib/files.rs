@@ -0,0 +1,41 @@
+use crate::interpreter::{ Interpreter, result::Flow, value::Value };
+use crate::lexer::token::Position;
+use crate::types::JType;
+use crate::utils::{ codes, diagnostic::Diagnostic };
+use super::{ fn_type, wrong_args, Library, Native };
+use std::fs;
+
+fn read(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    let path = match &args[..] {
+        [Value::Str(path)] => path,
+        _ => return wrong_args(pos, "read"),
+    };
+    match fs::read_to_string(&**path) {
+        Ok(src) => Flow::Ok(Value::Str(src.into())),
+        Err(e) => Flow::Err(Diagnostic::error(codes::IO,
+            format!("Could not read '{}': {}", path, e), *pos)),
+    }
+}
+
+fn write(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    let (path, contents) = match &args[..] {
+        [Value::Str(path), Value::Str(contents)] => (path, contents),
+        _ => return wrong_args(pos, "write"),
+    };
+    match fs::write(&**path, contents.as_bytes()) {
+        Ok(_) => Flow::Ok(Value::Void),
+        Err(e) => Flow::Err(Diagnostic::error(codes::IO,
+            format!("Could not write '{}': {}", path, e), *pos)),
+    }
+}
+
+pub fn library() -> Library {
+    Library {
+        name: "files",
+        natives: vec![
+            Native { name: "read", t: fn_type(vec![JType::String], JType::String), call: read },
+            // Replaces whatever the file had in it
+            Native { name: "write", t: fn_type(vec![JType::String, JType::String], JType::Void), call: write },
+        ],
+    }
+}
@@ -0,0 +1,34 @@
+use crate::interpreter::{ Interpreter, result::Flow, value::Value };
+use crate::lexer::token::Position;
+use crate::types::JType;
+use crate::utils::logging;
+use super::{ fn_type, Native };
+
+/// Turns a value into text for people to read,
+/// so strings and chars don't get quotes around them.
+fn text(value: &Value) -> String {
+    match value {
+        Value::Str(s) => s.to_string(),
+        Value::Char(c) => c.to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn print(_: &mut Interpreter, _: &Position, args: Vec<Value>) -> Flow<Value> {
+    logging::print(&text(&args[0]));
+    Flow::Ok(Value::Void)
+}
+
+fn println(_: &mut Interpreter, _: &Position, args: Vec<Value>) -> Flow<Value> {
+    logging::println(&text(&args[0]));
+    Flow::Ok(Value::Void)
+}
+
+pub fn natives() -> Vec<Native> {
+    // Anything can be printed
+    let printer = || fn_type(vec![JType::Var(0)], JType::Void);
+    vec![
+        Native { name: "print", t: printer(), call: print },
+        Native { name: "println", t: printer(), call: println },
+    ]
+}
@@ -0,0 +1,86 @@
+use crate::interpreter::{ Interpreter, result::Flow, value::Value };
+use crate::lexer::token::Position;
+use crate::types::JType;
+use crate::utils::{ codes, diagnostic::Diagnostic };
+use super::{ fn_type, list, wrong_args, Library, Native };
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Adds an item to the end of a list.
+fn push(_: &mut Interpreter, pos: &Position, mut args: Vec<Value>) -> Flow<Value> {
+    let item = args.pop();
+    match (&args[..], item) {
+        ([Value::List(items)], Some(item)) => {
+            items.borrow_mut().push(item);
+            Flow::Ok(Value::Void)
+        },
+        _ => wrong_args(pos, "push"),
+    }
+}
+
+/// Takes the last item off a list.
+fn pop(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    let items = match &args[..] {
+        [Value::List(items)] => items,
+        _ => return wrong_args(pos, "pop"),
+    };
+    // Bound first so the borrow ends before the list is dropped
+    let item = items.borrow_mut().pop();
+    match item {
+        Some(item) => Flow::Ok(item),
+        None => Flow::Err(Diagnostic::error(codes::OUT_OF_RANGE,
+            "You cannot pop from an empty list", *pos)),
+    }
+}
+
+fn len(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    match &args[..] {
+        [Value::List(items)] => Flow::Ok(Value::Int(items.borrow().len() as i64)),
+        _ => wrong_args(pos, "len"),
+    }
+}
+
+/// Gets the item at an index, counting from 0.
+fn get(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    let (items, index) = match &args[..] {
+        [Value::List(items), Value::Int(index)] => (items.borrow(), *index),
+        _ => return wrong_args(pos, "get"),
+    };
+    match usize::try_from(index).ok().and_then(|i| items.get(i)) {
+        Some(item) => Flow::Ok(item.clone()),
+        None => Flow::Err(Diagnostic::error(codes::OUT_OF_RANGE,
+            format!("There's no item {} in a list with {} items", index, items.len()), *pos)),
+    }
+}
+
+/// Makes a new list by calling a function on every item.
+fn map(interpreter: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    let (items, f) = match &args[..] {
+        // Copied so the function can change the list while it runs
+        [Value::List(items), f] => (items.borrow().clone(), f),
+        _ => return wrong_args(pos, "map"),
+    };
+    let mut mapped = Vec::with_capacity(items.len());
+    for item in items {
+        match interpreter.apply(pos, f.clone(), vec![item]) {
+            Flow::Ok(value) => mapped.push(value),
+            flow => return flow,
+        }
+    }
+    Flow::Ok(Value::List(Rc::new(RefCell::new(mapped))))
+}
+
+pub fn library() -> Library {
+    // Lists can hold anything, T and U here
+    let (t, u) = (JType::Var(0), JType::Var(1));
+    Library {
+        name: "lists",
+        natives: vec![
+            Native { name: "push", t: fn_type(vec![list(t.clone()), t.clone()], JType::Void), call: push },
+            Native { name: "pop", t: fn_type(vec![list(t.clone())], t.clone()), call: pop },
+            Native { name: "len", t: fn_type(vec![list(t.clone())], JType::Int), call: len },
+            Native { name: "get", t: fn_type(vec![list(t.clone()), JType::Int], t.clone()), call: get },
+            Native { name: "map", t: fn_type(vec![list(t.clone()), fn_type(vec![t.clone()], u.clone())], list(u)), call: map },
+        ],
+    }
+}
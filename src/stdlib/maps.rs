@@ -0,0 +1,101 @@
+use crate::interpreter::{ Interpreter, result::Flow, value::{ set_entry, Value } };
+use crate::lexer::token::Position;
+use crate::types::JType;
+use super::{ fn_type, list, wrong_args, Library, Native };
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/*
+* Maps keep their entries in the order they were added and look
+* keys up by going through them. Keys can be any type, including
+* ones that can't be hashed, so this is the one way that always
+* works. Maps in JPizza programs are small enough for it.
+*/
+
+/// Shorthand for a map type.
+fn map(key: JType, value: JType) -> JType {
+    JType::Map(Box::new(key), Box::new(value))
+}
+
+/// Shorthand for an optional type.
+fn optional(t: JType) -> JType {
+    JType::Optional(Box::new(t))
+}
+
+/// Gets what a key is set to, or null if it isn't.
+fn get(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    match &args[..] {
+        [Value::Map(entries), key] => Flow::Ok(entries.borrow().iter()
+            .find(|(k, _)| k.is_same_key(key))
+            .map_or(Value::Null, |(_, value)| value.clone())),
+        _ => wrong_args(pos, "get"),
+    }
+}
+
+/// Sets a key, replacing what it was set to before.
+fn set(_: &mut Interpreter, pos: &Position, mut args: Vec<Value>) -> Flow<Value> {
+    let (value, key) = (args.pop(), args.pop());
+    match (&args[..], key, value) {
+        ([Value::Map(entries)], Some(key), Some(value)) => {
+            set_entry(&mut entries.borrow_mut(), key, value);
+            Flow::Ok(Value::Void)
+        },
+        _ => wrong_args(pos, "set"),
+    }
+}
+
+fn has(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    match &args[..] {
+        [Value::Map(entries), key] => Flow::Ok(Value::Bool(entries.borrow().iter().any(|(k, _)| k.is_same_key(key)))),
+        _ => wrong_args(pos, "has"),
+    }
+}
+
+/// Takes a key out of a map, giving what it was set
+/// to, or null if it wasn't.
+fn remove(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    let (entries, key) = match &args[..] {
+        [Value::Map(entries), key] => (entries, key),
+        _ => return wrong_args(pos, "remove"),
+    };
+    let mut entries = entries.borrow_mut();
+    match entries.iter().position(|(k, _)| k.is_same_key(key)) {
+        Some(index) => Flow::Ok(entries.remove(index).1),
+        None => Flow::Ok(Value::Null),
+    }
+}
+
+fn len(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    match &args[..] {
+        [Value::Map(entries)] => Flow::Ok(Value::Int(entries.borrow().len() as i64)),
+        _ => wrong_args(pos, "len"),
+    }
+}
+
+/// Makes a list of the keys, in the order they were added.
+fn keys(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    match &args[..] {
+        [Value::Map(entries)] => {
+            let keys = entries.borrow().iter().map(|(key, _)| key.clone()).collect();
+            Flow::Ok(Value::List(Rc::new(RefCell::new(keys))))
+        },
+        _ => wrong_args(pos, "keys"),
+    }
+}
+
+pub fn library() -> Library {
+    // Keys are K and values are V
+    let (k, v) = (JType::Var(0), JType::Var(1));
+    let m = map(k.clone(), v.clone());
+    Library {
+        name: "maps",
+        natives: vec![
+            Native { name: "get", t: fn_type(vec![m.clone(), k.clone()], optional(v.clone())), call: get },
+            Native { name: "set", t: fn_type(vec![m.clone(), k.clone(), v.clone()], JType::Void), call: set },
+            Native { name: "has", t: fn_type(vec![m.clone(), k.clone()], JType::Bool), call: has },
+            Native { name: "remove", t: fn_type(vec![m.clone(), k.clone()], optional(v)), call: remove },
+            Native { name: "len", t: fn_type(vec![m.clone()], JType::Int), call: len },
+            Native { name: "keys", t: fn_type(vec![m], list(k)), call: keys },
+        ],
+    }
+}
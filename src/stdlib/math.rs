@@ -0,0 +1,36 @@
+use crate::interpreter::{ Interpreter, result::Flow, value::Value };
+use crate::lexer::token::Position;
+use crate::types::JType;
+use super::{ fn_type, wrong_args, Library, Native };
+
+fn sqrt(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    match args[..] {
+        [Value::Float(n)] => Flow::Ok(Value::Float(n.sqrt())),
+        _ => wrong_args(pos, "sqrt"),
+    }
+}
+
+fn floor(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    match args[..] {
+        [Value::Float(n)] => Flow::Ok(Value::Float(n.floor())),
+        _ => wrong_args(pos, "floor"),
+    }
+}
+
+fn pow(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    match args[..] {
+        [Value::Float(base), Value::Float(exponent)] => Flow::Ok(Value::Float(base.powf(exponent))),
+        _ => wrong_args(pos, "pow"),
+    }
+}
+
+pub fn library() -> Library {
+    Library {
+        name: "math",
+        natives: vec![
+            Native { name: "sqrt", t: fn_type(vec![JType::Float], JType::Float), call: sqrt },
+            Native { name: "floor", t: fn_type(vec![JType::Float], JType::Float), call: floor },
+            Native { name: "pow", t: fn_type(vec![JType::Float, JType::Float], JType::Float), call: pow },
+        ],
+    }
+}
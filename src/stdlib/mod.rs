@@ -0,0 +1,87 @@
+mod files;
+mod io;
+mod lists;
+mod maps;
+mod math;
+mod strings;
+
+use crate::interpreter::{ Interpreter, result::Flow, value::Value };
+use crate::lexer::token::Position;
+use crate::types::JType;
+use crate::utils::{ codes, diagnostic::Diagnostic };
+use std::sync::OnceLock;
+
+/*
+* Natives are functions written in Rust that JPizza code can
+* call like any other function. A few of them, like print, can
+* be used anywhere. The rest come in libraries, which look like
+* modules that are always imported, eg `math.sqrt(2.0)`.
+*
+* Every native has a type the checker uses to check calls to
+* it. Natives that work on any type, like lists.push, use type
+* variables in their type. The checker swaps those for fresh
+* ones every time the native is used, so pushing ints onto one
+* list doesn't stop strings being pushed onto another.
+*
+* The natives and libraries are only built once, the first time
+* anything asks for them, and shared from then on.
+*/
+
+/// What a native does when it's called. The arguments were
+/// checked against its type, and the position is of the call.
+pub type NativeFn = fn(&mut Interpreter, &Position, Vec<Value>) -> Flow<Value>;
+
+/// A function written in Rust.
+pub struct Native {
+    pub name: &'static str,
+    pub t: JType,
+    pub call: NativeFn,
+}
+
+/// A group of natives used through its name, like `math`.
+pub struct Library {
+    pub name: &'static str,
+    pub natives: Vec<Native>,
+}
+
+/// Shorthand for a function type.
+fn fn_type(params: Vec<JType>, ret: JType) -> JType {
+    JType::Fn(params, Box::new(ret))
+}
+
+/// Shorthand for a list type.
+fn list(t: JType) -> JType {
+    JType::List(Box::new(t))
+}
+
+/// The natives that can be used anywhere.
+pub fn prelude() -> &'static [Native] {
+    static PRELUDE: OnceLock<Vec<Native>> = OnceLock::new();
+    PRELUDE.get_or_init(io::natives)
+}
+
+/// Every library.
+pub fn libraries() -> &'static [Library] {
+    static LIBRARIES: OnceLock<Vec<Library>> = OnceLock::new();
+    LIBRARIES.get_or_init(|| vec![math::library(), strings::library(), lists::library(), maps::library(), files::library()])
+}
+
+/// Finds a library by name.
+pub fn library(name: &str) -> Option<&'static Library> {
+    libraries().iter().find(|library| library.name == name)
+}
+
+/// Finds the type of a native or a library that can be used by name.
+pub fn global(name: &str) -> Option<JType> {
+    if let Some(native) = prelude().iter().find(|native| native.name == name) {
+        return Some(native.t.clone());
+    }
+    library(name).map(|_| JType::Module(name.to_string()))
+}
+
+/// Error for a native called with arguments its type doesn't
+/// allow. The checker stops this from ever happening.
+fn wrong_args<T>(pos: &Position, name: &str) -> Flow<T> {
+    Flow::Err(Diagnostic::error(codes::RUNTIME_TYPE_MISMATCH,
+        format!("'{}' was called with the wrong arguments", name), *pos))
+}
@@ -0,0 +1,80 @@
+use crate::interpreter::{ Interpreter, result::Flow, value::Value };
+use crate::lexer::token::Position;
+use crate::types::JType;
+use crate::utils::{ codes, diagnostic::Diagnostic };
+use super::{ fn_type, list, wrong_args, Library, Native };
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/*
+* Strings are counted in chars rather than bytes,
+* so slicing can never cut a char in half.
+*/
+
+fn len(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    match &args[..] {
+        [Value::Str(s)] => Flow::Ok(Value::Int(s.chars().count() as i64)),
+        _ => wrong_args(pos, "len"),
+    }
+}
+
+/// Gets the chars from start up to but not including end.
+fn slice(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    let (s, start, end) = match &args[..] {
+        [Value::Str(s), Value::Int(start), Value::Int(end)] => (s, *start, *end),
+        _ => return wrong_args(pos, "slice"),
+    };
+    let len = s.chars().count() as i64;
+    if start < 0 || start > end || end > len {
+        return Flow::Err(Diagnostic::error(codes::OUT_OF_RANGE,
+            format!("Can't slice {} to {} out of a string with {} chars", start, end, len), *pos)
+            .with_note("The start has to come before the end, and both have to be inside the string"));
+    }
+    let sliced: String = s.chars().skip(start as usize).take((end - start) as usize).collect();
+    Flow::Ok(Value::Str(sliced.into()))
+}
+
+/// Splits a string everywhere the separator is.
+/// An empty separator splits it into chars.
+fn split(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    let (s, separator) = match &args[..] {
+        [Value::Str(s), Value::Str(separator)] => (s, separator),
+        _ => return wrong_args(pos, "split"),
+    };
+    let parts: Vec<Value> = if separator.is_empty() {
+        s.chars().map(|c| Value::Str(c.to_string().into())).collect()
+    } else {
+        s.split(&**separator).map(|part| Value::Str(part.into())).collect()
+    };
+    Flow::Ok(Value::List(Rc::new(RefCell::new(parts))))
+}
+
+fn join(_: &mut Interpreter, pos: &Position, args: Vec<Value>) -> Flow<Value> {
+    let (parts, separator) = match &args[..] {
+        [Value::List(parts), Value::Str(separator)] => (parts, separator),
+        _ => return wrong_args(pos, "join"),
+    };
+    let mut joined = String::new();
+    for (i, part) in parts.borrow().iter().enumerate() {
+        if i > 0 {
+            joined.push_str(separator);
+        }
+        match part {
+            Value::Str(part) => joined.push_str(part),
+            _ => return wrong_args(pos, "join"),
+        }
+    }
+    Flow::Ok(Value::Str(joined.into()))
+}
+
+pub fn library() -> Library {
+    Library {
+        name: "strings",
+        natives: vec![
+            Native { name: "len", t: fn_type(vec![JType::String], JType::Int), call: len },
+            Native { name: "slice", t: fn_type(vec![JType::String, JType::Int, JType::Int], JType::String), call: slice },
+            Native { name: "split", t: fn_type(vec![JType::String, JType::String], list(JType::String)), call: split },
+            Native { name: "join", t: fn_type(vec![list(JType::String), JType::String], JType::String), call: join },
+        ],
+    }
+}
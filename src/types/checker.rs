@@ -0,0 +1,1955 @@
+use crate::lexer::token::Position;
+use crate::parser::expr::{ Arm, EnumDecl, Expr, Function, LoopLabel, Member, Pattern, StructDecl };
+use crate::stdlib;
+use crate::utils::{ codes, diagnostic::Diagnostic };
+use super::{ JType, result::Checked, utils::is_numeric };
+use super::patterns::{ Coverage, Ctor, Space };
+use std::collections::{ HashMap, HashSet };
+
+/*
+* The checker walks the tree once before anything runs.
+* Instead of stopping at the first problem it keeps going,
+* so every error in a file shows up at once.
+*
+* Types are inferred by unification. A type that isn't known
+* yet is a type variable (JType::Var), and using it somewhere
+* that needs a certain type binds it to that type. Bindings
+* are stored here rather than in the types, so resolve has to
+* be used to see what a variable turned into.
+*
+* Functions declared with fn, and lets holding a lambda or another
+* variable, are generic over the type variables nothing else can see.
+* Each use of them gets fresh ones, so `let id = fn(x) { x }` can
+* be used on an int in one place and a string in another. Other
+* lets stay the same type everywhere, since their value is only
+* worked out once.
+*
+* Type variables used with an operator remember what the operator
+* works on, so `fn lt(a, b) { a < b }` can't be called with lists.
+* What an operator gives back can depend on types that aren't known
+* yet, like `a + b` being a float if either side is. Those wait in
+* `pending` until enough is known, and each use of a generic function
+* gets its own copy of whatever is waiting on its type variables.
+*
+* Expressions that already had an error report no type (None),
+* and anything using them quietly gives up too. Variables declared
+* with a broken value get a fresh type variable, which just takes
+* on whatever type they're used as. That way one typo doesn't
+* turn into a wall of errors about everything it touches.
+*
+* Every module gets its own checker. Once a module has been
+* checked, what it exports gets handed to the checker of each
+* module importing it, which only ever sees those types and
+* never the code they came from.
+*/
+
+/// A declared variable.
+#[derive(Clone)]
+struct Symbol {
+    t: JType,
+    // Where it was declared, to point at on redeclarations
+    pos: Position,
+    // Type variables each use gets its own copy of
+    vars: Vec<usize>,
+    // Only variables from a let, parameters and
+    // match bindings can be assigned to
+    assignable: bool,
+}
+
+/// A declared struct.
+#[derive(Clone)]
+struct StructInfo {
+    fields: Vec<(String, JType)>,
+    // Types of the methods as they're called, so without self
+    methods: Vec<(String, JType)>,
+    // Where it was declared, to point at when a field doesn't exist
+    pos: Position,
+}
+
+/// A declared enum.
+#[derive(Clone)]
+struct EnumInfo {
+    // Names of the variants with the types they hold
+    variants: Vec<(String, Vec<JType>)>,
+    pos: Position,
+}
+
+/// What a type variable is only allowed to turn into.
+/// Each one allows less than the one before it.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Constraint {
+    // Anything == works on, like both sides of `a == b`
+    Comparable,
+    // Numbers, chars and strings, like both sides of `a < b`
+    Ordered,
+    // Only int or float, like both sides of `a + b`
+    Numeric,
+}
+
+/// Something about type variables that can't be
+/// worked out until more of them are known.
+#[derive(Clone)]
+enum Pending {
+    // The type of `a + b`, which is a float if
+    // either side is and an int otherwise
+    Promote(JType, JType, JType),
+    // Both sides of a comparison, which have the same
+    // type unless they're both numbers
+    Compare(JType, JType),
+}
+
+impl Pending {
+    fn types(&self) -> Vec<&JType> {
+        match self {
+            Pending::Promote(a, b, result) => vec![a, b, result],
+            Pending::Compare(a, b) => vec![a, b],
+        }
+    }
+
+    /// Makes a copy with every type swapped for what `f` gives.
+    fn map(&self, mut f: impl FnMut(&JType) -> JType) -> Pending {
+        match self {
+            Pending::Promote(a, b, result) => Pending::Promote(f(a), f(b), f(result)),
+            Pending::Compare(a, b) => Pending::Compare(f(a), f(b)),
+        }
+    }
+}
+
+/// What a checked module lets the modules importing it use.
+#[derive(Clone, Default)]
+pub struct Exports {
+    // Exported variables, functions and struct constructors
+    values: HashMap<String, Symbol>,
+    structs: HashMap<String, StructInfo>,
+    enums: HashMap<String, EnumInfo>,
+    // Everything else declared at the top of the module, so using
+    // it can say it isn't exported rather than that it doesn't exist
+    private: HashMap<String, Position>,
+    // What the type variables in the types can turn into,
+    // and what's still waiting on them
+    constraints: HashMap<usize, Constraint>,
+    pending: Vec<Pending>,
+}
+
+/// Type checks expression trees.
+/// Declarations stick around between checks,
+/// so the REPL can keep one for its whole session.
+#[derive(Clone)]
+pub struct Checker {
+    // Innermost scope last. There's always at least one.
+    scopes: Vec<HashMap<String, Symbol>>,
+    // What each type variable is bound to, indexed by its number
+    bindings: Vec<Option<JType>>,
+    // What type variables used with operators can turn into,
+    // like int or float for both sides of `a + b`
+    constraints: HashMap<usize, Constraint>,
+    // Operations on type variables that wait for them to be known
+    pending: Vec<Pending>,
+    // Labels of the loops around the expression being checked,
+    // innermost last, so break and continue know if they're in one
+    loops: Vec<Option<String>>,
+    // Return types of the functions around the expression being
+    // checked, innermost last, with where each one comes from
+    returns: Vec<(JType, Position)>,
+    // Every struct declared so far. Types are looked up by name
+    // anywhere, so unlike variables these aren't scoped.
+    structs: HashMap<String, StructInfo>,
+    enums: HashMap<String, EnumInfo>,
+    // What each module this one imports exports, by module name
+    modules: HashMap<String, Exports>,
+    // Names of the top level declarations marked with export
+    exported: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            bindings: Vec::new(),
+            constraints: HashMap::new(),
+            pending: Vec::new(),
+            loops: Vec::new(),
+            returns: Vec::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            modules: HashMap::new(),
+            exported: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Makes a module's exports available to import.
+    /// Types it hasn't worked out yet get type variables of this
+    /// checker instead, so whatever imports them can pin them down.
+    pub fn provide(&mut self, name: &str, mut exports: Exports) {
+        let mut vars = HashMap::new();
+        for symbol in exports.values.values_mut() {
+            symbol.t = self.instantiate(&symbol.t, &mut vars);
+        }
+        for info in exports.structs.values_mut() {
+            for (_, t) in &mut info.methods {
+                *t = self.instantiate(t, &mut vars);
+            }
+        }
+        for op in &exports.pending {
+            let op = op.map(|t| self.instantiate(t, &mut vars));
+            self.pending.push(op);
+        }
+        let renamed = |n: &usize| match vars.get(n) {
+            Some(JType::Var(n)) => Some(*n),
+            _ => None,
+        };
+        for symbol in exports.values.values_mut() {
+            symbol.vars = symbol.vars.iter().filter_map(renamed).collect();
+        }
+        for (n, constraint) in &exports.constraints {
+            if let Some(m) = renamed(n) {
+                self.constraints.insert(m, *constraint);
+            }
+        }
+        self.modules.insert(name.to_string(), exports);
+    }
+
+    /// Gets everything checked so far that's been exported.
+    pub fn exports(&self) -> Exports {
+        let mut exports = Exports::default();
+        let exported = |name: &String| self.exported.contains(name);
+        for (name, symbol) in &self.scopes[0] {
+            if exported(name) {
+                let t = self.resolve(&symbol.t);
+                exports.values.insert(name.clone(), Symbol { t, pos: symbol.pos, vars: symbol.vars.clone(), assignable: false });
+            } else {
+                exports.private.insert(name.clone(), symbol.pos);
+            }
+        }
+        for (name, info) in &self.structs {
+            if exported(name) {
+                let mut info = info.clone();
+                for (_, t) in &mut info.methods {
+                    *t = self.resolve(t);
+                }
+                exports.structs.insert(name.clone(), info);
+            } else {
+                exports.private.insert(name.clone(), info.pos);
+            }
+        }
+        for (name, info) in &self.enums {
+            if exported(name) {
+                exports.enums.insert(name.clone(), info.clone());
+            } else {
+                exports.private.insert(name.clone(), info.pos);
+            }
+        }
+        exports.constraints = self.constraints.clone();
+        exports.pending = self.pending.iter().map(|op| op.map(|t| self.resolve(t))).collect();
+        exports
+    }
+
+    /// Starts a new scope. Declarations in it can
+    /// shadow the ones in scopes around it.
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// Throws away the innermost scope and everything declared in it.
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Finds a variable, looking from the innermost scope outwards.
+    fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    /// Gets the type of one use of a variable, with fresh
+    /// type variables for the ones it's generic over.
+    fn instance(&mut self, symbol: &Symbol) -> JType {
+        let mut free = HashSet::new();
+        self.free_vars(&symbol.t, &mut free);
+        // Variables it isn't generic over stay as they are
+        let mut vars: HashMap<usize, JType> = free.into_iter().map(|n| (n, JType::Var(n))).collect();
+        for n in &symbol.vars {
+            let fresh = self.fresh();
+            if let (Some(constraint), JType::Var(m)) = (self.constraints.get(n), &fresh) {
+                self.constraints.insert(*m, *constraint);
+            }
+            vars.insert(*n, fresh);
+        }
+        let t = self.resolve(&symbol.t);
+        let t = self.instantiate(&t, &mut vars);
+        // Whatever was waiting on the variables waits on the copies too
+        for op in self.pending.clone() {
+            let mut free = HashSet::new();
+            for t in op.types() {
+                self.free_vars(t, &mut free);
+            }
+            if free.iter().any(|n| symbol.vars.contains(n)) {
+                for n in free {
+                    vars.entry(n).or_insert(JType::Var(n));
+                }
+                let op = op.map(|t| {
+                    let t = self.resolve(t);
+                    self.instantiate(&t, &mut vars)
+                });
+                self.pending.push(op);
+            }
+        }
+        t
+    }
+
+    /// Adds every unbound type variable in a type to `vars`.
+    fn free_vars(&self, t: &JType, vars: &mut HashSet<usize>) {
+        match self.resolve(t) {
+            JType::Var(n) => {
+                vars.insert(n);
+            },
+            JType::List(t) | JType::Optional(t) => self.free_vars(&t, vars),
+            JType::Map(k, v) => {
+                self.free_vars(&k, vars);
+                self.free_vars(&v, vars);
+            },
+            JType::Tuple(types) => for t in &types {
+                self.free_vars(t, vars);
+            },
+            JType::Fn(params, ret) => {
+                for t in &params {
+                    self.free_vars(t, vars);
+                }
+                self.free_vars(&ret, vars);
+            },
+            _ => {},
+        }
+    }
+
+    /// Makes a variable just declared in the innermost scope generic
+    /// over the type variables in its type that nothing else can see.
+    /// Anything that can still pin one down, like another variable or
+    /// the return type of the function around it, keeps it fixed.
+    fn generalize(&mut self, name: &str, pos: Position) {
+        let innermost = self.scopes.len() - 1;
+        let t = match self.scopes[innermost].get(name) {
+            // It wasn't declared if the name was taken
+            Some(symbol) if symbol.pos == pos => symbol.t.clone(),
+            _ => return,
+        };
+        let mut fixed = HashSet::new();
+        let symbols = self.scopes.iter().enumerate()
+            .flat_map(|(i, scope)| scope.iter().filter(move |(n, _)| i != innermost || n.as_str() != name))
+            .map(|(_, symbol)| symbol)
+            .chain(self.modules.values().flat_map(|exports| exports.values.values()));
+        for symbol in symbols {
+            let mut vars = HashSet::new();
+            self.free_vars(&symbol.t, &mut vars);
+            fixed.extend(vars.into_iter().filter(|n| !symbol.vars.contains(n)));
+        }
+        let methods = self.structs.values().flat_map(|info| info.methods.iter().map(|(_, t)| t));
+        for t in methods.chain(self.returns.iter().map(|(t, _)| t)) {
+            self.free_vars(t, &mut fixed);
+        }
+        let mut vars = HashSet::new();
+        self.free_vars(&t, &mut vars);
+        vars.retain(|n| !fixed.contains(n));
+        // Variables only used by something waiting on the generic ones,
+        // like the type of `a + b`, get their own copies too
+        let mut grown = true;
+        while grown {
+            grown = false;
+            for op in &self.pending {
+                let mut free = HashSet::new();
+                for t in op.types() {
+                    self.free_vars(t, &mut free);
+                }
+                if free.iter().any(|n| vars.contains(n)) {
+                    for n in free {
+                        grown |= !fixed.contains(&n) && vars.insert(n);
+                    }
+                }
+            }
+        }
+        let symbol = self.scopes[innermost].get_mut(name).expect("checked above");
+        symbol.vars = vars.into_iter().collect();
+    }
+
+    /// Writes out a type for an error message.
+    /// Type variables that can only be some types say so.
+    fn show(&self, t: &JType) -> String {
+        match self.resolve(t) {
+            JType::Var(n) => match self.constraints.get(&n) {
+                Some(Constraint::Numeric) => "a number".to_string(),
+                Some(Constraint::Ordered) => "a number, char or string".to_string(),
+                Some(Constraint::Comparable) => "something that can be compared".to_string(),
+                None => format!("'{}'", t),
+            },
+            t => format!("'{}'", t),
+        }
+    }
+
+    /// Makes a new type variable for a type that isn't known yet.
+    fn fresh(&mut self) -> JType {
+        self.bindings.push(None);
+        JType::Var(self.bindings.len() - 1)
+    }
+
+    /// Swaps the type variables in a type from another checker for
+    /// fresh ones of this checker. `vars` keeps track of what each
+    /// one turned into, so a variable showing up twice stays the same.
+    fn instantiate(&mut self, t: &JType, vars: &mut HashMap<usize, JType>) -> JType {
+        match t {
+            JType::Var(n) => match vars.get(n) {
+                Some(t) => t.clone(),
+                None => {
+                    let fresh = self.fresh();
+                    vars.insert(*n, fresh.clone());
+                    fresh
+                },
+            },
+            JType::List(t) => JType::List(Box::new(self.instantiate(t, vars))),
+            JType::Map(k, v) => JType::Map(Box::new(self.instantiate(k, vars)), Box::new(self.instantiate(v, vars))),
+            JType::Tuple(types) => JType::Tuple(types.iter().map(|t| self.instantiate(t, vars)).collect()),
+            JType::Fn(params, ret) => {
+                let params = params.iter().map(|t| self.instantiate(t, vars)).collect();
+                JType::Fn(params, Box::new(self.instantiate(ret, vars)))
+            },
+            JType::Optional(t) => JType::Optional(Box::new(self.instantiate(t, vars))),
+            _ => t.clone(),
+        }
+    }
+
+    /// Replaces type variables with the types they're bound to,
+    /// all the way down. Unbound variables are left as they are.
+    pub fn resolve(&self, t: &JType) -> JType {
+        let resolve_all = |types: &[JType]| types.iter().map(|t| self.resolve(t)).collect();
+        match t {
+            JType::Var(n) => match &self.bindings[*n] {
+                Some(bound) => self.resolve(bound),
+                None => t.clone(),
+            },
+            JType::List(t) => JType::List(Box::new(self.resolve(t))),
+            JType::Map(k, v) => JType::Map(Box::new(self.resolve(k)), Box::new(self.resolve(v))),
+            JType::Tuple(types) => JType::Tuple(resolve_all(types)),
+            JType::Fn(params, ret) => JType::Fn(resolve_all(params), Box::new(self.resolve(ret))),
+            JType::Optional(t) => JType::Optional(Box::new(self.resolve(t))),
+            _ => t.clone(),
+        }
+    }
+
+    /// Returns true if the type variable shows up somewhere in the type.
+    /// Binding a variable to a type containing itself would make
+    /// an infinite type, like List<List<List<...>>>.
+    fn occurs(&self, var: usize, t: &JType) -> bool {
+        match self.resolve(t) {
+            JType::Var(n) => n == var,
+            JType::List(t) | JType::Optional(t) => self.occurs(var, &t),
+            JType::Map(k, v) => self.occurs(var, &k) || self.occurs(var, &v),
+            JType::Tuple(types) => types.iter().any(|t| self.occurs(var, t)),
+            JType::Fn(params, ret) => params.iter().any(|t| self.occurs(var, t)) || self.occurs(var, &ret),
+            _ => false,
+        }
+    }
+
+    /// Tries to make two types the same by binding type variables.
+    /// Returns false if they can't be.
+    fn unify(&mut self, a: &JType, b: &JType) -> bool {
+        let unify_all = |checker: &mut Self, a: &[JType], b: &[JType]|
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| checker.unify(a, b));
+        match (self.resolve(a), self.resolve(b)) {
+            (JType::Var(x), JType::Var(y)) if x == y => true,
+            (JType::Var(x), t) | (t, JType::Var(x)) => {
+                if self.occurs(x, &t) {
+                    return false;
+                }
+                // Whatever it turns into has to be allowed too, eg a number stays a number
+                if let Some(constraint) = self.constraints.get(&x).copied() {
+                    if !self.fits(&t, constraint) {
+                        return false;
+                    }
+                }
+                self.bindings[x] = Some(t);
+                self.settle()
+            },
+            (JType::List(a), JType::List(b)) | (JType::Optional(a), JType::Optional(b)) => self.unify(&a, &b),
+            (JType::Map(ak, av), JType::Map(bk, bv)) => self.unify(&ak, &bk) && self.unify(&av, &bv),
+            (JType::Tuple(a), JType::Tuple(b)) => unify_all(self, &a, &b),
+            (JType::Fn(a_params, a_ret), JType::Fn(b_params, b_ret)) =>
+                unify_all(self, &a_params, &b_params) && self.unify(&a_ret, &b_ret),
+            (a, b) => a == b,
+        }
+    }
+
+    /// Returns true if a type is allowed by a constraint. Type variables
+    /// always are, but they get the constraint too so they stay that way.
+    fn fits(&mut self, t: &JType, constraint: Constraint) -> bool {
+        match self.resolve(t) {
+            JType::Var(n) => {
+                let current = self.constraints.get(&n).copied();
+                if current.is_none_or(|current| current < constraint) {
+                    self.constraints.insert(n, constraint);
+                    return self.settle();
+                }
+                true
+            },
+            JType::Int | JType::Float => true,
+            JType::Char | JType::String => constraint <= Constraint::Ordered,
+            JType::Bool => constraint == Constraint::Comparable,
+            // Optionals are compared by what they hold, but can't be ordered
+            JType::Optional(inner) => constraint == Constraint::Comparable && self.fits(&inner, constraint),
+            _ => false,
+        }
+    }
+
+    /// Works out the type of arithmetic on two numbers, if
+    /// enough is known about them yet. Ints only stay ints
+    /// if both sides are, like at runtime.
+    fn promoted(&self, a: &JType, b: &JType) -> Option<JType> {
+        match (self.resolve(a), self.resolve(b)) {
+            (JType::Float, _) | (_, JType::Float) => Some(JType::Float),
+            (JType::Int, t) | (t, JType::Int) => Some(t),
+            (a, b) if a == b => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Makes both sides of a comparison fit together, if enough is
+    /// known about them yet. They need the same type, unless they're
+    /// both numbers, and optionals can be compared with what they hold.
+    fn compared(&mut self, a: &JType, b: &JType) -> Option<bool> {
+        let (a, b) = (self.resolve(a), self.resolve(b));
+        let numeric = |checker: &Self, t: &JType| match t {
+            JType::Var(n) => checker.constraints.get(n) == Some(&Constraint::Numeric),
+            t => is_numeric(t),
+        };
+        match (&a, &b) {
+            (JType::Var(x), JType::Var(y)) if x == y => Some(true),
+            (JType::Var(_), JType::Var(_)) if !numeric(self, &a) && !numeric(self, &b) => None,
+            _ if numeric(self, &a) || numeric(self, &b) => Some(
+                (self.fits(&a, Constraint::Numeric) && self.fits(&b, Constraint::Numeric))
+                    || self.holds(&a, &b) || self.holds(&b, &a)),
+            _ => Some(self.unify(&a, &b) || self.holds(&a, &b) || self.holds(&b, &a)),
+        }
+    }
+
+    /// Works out whatever was waiting on type variables that are
+    /// known now. Returns false if one of them can't work anymore.
+    fn settle(&mut self) -> bool {
+        let mut fine = true;
+        let mut progress = true;
+        while progress {
+            progress = false;
+            // Settling can bind more variables, which settles again,
+            // so the list is taken out while going through it
+            for op in std::mem::take(&mut self.pending) {
+                let settled = match &op {
+                    Pending::Promote(a, b, result) => match self.promoted(a, b) {
+                        Some(t) => Some(self.unify(result, &t)),
+                        // Ints only come out if both sides are ints
+                        None if self.resolve(result) == JType::Int =>
+                            Some(self.unify(a, &JType::Int) && self.unify(b, &JType::Int)),
+                        None => None,
+                    },
+                    Pending::Compare(a, b) => self.compared(a, b),
+                };
+                match settled {
+                    Some(ok) => {
+                        fine &= ok;
+                        progress = true;
+                    },
+                    None => self.pending.push(op),
+                }
+            }
+        }
+        fine
+    }
+
+    /// Returns true if a value of the found type can be used where the
+    /// expected type is, binding type variables to make it work if it can.
+    fn assignable(&mut self, expected: &JType, found: &JType) -> bool {
+        let (expected, found) = (self.resolve(expected), self.resolve(found));
+        // Types that are fully known can use the looser assignability
+        // rules, eg an int going into an int?
+        if found.is_assignable_to(&expected) {
+            return true;
+        }
+        match (&expected, &found) {
+            // Ones that aren't known yet are looked at piece by piece,
+            // so `fn(x) { x }` can still go into a fn(int) -> int?.
+            // Parameters go the other way, the same as in is_assignable_to.
+            (JType::Fn(expected_params, expected_ret), JType::Fn(found_params, found_ret))
+                if expected_params.len() == found_params.len() =>
+                expected_params.iter().zip(found_params).all(|(expected, found)| self.assignable(found, expected))
+                    && self.assignable(expected_ret, found_ret),
+            (JType::Tuple(expected), JType::Tuple(found)) if expected.len() == found.len() =>
+                expected.iter().zip(found).all(|(expected, found)| self.assignable(expected, found)),
+            // Otherwise they have to be made equal. Anything can go into
+            // an optional of its own type, which is how types that aren't
+            // known yet find out what they are.
+            (JType::Optional(inner), found) if !matches!(found, JType::Optional(_)) =>
+                self.unify(&expected, found) || self.unify(inner, found),
+            _ => self.unify(&expected, &found),
+        }
+    }
+
+    /// Checks that something has a type that can be used
+    /// where something else expects one, reporting both
+    /// of them if it doesn't.
+    fn expect(&mut self, expected: &JType, expected_pos: Position, found: &JType, found_expr: &Expr) -> bool {
+        if self.assignable(expected, found) {
+            return true;
+        }
+        let (expected, found) = (self.resolve(expected), self.resolve(found));
+        let mut error = Diagnostic::error(codes::TYPE_MISMATCH,
+            format!("Expected {} but found {}", self.show(&expected), self.show(&found)), *found_expr.pos())
+            .with_label(expected_pos, format!("{} is expected because of this", self.show(&expected)));
+        // Ints don't turn into floats by themselves
+        if let (JType::Float, Expr::Int(_, n)) = (&expected, found_expr) {
+            error = error.with_help(format!("Write it as '{}.0' to make it a float", n));
+        }
+        self.diagnostics.push(error);
+        false
+    }
+
+    /// Declares a variable in the innermost scope.
+    /// Declaring the same name twice in one scope is an error.
+    pub fn declare(&mut self, name: &str, t: JType, pos: Position) {
+        let scope = self.scopes.last_mut().expect("checker always has a scope");
+        if let Some(previous) = scope.get(name) {
+            let previous = previous.pos;
+            self.diagnostics.push(Diagnostic::error(codes::REDECLARED_VARIABLE,
+                format!("'{}' is already declared", name), pos)
+                .with_label(previous, format!("'{}' was first declared here", name))
+                .with_help("Pick a different name, or remove one of the declarations"));
+            return;
+        }
+        scope.insert(name.to_string(), Symbol { t, pos, vars: Vec::new(), assignable: false });
+    }
+
+    /// Declares a variable that can be assigned to later.
+    fn declare_variable(&mut self, name: &str, t: JType, pos: Position) {
+        self.declare(name, t, pos);
+        let scope = self.scopes.last_mut().expect("checker always has a scope");
+        match scope.get_mut(name) {
+            // It wasn't declared if the name was taken
+            Some(symbol) if symbol.pos == pos => symbol.assignable = true,
+            _ => {},
+        }
+    }
+
+    /// Checks an expression tree, returning its type
+    /// along with every problem found in it.
+    pub fn check(&mut self, expr: &Expr) -> Checked {
+        let t = match expr {
+            // The outermost body shares the checker's scope,
+            // so its declarations are still around afterwards.
+            Expr::Body(_, exprs) => self.body(exprs),
+            _ => self.expr(expr),
+        };
+        Checked { t, diagnostics: std::mem::take(&mut self.diagnostics) }
+    }
+
+    /// Checks every expression in a body.
+    /// A body takes the type of its last expression.
+    fn body(&mut self, exprs: &[Expr]) -> Option<JType> {
+        self.declare_types(exprs);
+        let mut body_type = Some(JType::Void);
+        for expr in exprs {
+            body_type = self.expr(expr);
+        }
+        body_type
+    }
+
+    /// Checks a binary arithmetic operation.
+    /// `verb` is only used for the error message, eg "add".
+    fn arithmetic(&mut self, pos: &Position, verb: &str, left: &Expr, right: &Expr) -> Option<JType> {
+        // Check both sides before giving up, so errors in either get reported
+        let left_type = self.expr(left);
+        let right_type = self.expr(right);
+        let (left_type, right_type) = (self.resolve(&left_type?), self.resolve(&right_type?));
+        // A side that isn't known yet has to be a number. What comes out
+        // follows the same rule as below, so it might not be known either
+        // until both sides are.
+        if let (JType::Var(_), t) | (t, JType::Var(_)) = (&left_type, &right_type) {
+            if matches!(t, JType::Var(_)) || is_numeric(t) {
+                self.fits(&left_type, Constraint::Numeric);
+                self.fits(&right_type, Constraint::Numeric);
+                if let Some(t) = self.promoted(&left_type, &right_type) {
+                    return Some(t);
+                }
+                let result = self.fresh();
+                self.fits(&result, Constraint::Numeric);
+                self.pending.push(Pending::Promote(left_type, right_type, result.clone()));
+                return Some(result);
+            }
+        }
+        // If they're both numbers
+        if is_numeric(&left_type) && is_numeric(&right_type) {
+            // If one is a float, the outcome will be a float.
+            // Otherwise it will be an integer.
+            return if left_type == JType::Float || right_type == JType::Float {
+                Some(JType::Float)
+            } else {
+                Some(JType::Int)
+            };
+        }
+        let (left_shown, right_shown) = (self.show(&left_type), self.show(&right_type));
+        self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+            format!("You cannot {} {} and {}", verb, left_shown, right_shown), *pos)
+            .with_label(*left.pos(), format!("This is {}", left_shown))
+            .with_label(*right.pos(), format!("This is {}", right_shown)));
+        None
+    }
+
+    /// Checks a comparison, which always gives a bool.
+    /// Anything can be compared for equality with a value of the same
+    /// type, but only numbers, chars and strings can be ordered.
+    fn comparison(&mut self, pos: &Position, symbol: &str, left: &Expr, right: &Expr) -> Option<JType> {
+        let left_type = self.expr(left);
+        let right_type = self.expr(right);
+        let (left_type, right_type) = (self.resolve(&left_type?), self.resolve(&right_type?));
+        // Ints get turned into floats when compared with one, and
+        // optionals can be compared with what they hold, eg `x == 1`.
+        // Otherwise both sides need the same type. Two sides that aren't
+        // known yet could still turn out to be an int and a float, so
+        // that waits until one of them is.
+        let unified = match self.compared(&left_type, &right_type) {
+            Some(unified) => unified,
+            None => {
+                self.pending.push(Pending::Compare(left_type.clone(), right_type.clone()));
+                true
+            },
+        };
+        if !unified {
+            self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                format!("You cannot compare '{}' and '{}'", left_type, right_type), *pos)
+                .with_label(*left.pos(), format!("This is '{}'", left_type))
+                .with_label(*right.pos(), format!("This is '{}'", right_type)));
+            return None;
+        }
+        let optional = [&left_type, &right_type].iter().any(|t| matches!(self.resolve(t), JType::Optional(_)));
+        // Optionals are compared by what they hold
+        let mut t = self.resolve(&left_type);
+        while let JType::Optional(inner) = t {
+            t = *inner;
+        }
+        if t == JType::Void {
+            self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                "You cannot compare 'void' values", *pos));
+            return None;
+        }
+        if let JType::Fn(_, _) = t {
+            self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                "You cannot compare functions", *pos));
+            return None;
+        }
+        if let JType::List(_) = t {
+            self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                "You cannot compare lists", *pos));
+            return None;
+        }
+        if let JType::Tuple(_) = t {
+            self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                "You cannot compare tuples", *pos)
+                .with_help("Compare what they hold instead"));
+            return None;
+        }
+        if let JType::Map(_, _) = t {
+            self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                "You cannot compare maps", *pos));
+            return None;
+        }
+        if let JType::Module(_) = t {
+            self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                "You cannot compare modules", *pos));
+            return None;
+        }
+        if let JType::User(_) = t {
+            self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                format!("You cannot compare '{}' values", t), *pos)
+                .with_help("Compare their fields instead"));
+            return None;
+        }
+        let ordered = !matches!(symbol, "==" | "!=");
+        if ordered && optional {
+            self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                format!("You cannot use '{}' on optionals", symbol), *pos)
+                .with_help("Use '??' to give it a value for when it's null first"));
+            return None;
+        }
+        // Sides that aren't known yet can only turn into something it works on
+        let constraint = if ordered { Constraint::Ordered } else { Constraint::Comparable };
+        if !self.fits(&left_type, constraint) || !self.fits(&right_type, constraint) {
+            self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                format!("You cannot use '{}' on '{}'", symbol, t), *pos)
+                .with_help("Only numbers, chars and strings can be ordered"));
+            return None;
+        }
+        Some(JType::Bool)
+    }
+
+    /// Returns true if `optional` is an optional holding the other type,
+    /// binding type variables to make it one if it can.
+    fn holds(&mut self, optional: &JType, t: &JType) -> bool {
+        match self.resolve(optional) {
+            JType::Optional(inner) => self.unify(&inner, t),
+            _ => false,
+        }
+    }
+
+    /// Checks `a ?? b`. The left side has to be an optional, and the
+    /// right side is used instead when it's null, so it needs the type
+    /// the optional holds. That's the type of the whole thing.
+    fn coalesce(&mut self, pos: &Position, left: &Expr, right: &Expr) -> Option<JType> {
+        let left_type = self.expr(left);
+        let right_type = self.expr(right);
+        let left_type = self.resolve(&left_type?);
+        let inner = match &left_type {
+            JType::Optional(inner) => (**inner).clone(),
+            t => {
+                // Something that isn't known yet can turn out to be one
+                let inner = self.fresh();
+                if !self.unify(t, &JType::Optional(Box::new(inner.clone()))) {
+                    self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                        format!("'??' only works on optionals, but this is {}", self.show(t)), *left.pos())
+                        .with_label(*pos, "'??' is used here")
+                        .with_help("It gives a value to use when an optional is null"));
+                    return None;
+                }
+                inner
+            },
+        };
+        self.expect(&inner, *left.pos(), &right_type?, right).then(|| self.resolve(&inner))
+    }
+
+    /// Checks that an expression has the given type, reporting
+    /// a mismatch at the expression itself since that's what
+    /// needs fixing. `why` adds the reason to the error.
+    fn require(&mut self, expr: &Expr, expected: &JType, why: impl FnOnce(Diagnostic) -> Diagnostic) -> bool {
+        let t = match self.expr(expr) {
+            Some(t) => t,
+            None => return false,
+        };
+        if self.unify(expected, &t) {
+            return true;
+        }
+        let t = self.show(&t);
+        self.diagnostics.push(why(Diagnostic::error(codes::TYPE_MISMATCH,
+            format!("Expected '{}' but found {}", expected, t), *expr.pos())));
+        false
+    }
+
+    /// Checks `&&` or `||`. Both sides are checked
+    /// even if the left one is wrong.
+    fn logical(&mut self, pos: &Position, symbol: &str, left: &Expr, right: &Expr) -> Option<JType> {
+        let why = |e: Diagnostic| e.with_label(*pos, format!("'{}' only works on bools", symbol));
+        let left_ok = self.require(left, &JType::Bool, why);
+        let right_ok = self.require(right, &JType::Bool, why);
+        (left_ok && right_ok).then_some(JType::Bool)
+    }
+
+    /// Checks an if expression. With an else branch both branches
+    /// need the same type, which the if takes. Without one the
+    /// value of the branch is thrown away, so the if is void.
+    fn if_expr(&mut self, condition: &Expr, then: &Expr, otherwise: Option<&Expr>) -> Option<JType> {
+        let ok = self.require(condition, &JType::Bool,
+            |e| e.with_help("Conditions have to be 'bool'"));
+        let then_type = self.expr(then);
+        let otherwise = match otherwise {
+            Some(otherwise) => otherwise,
+            None => return ok.then_some(JType::Void),
+        };
+        let otherwise_type = self.expr(otherwise);
+        let (then_type, otherwise_type) = (then_type?, otherwise_type?);
+        // A branch that always jumps away never gives a value,
+        // so the if can only end up with the other one's
+        if then.diverges() {
+            return ok.then(|| self.resolve(&otherwise_type));
+        }
+        if otherwise.diverges() {
+            return ok.then(|| self.resolve(&then_type));
+        }
+        // Point at the values the branches end with rather than the whole branches
+        if !self.expect(&then_type, *tail(then).pos(), &otherwise_type, tail(otherwise)) {
+            return None;
+        }
+        ok.then(|| self.resolve(&then_type))
+    }
+
+    /// Checks the body of a loop with the given label.
+    /// Loops are statements, so the body's value is thrown away.
+    fn loop_body(&mut self, label: &Option<LoopLabel>, body: &Expr) -> bool {
+        self.loops.push(label.as_ref().map(|label| label.name.clone()));
+        let t = self.expr(body);
+        self.loops.pop();
+        t.is_some()
+    }
+
+    /// Checks that `break` or `continue` is inside a loop,
+    /// and that the loop it names exists.
+    fn jump(&mut self, pos: &Position, keyword: &str, label: &Option<LoopLabel>) -> Option<JType> {
+        if self.loops.is_empty() {
+            self.diagnostics.push(Diagnostic::error(codes::MISPLACED_BREAK,
+                format!("'{}' can only be used inside a loop", keyword), *pos));
+            return None;
+        }
+        if let Some(label) = label {
+            if !self.loops.iter().any(|l| l.as_ref() == Some(&label.name)) {
+                self.diagnostics.push(Diagnostic::error(codes::UNDEFINED_LABEL,
+                    format!("There is no loop labelled '{}' around this '{}'", label.name, keyword), label.pos)
+                    .with_help(format!("Label a loop with '{}: while ...'", label.name)));
+                return None;
+            }
+        }
+        Some(JType::Void)
+    }
+
+    /// Gets the parameter and return types of a function from
+    /// its annotations. Anything left out gets a type variable.
+    fn signature(&mut self, function: &Function) -> (Vec<JType>, JType) {
+        let params = function.params.iter().map(|param| match &param.annotation {
+            Some(annotation) => {
+                self.known(&annotation.t, annotation.pos);
+                annotation.t.clone()
+            },
+            None => self.fresh(),
+        }).collect();
+        let ret = match &function.ret {
+            Some(annotation) => {
+                self.known(&annotation.t, annotation.pos);
+                annotation.t.clone()
+            },
+            None => self.fresh(),
+        };
+        (params, ret)
+    }
+
+    /// Checks that every struct named in a written out type
+    /// has been declared, reporting the ones that haven't.
+    fn known(&mut self, t: &JType, pos: Position) -> bool {
+        match t {
+            JType::User(name) if self.type_pos(name).is_none() => {
+                self.diagnostics.push(Diagnostic::error(codes::UNDEFINED_TYPE,
+                    format!("There is no struct or enum called '{}'", name), pos)
+                    .with_help(format!("Declare it with 'struct {} {{ ... }}'", name)));
+                false
+            },
+            JType::List(t) | JType::Optional(t) => self.known(t, pos),
+            JType::Map(k, v) => self.known(k, pos) & self.known(v, pos),
+            JType::Tuple(types) => types.iter().fold(true, |ok, t| self.known(t, pos) & ok),
+            JType::Fn(params, ret) => params.iter().fold(true, |ok, t| self.known(t, pos) & ok) & self.known(ret, pos),
+            _ => true,
+        }
+    }
+
+    /// Finds where a struct or enum was declared.
+    fn type_pos(&self, name: &str) -> Option<Position> {
+        self.structs.get(name).map(|info| info.pos).or_else(|| self.enums.get(name).map(|info| info.pos))
+    }
+
+    /// Reports a struct or enum with a name that's already taken.
+    /// Structs and enums share names, since they're both types.
+    fn redeclared_type(&mut self, pos: &Position, name: &str) -> bool {
+        let previous = match self.type_pos(name) {
+            Some(previous) => previous,
+            None => return false,
+        };
+        self.diagnostics.push(Diagnostic::error(codes::REDECLARED_VARIABLE,
+            format!("'{}' is already declared", name), *pos)
+            .with_label(previous, format!("'{}' was first declared here", name))
+            .with_help("Pick a different name, or remove one of the declarations"));
+        true
+    }
+
+    /// Adds every struct and enum declared in a body before
+    /// anything in it is checked, so they can be used in types
+    /// above where they're declared, like in `fn f(p: Point)`
+    /// before `struct Point`. Their constructors and methods
+    /// are still only checked where they're declared.
+    fn declare_types(&mut self, exprs: &[Expr]) {
+        let decls: Vec<&Expr> = exprs.iter().map(Expr::unexported).collect();
+        // Names first, so fields and variants can use any of them
+        for expr in &decls {
+            match expr {
+                Expr::Struct(pos, decl) if !self.redeclared_type(pos, &decl.name) => {
+                    self.structs.insert(decl.name.clone(), StructInfo { fields: Vec::new(), methods: Vec::new(), pos: *pos });
+                },
+                Expr::Enum(pos, decl) if !self.redeclared_type(pos, &decl.name) => {
+                    let variants = decl.variants.iter().map(|variant| (variant.name.clone(), variant.fields.clone())).collect();
+                    self.enums.insert(decl.name.clone(), EnumInfo { variants, pos: *pos });
+                },
+                _ => {},
+            }
+        }
+        for expr in decls {
+            match expr {
+                Expr::Struct(pos, decl) if self.type_pos(&decl.name) == Some(*pos) => {
+                    let mut info = StructInfo { fields: Vec::new(), methods: Vec::new(), pos: *pos };
+                    for field in &decl.fields {
+                        self.known(&field.annotation.t, field.annotation.pos);
+                        info.fields.push((field.name.clone(), field.annotation.t.clone()));
+                    }
+                    // Every signature is known before any body is checked,
+                    // so methods can call each other through self
+                    for method in &decl.methods {
+                        let (params, ret) = self.signature(&method.function);
+                        info.methods.push((method.name.clone(), JType::Fn(params, Box::new(ret))));
+                    }
+                    self.structs.insert(decl.name.clone(), info);
+                },
+                Expr::Enum(pos, decl) if self.type_pos(&decl.name) == Some(*pos) => for variant in &decl.variants {
+                    for t in &variant.fields {
+                        self.known(t, variant.pos);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Checks a struct declaration. Its name is declared as a
+    /// constructor taking the fields in order, and each method
+    /// is checked with `self` being an instance of it.
+    fn struct_decl(&mut self, pos: &Position, decl: &StructDecl) -> Option<JType> {
+        let info = match self.structs.get(&decl.name) {
+            // Added by the body it's in
+            Some(info) if info.pos == *pos => info.clone(),
+            // Already reported as taken
+            _ => return None,
+        };
+        let t = JType::User(decl.name.clone());
+        let mut ok = true;
+        let mut names: HashMap<&str, Position> = HashMap::new();
+        let fields = decl.fields.iter().map(|field| (&field.name, field.pos));
+        for (name, pos) in fields.chain(decl.methods.iter().map(|method| (&method.name, method.pos))) {
+            if let Some(previous) = names.insert(name, pos) {
+                self.diagnostics.push(Diagnostic::error(codes::REDECLARED_VARIABLE,
+                    format!("'{}' already has something called '{}'", decl.name, name), pos)
+                    .with_label(previous, format!("'{}' was first declared here", name)));
+                ok = false;
+            }
+        }
+        let constructor = JType::Fn(info.fields.iter().map(|(_, t)| t.clone()).collect(), Box::new(t.clone()));
+        self.declare(&decl.name, constructor, *pos);
+        for (method, (_, signature)) in decl.methods.iter().zip(&info.methods) {
+            let (params, ret) = match signature {
+                JType::Fn(params, ret) => (params, ret),
+                _ => unreachable!("methods are always functions"),
+            };
+            self.push_scope();
+            self.declare("self", t.clone(), method.pos);
+            ok &= self.function(&method.pos, &method.function, params, ret);
+            self.pop_scope();
+        }
+        ok.then_some(JType::Void)
+    }
+
+    /// Checks a field access or a method lookup, like `point.x`.
+    fn field(&mut self, object: &Expr, member: &Member) -> Option<JType> {
+        let t = self.expr(object)?;
+        let t = self.resolve(&t);
+        let name = match &t {
+            JType::User(name) => name,
+            JType::Module(module) => return self.exported(module, member),
+            JType::Tuple(types) => return self.item(types, member),
+            // Fields can't be used to work out which struct something is
+            JType::Var(_) => {
+                self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                    format!("The type of this has to be known to use '.{}' on it", member.name), *object.pos())
+                    .with_help("Write its type out, eg 'p: Point'"));
+                return None;
+            },
+            _ => {
+                self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                    format!("'{}' doesn't have fields", t), member.pos)
+                    .with_label(*object.pos(), format!("This is '{}'", t)));
+                return None;
+            },
+        };
+        let info = match self.structs.get(name) {
+            Some(info) => info,
+            None => {
+                self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                    format!("'{}' doesn't have fields", t), member.pos)
+                    .with_help("Use 'match' to get at what a variant holds"));
+                return None;
+            },
+        };
+        let found = info.fields.iter().chain(&info.methods).find(|(field, _)| *field == member.name);
+        if let Some((_, t)) = found {
+            return Some(t.clone());
+        }
+        let mut error = Diagnostic::error(codes::UNKNOWN_FIELD,
+            format!("'{}' doesn't have a field called '{}'", name, member.name), member.pos)
+            .with_label(info.pos, format!("'{}' is declared here", name));
+        if !info.fields.is_empty() {
+            let fields: Vec<&str> = info.fields.iter().map(|(field, _)| field.as_str()).collect();
+            error = error.with_help(format!("Its fields are {}", fields.join(", ")));
+        }
+        self.diagnostics.push(error);
+        None
+    }
+
+    /// Finds the type of an item of a tuple, like `pair.0`.
+    fn item(&mut self, types: &[JType], member: &Member) -> Option<JType> {
+        if let Some(t) = member.name.parse::<usize>().ok().and_then(|i| types.get(i)) {
+            return Some(t.clone());
+        }
+        let t = JType::Tuple(types.to_vec());
+        let mut error = Diagnostic::error(codes::UNKNOWN_FIELD,
+            format!("'{}' doesn't have a field called '{}'", t, member.name), member.pos);
+        if !types.is_empty() {
+            error = error.with_help(format!("Its fields are numbered 0 to {}", types.len() - 1));
+        }
+        self.diagnostics.push(error);
+        None
+    }
+
+    /// Checks the body of a function against its signature.
+    /// `pos` is pointed at for the return type if it isn't written out.
+    fn function(&mut self, pos: &Position, function: &Function, params: &[JType], ret: &JType) -> bool {
+        let ret_pos = function.ret.as_ref().map_or(*pos, |annotation| annotation.pos);
+        self.push_scope();
+        for (param, t) in function.params.iter().zip(params) {
+            self.declare_variable(&param.name, t.clone(), param.pos);
+        }
+        // Loops around the function can't be broken out of from inside it
+        let loops = std::mem::take(&mut self.loops);
+        self.returns.push((ret.clone(), ret_pos));
+        let body_type = self.expr(&function.body);
+        self.returns.pop();
+        self.loops = loops;
+        self.pop_scope();
+        let body_type = match body_type {
+            Some(t) => t,
+            None => return false,
+        };
+        // Bodies that always return were already checked by their returns
+        function.body.diverges() || self.expect(ret, ret_pos, &body_type, tail(&function.body))
+    }
+
+    /// Checks a `return`, whose value has to match
+    /// the return type of the function it's in.
+    fn return_expr(&mut self, expr: &Expr, value: Option<&Expr>) -> Option<JType> {
+        let found = match value {
+            Some(value) => self.expr(value),
+            None => Some(JType::Void),
+        };
+        let (ret, ret_pos) = match self.returns.last() {
+            Some(ret) => ret.clone(),
+            None => {
+                self.diagnostics.push(Diagnostic::error(codes::MISPLACED_RETURN,
+                    "'return' can only be used inside a function", *expr.pos()));
+                return None;
+            },
+        };
+        // A bare return gets pointed at itself
+        self.expect(&ret, ret_pos, &found?, value.unwrap_or(expr)).then_some(JType::Void)
+    }
+
+    /// Checks a call, which needs the right number of
+    /// arguments with the types the function expects.
+    fn call(&mut self, pos: &Position, callee: &Expr, args: &[Expr]) -> Option<JType> {
+        let callee_type = self.expr(callee);
+        let arg_types: Vec<Option<JType>> = args.iter().map(|arg| self.expr(arg)).collect();
+        let callee_type = self.resolve(&callee_type?);
+        // Point at the declaration when calling something by name
+        let declared = match callee {
+            Expr::Var(_, name) => self.symbol(name).map(|symbol| (name.clone(), symbol.pos)),
+            _ => None,
+        };
+        let (params, ret) = match callee_type {
+            JType::Fn(params, ret) => (params, ret),
+            // Something that isn't known yet must be a function
+            // taking whatever it's given
+            JType::Var(_) => {
+                let arg_types = arg_types.into_iter().collect::<Option<Vec<_>>>()?;
+                let ret = self.fresh();
+                self.unify(&callee_type, &JType::Fn(arg_types, Box::new(ret.clone())));
+                return Some(ret);
+            },
+            t => {
+                self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                    format!("You cannot call '{}'", t), *callee.pos())
+                    .with_help("Only functions can be called"));
+                return None;
+            },
+        };
+        if params.len() != args.len() {
+            let mut error = Diagnostic::error(codes::ARGUMENT_COUNT,
+                format!("Expected {} argument{} but found {}", params.len(),
+                    if params.len() == 1 { "" } else { "s" }, args.len()), *pos);
+            if let Some((name, declared)) = declared {
+                error = error.with_label(declared, format!("'{}' is declared here", name));
+            }
+            self.diagnostics.push(error);
+            return None;
+        }
+        let expected_pos = declared.map_or(*callee.pos(), |(_, pos)| pos);
+        let mut ok = true;
+        for ((param, arg), t) in params.iter().zip(args).zip(arg_types) {
+            ok &= match t {
+                Some(t) => self.expect(param, expected_pos, &t, arg),
+                None => false,
+            };
+        }
+        ok.then(|| self.resolve(&ret))
+    }
+
+    /// Finds something a module exports, reporting it if the
+    /// module doesn't have it or keeps it to itself.
+    fn exported(&mut self, module: &str, member: &Member) -> Option<JType> {
+        let exports = match self.modules.get(module) {
+            Some(exports) => exports,
+            // Modules that weren't imported are libraries
+            None => return self.native(module, member),
+        };
+        if let Some(symbol) = exports.values.get(&member.name) {
+            let symbol = symbol.clone();
+            return Some(self.instance(&symbol));
+        }
+        let error = if exports.enums.contains_key(&member.name) {
+            Diagnostic::error(codes::TYPE_MISMATCH,
+                format!("'{}' is an enum, so it can't be used as a value", member.name), member.pos)
+                .with_help(format!("Use one of its variants, like '{}::...'", member.name))
+        } else if let Some(pos) = exports.private.get(&member.name) {
+            Diagnostic::error(codes::NOT_EXPORTED,
+                format!("'{}' isn't exported from '{}'", member.name, module), member.pos)
+                .with_label(*pos, format!("'{}' is declared here", member.name))
+                .with_help("Add 'export' in front of its declaration")
+        } else {
+            Diagnostic::error(codes::UNDEFINED_VARIABLE,
+                format!("'{}' doesn't have anything called '{}'", module, member.name), member.pos)
+        };
+        self.diagnostics.push(error);
+        None
+    }
+
+    /// Finds the type of a native in a library,
+    /// reporting it if the library doesn't have it.
+    fn native(&mut self, library: &str, member: &Member) -> Option<JType> {
+        let library = stdlib::library(library)?;
+        if let Some(native) = library.natives.iter().find(|native| native.name == member.name) {
+            return Some(self.instantiate(&native.t, &mut HashMap::new()));
+        }
+        let names: Vec<&str> = library.natives.iter().map(|native| native.name).collect();
+        self.diagnostics.push(Diagnostic::error(codes::UNDEFINED_VARIABLE,
+            format!("'{}' doesn't have anything called '{}'", library.name, member.name), member.pos)
+            .with_help(format!("It has {}", names.join(", "))));
+        None
+    }
+
+    /// Checks a list. Every item needs the same type.
+    fn list(&mut self, items: &[Expr]) -> Option<JType> {
+        let items: Vec<&Expr> = items.iter().collect();
+        Some(JType::List(Box::new(self.same_type(&items)?)))
+    }
+
+    /// Checks a map. Every key needs the same type, and so does every value.
+    fn map(&mut self, entries: &[(Expr, Expr)]) -> Option<JType> {
+        let keys: Vec<&Expr> = entries.iter().map(|(key, _)| key).collect();
+        let values: Vec<&Expr> = entries.iter().map(|(_, value)| value).collect();
+        // Both checked before giving up
+        let (key, value) = (self.same_type(&keys), self.same_type(&values));
+        Some(JType::Map(Box::new(key?), Box::new(value?)))
+    }
+
+    /// Checks expressions that all need the same type, returning it.
+    /// Without any to work it out from, it's a fresh type variable.
+    fn same_type(&mut self, items: &[&Expr]) -> Option<JType> {
+        let types: Vec<Option<JType>> = items.iter().map(|item| self.expr(item)).collect();
+        let mut ok = true;
+        let mut first: Option<(JType, &Expr)> = None;
+        for (&item, t) in items.iter().zip(types) {
+            let t = match t {
+                Some(t) => t,
+                None => {
+                    ok = false;
+                    continue;
+                },
+            };
+            match &first {
+                Some((expected, from)) => {
+                    let (expected, from) = (expected.clone(), *from.pos());
+                    ok &= self.expect(&expected, from, &t, item);
+                },
+                None => first = Some((t, item)),
+            }
+        }
+        if !ok {
+            return None;
+        }
+        // What an empty list holds gets worked out from how it's used
+        Some(match first {
+            Some((t, _)) => self.resolve(&t),
+            None => self.fresh(),
+        })
+    }
+
+    /// Checks an import. Types are looked up by name anywhere,
+    /// so the structs and enums a module exports come along with
+    /// either kind of import. Other names have to be asked for.
+    fn import(&mut self, pos: &Position, module: &Member, names: Option<&[Member]>) -> Option<JType> {
+        let exports = match self.modules.get(&module.name) {
+            Some(exports) => exports.clone(),
+            None => {
+                self.diagnostics.push(Diagnostic::error(codes::MODULE_NOT_FOUND,
+                    format!("There is no module called '{}'", module.name), module.pos));
+                return None;
+            },
+        };
+        let mut ok = true;
+        // The same declaration can come in through more than one import
+        for (name, info) in &exports.structs {
+            if self.type_pos(name) == Some(info.pos) {
+                continue;
+            }
+            if self.redeclared_type(pos, name) {
+                ok = false;
+                continue;
+            }
+            self.structs.insert(name.clone(), info.clone());
+        }
+        for (name, info) in &exports.enums {
+            if self.type_pos(name) == Some(info.pos) {
+                continue;
+            }
+            if self.redeclared_type(pos, name) {
+                ok = false;
+                continue;
+            }
+            self.enums.insert(name.clone(), info.clone());
+        }
+        let names = match names {
+            Some(names) => names,
+            None => {
+                self.declare(&module.name, JType::Module(module.name.clone()), module.pos);
+                return Some(JType::Void);
+            },
+        };
+        for name in names {
+            // Enums aren't values, their variants are used through the type
+            if exports.enums.contains_key(&name.name) {
+                continue;
+            }
+            match self.exported(&module.name, name) {
+                Some(t) => self.declare(&name.name, t, name.pos),
+                None => ok = false,
+            }
+        }
+        ok.then_some(JType::Void)
+    }
+
+    /// Checks an enum declaration.
+    fn enum_decl(&mut self, pos: &Position, decl: &EnumDecl) -> Option<JType> {
+        match self.enums.get(&decl.name) {
+            // Added by the body it's in
+            Some(info) if info.pos == *pos => {},
+            // Already reported as taken
+            _ => return None,
+        }
+        let mut ok = true;
+        let mut names: HashMap<&str, Position> = HashMap::new();
+        for variant in &decl.variants {
+            if let Some(previous) = names.insert(&variant.name, variant.pos) {
+                self.diagnostics.push(Diagnostic::error(codes::REDECLARED_VARIABLE,
+                    format!("'{}' already has a variant called '{}'", decl.name, variant.name), variant.pos)
+                    .with_label(previous, format!("'{}' was first declared here", variant.name)));
+                ok = false;
+            }
+        }
+        ok.then_some(JType::Void)
+    }
+
+    /// Finds what a variant of an enum holds, reporting
+    /// it if the enum or the variant doesn't exist.
+    fn variant(&mut self, pos: &Position, name: &str, variant: &Member) -> Option<(usize, Vec<JType>)> {
+        let info = match self.enums.get(name) {
+            Some(info) => info,
+            None => {
+                self.diagnostics.push(Diagnostic::error(codes::UNDEFINED_TYPE,
+                    format!("There is no enum called '{}'", name), *pos)
+                    .with_help(format!("Declare it first with 'enum {} {{ ... }}'", name)));
+                return None;
+            },
+        };
+        if let Some(index) = info.variants.iter().position(|(v, _)| *v == variant.name) {
+            return Some((index, info.variants[index].1.clone()));
+        }
+        let variants: Vec<&str> = info.variants.iter().map(|(v, _)| v.as_str()).collect();
+        let mut error = Diagnostic::error(codes::UNKNOWN_VARIANT,
+            format!("'{}' doesn't have a variant called '{}'", name, variant.name), variant.pos)
+            .with_label(info.pos, format!("'{}' is declared here", name));
+        if !variants.is_empty() {
+            error = error.with_help(format!("Its variants are {}", variants.join(", ")));
+        }
+        self.diagnostics.push(error);
+        None
+    }
+
+    /// Checks an assignment, which needs a variable that can be
+    /// assigned to and a value of the type it already has.
+    fn assign(&mut self, pos: &Position, name: &str, value: &Expr) -> Option<JType> {
+        let found = self.expr(value);
+        let target = Position::new(pos.file(), pos.start(), pos.start() + name.len());
+        let symbol = match self.symbol(name) {
+            Some(symbol) => symbol.clone(),
+            None if stdlib::global(name).is_some() => {
+                self.diagnostics.push(Diagnostic::error(codes::NOT_ASSIGNABLE,
+                    format!("'{}' is part of the standard library, so it can't be assigned to", name), target)
+                    .with_help(format!("Declare a new variable with 'let {} = ...' instead", name)));
+                return None;
+            },
+            None => {
+                self.diagnostics.push(Diagnostic::error(codes::UNDEFINED_VARIABLE,
+                    format!("'{}' is not defined", name), target)
+                    .with_help(format!("Declare it first with 'let {} = ...'", name)));
+                return None;
+            },
+        };
+        if !symbol.assignable {
+            self.diagnostics.push(Diagnostic::error(codes::NOT_ASSIGNABLE,
+                format!("'{}' can't be assigned to", name), target)
+                .with_label(symbol.pos, format!("'{}' is declared here", name))
+                .with_help("Only variables declared with 'let', parameters and match bindings can be assigned to"));
+            return None;
+        }
+        // Another type could break the uses that expect one of the others
+        if !symbol.vars.is_empty() {
+            self.diagnostics.push(Diagnostic::error(codes::NOT_ASSIGNABLE,
+                format!("'{}' can't be assigned to since it's used as more than one type", name), target)
+                .with_label(symbol.pos, format!("'{}' is declared here", name))
+                .with_help("Write out its type where it's declared to make it a single type"));
+            return None;
+        }
+        self.expect(&symbol.t, symbol.pos, &found?, value).then_some(JType::Void)
+    }
+
+    /// Checks a pattern against the type of the value it's
+    /// matched with, declaring the names it binds.
+    fn pattern(&mut self, pattern: &Pattern, t: &JType) -> bool {
+        let found = match pattern {
+            Pattern::Wildcard(_) => return true,
+            Pattern::Binding(pos, name) => {
+                self.declare_variable(name, t.clone(), *pos);
+                return true;
+            },
+            Pattern::Int(_, _) => JType::Int,
+            Pattern::Str(_, _) => JType::String,
+            Pattern::Char(_, _) => JType::Char,
+            Pattern::Bool(_, _) => JType::Bool,
+            Pattern::Variant(_, name, _, _) => JType::User(name.clone()),
+        };
+        let fields = match pattern {
+            Pattern::Variant(pos, name, variant, fields) => match self.variant(pos, name, variant) {
+                Some((_, types)) => Some((fields, types)),
+                None => return false,
+            },
+            _ => None,
+        };
+        if !self.unify(t, &found) {
+            let t = self.resolve(t);
+            self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                format!("Expected '{}' but found '{}'", t, found), *pattern.pos())
+                .with_help(format!("The value being matched is '{}'", t)));
+            return false;
+        }
+        let (fields, types) = match fields {
+            Some(fields) => fields,
+            None => return true,
+        };
+        if fields.len() != types.len() {
+            self.diagnostics.push(Diagnostic::error(codes::ARGUMENT_COUNT,
+                format!("Expected {} value{} in the pattern but found {}", types.len(),
+                    if types.len() == 1 { "" } else { "s" }, fields.len()), *pattern.pos()));
+            return false;
+        }
+        fields.iter().zip(&types).fold(true, |ok, (field, t)| self.pattern(field, t) & ok)
+    }
+
+    /// Boils a pattern down to what matters for coverage.
+    /// Only works on patterns that have been checked.
+    fn space(&self, pattern: &Pattern) -> Space {
+        match pattern {
+            Pattern::Wildcard(_) | Pattern::Binding(_, _) => Space::Any,
+            Pattern::Int(_, n) => Space::Ctor(Ctor::Int(*n), Vec::new()),
+            Pattern::Str(_, s) => Space::Ctor(Ctor::Str(s.clone()), Vec::new()),
+            Pattern::Char(_, c) => Space::Ctor(Ctor::Char(*c), Vec::new()),
+            Pattern::Bool(_, b) => Space::Ctor(Ctor::Bool(*b), Vec::new()),
+            Pattern::Variant(_, name, variant, fields) => {
+                let index = self.enums[name].variants.iter().position(|(v, _)| *v == variant.name)
+                    .expect("checked patterns have real variants");
+                Space::Ctor(Ctor::Variant(index), fields.iter().map(|field| self.space(field)).collect())
+            },
+        }
+    }
+
+    /// Checks that the arms of a match cover every value of the
+    /// type, and warns about arms that can never be reached.
+    fn coverage(&mut self, pos: &Position, t: &JType, arms: &[Arm]) -> bool {
+        let enums = &self.enums;
+        let variants = |name: &str| enums.get(name).map(|info| info.variants.clone());
+        let coverage = Coverage { variants: &variants };
+        let types = [t.clone()];
+        // Arms with a guard might not match, so they don't cover anything
+        let mut covered: Vec<Vec<Space>> = Vec::new();
+        let mut warnings = Vec::new();
+        for arm in arms {
+            let space = self.space(&arm.pattern);
+            if !coverage.useful(&covered, std::slice::from_ref(&space), &types) {
+                warnings.push(Diagnostic::warning(codes::UNREACHABLE_ARM,
+                    "This arm can never match", *arm.pattern.pos())
+                    .with_help("The arms before it already match everything it does"));
+            }
+            if arm.guard.is_none() {
+                covered.push(vec![space]);
+            }
+        }
+        let patterns: Vec<Space> = covered.into_iter().map(|mut row| row.remove(0)).collect();
+        let missing = coverage.uncovered(&patterns, t);
+        self.diagnostics.extend(warnings);
+        if missing.is_empty() {
+            return true;
+        }
+        let missing: Vec<String> = missing.iter().map(|pattern| format!("'{}'", pattern)).collect();
+        let listed = match missing.split_last() {
+            Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+            _ => missing.join(""),
+        };
+        self.diagnostics.push(Diagnostic::error(codes::NON_EXHAUSTIVE,
+            format!("This match doesn't cover {}", listed), *pos)
+            .with_help("Add arms for them, or a '_' arm to cover everything else"));
+        false
+    }
+
+    /// Checks a match. Every arm needs the same type,
+    /// which the match takes, unless it jumps away.
+    fn match_expr(&mut self, pos: &Position, value: &Expr, arms: &[Arm]) -> Option<JType> {
+        let value_type = self.expr(value);
+        let mut ok = value_type.is_some();
+        // Still check the arms when the value is broken
+        let t = value_type.unwrap_or_else(|| self.fresh());
+        let mut result: Option<(JType, &Expr)> = None;
+        for arm in arms {
+            self.push_scope();
+            ok &= self.pattern(&arm.pattern, &t);
+            if let Some(guard) = &arm.guard {
+                ok &= self.require(guard, &JType::Bool, |e| e.with_help("Guards have to be 'bool'"));
+            }
+            let body_type = self.expr(&arm.body);
+            self.pop_scope();
+            let body_type = match body_type {
+                Some(t) => t,
+                None => {
+                    ok = false;
+                    continue;
+                },
+            };
+            if arm.body.diverges() {
+                continue;
+            }
+            match result {
+                // Point at the values the arms end with
+                Some((ref expected, from)) => {
+                    let expected = expected.clone();
+                    ok &= self.expect(&expected, *tail(from).pos(), &body_type, tail(&arm.body));
+                },
+                None => result = Some((body_type, &arm.body)),
+            }
+        }
+        // Patterns that don't make sense can't be looked at for coverage
+        if !ok {
+            return None;
+        }
+        let t = self.resolve(&t);
+        if !self.coverage(pos, &t, arms) {
+            return None;
+        }
+        Some(result.map_or(JType::Void, |(t, _)| self.resolve(&t)))
+    }
+
+    /// Gets the type of an expression, declaring any variables in it.
+    /// Returns None if the expression has an error.
+    fn expr(&mut self, expr: &Expr) -> Option<JType> {
+        match expr {
+            Expr::Body(_, exprs) => {
+                self.push_scope();
+                let t = self.body(exprs);
+                self.pop_scope();
+                t
+            },
+            Expr::Let(pos, name, annotation, value) => {
+                let found = self.expr(value).map(|t| self.resolve(&t));
+                let mut ok = found.is_some();
+                if found == Some(JType::Void) {
+                    self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                        "You cannot store 'void' in a variable", *pos));
+                    ok = false;
+                }
+                if let Some(annotation) = annotation {
+                    ok &= self.known(&annotation.t, annotation.pos);
+                }
+                let t = match (annotation, found) {
+                    (Some(annotation), Some(found)) if ok => {
+                        ok = self.expect(&annotation.t, annotation.pos, &found, value);
+                        annotation.t.clone()
+                    },
+                    // Written out types are still right when the value is broken
+                    (Some(annotation), _) => annotation.t.clone(),
+                    (None, Some(found)) if ok => found,
+                    // Still declare the variable when its value is broken,
+                    // so uses of it later don't count as undefined.
+                    _ => self.fresh(),
+                };
+                self.declare_variable(name, t, *pos);
+                // Only values that are worked out without running
+                // anything can be used as more than one type
+                if ok && matches!(**value, Expr::Lambda(_, _) | Expr::Var(_, _)) {
+                    self.generalize(name, *pos);
+                }
+                // Declarations are statements, so they don't have a value
+                if ok { Some(JType::Void) } else { None }
+            },
+            Expr::Assign(pos, name, value) => self.assign(pos, name, value),
+            Expr::If(_, condition, then, otherwise) => self.if_expr(condition, then, otherwise.as_deref()),
+            Expr::While(_, label, condition, body) => {
+                let ok = self.require(condition, &JType::Bool,
+                    |e| e.with_help("Conditions have to be 'bool'"));
+                let body_ok = self.loop_body(label, body);
+                (ok && body_ok).then_some(JType::Void)
+            },
+            Expr::For(pos, label, name, from, to, body) => {
+                let why = |e: Diagnostic| e.with_help("Loops go over a range of ints");
+                let from_ok = self.require(from, &JType::Int, why);
+                let to_ok = self.require(to, &JType::Int, why);
+                // The variable only exists inside the loop
+                self.push_scope();
+                self.declare(name, JType::Int, *pos);
+                let body_ok = self.loop_body(label, body);
+                self.pop_scope();
+                (from_ok && to_ok && body_ok).then_some(JType::Void)
+            },
+            Expr::Fn(pos, name, function) => {
+                let (params, ret) = self.signature(function);
+                // Declared before checking the body so it can call itself
+                self.declare(name, JType::Fn(params.clone(), Box::new(ret.clone())), *pos);
+                let ok = self.function(pos, function, &params, &ret);
+                // Only generic once the body is done, so calls inside it all agree
+                if ok {
+                    self.generalize(name, *pos);
+                }
+                ok.then_some(JType::Void)
+            },
+            Expr::Lambda(pos, function) => {
+                let (params, ret) = self.signature(function);
+                self.function(pos, function, &params, &ret)
+                    .then(|| self.resolve(&JType::Fn(params, Box::new(ret))))
+            },
+            Expr::Call(pos, callee, args) => self.call(pos, callee, args),
+            Expr::Return(_, value) => self.return_expr(expr, value.as_deref()),
+            Expr::Struct(pos, decl) => self.struct_decl(pos, decl),
+            Expr::Field(_, object, member) => self.field(object, member),
+            Expr::Enum(pos, decl) => self.enum_decl(pos, decl),
+            Expr::Variant(pos, name, variant) => {
+                let (_, fields) = self.variant(pos, name, variant)?;
+                let t = JType::User(name.clone());
+                // Variants that hold something are called like functions to make them
+                Some(if fields.is_empty() { t } else { JType::Fn(fields, Box::new(t)) })
+            },
+            Expr::Match(pos, value, arms) => self.match_expr(pos, value, arms),
+            Expr::Import(pos, module, names) => self.import(pos, module, names.as_deref()),
+            Expr::Export(_, decl) => {
+                let t = self.expr(decl);
+                if let Some(name) = decl.declared_name() {
+                    self.exported.push(name.to_string());
+                }
+                t
+            },
+            Expr::Break(pos, label) => self.jump(pos, "break", label),
+            Expr::Continue(pos, label) => self.jump(pos, "continue", label),
+            Expr::Var(pos, name) => match self.symbol(name).cloned() {
+                Some(symbol) => Some(self.instance(&symbol)),
+                None => match stdlib::global(name) {
+                    // Natives can take any type, so each use gets its own type variables
+                    Some(t) => Some(self.instantiate(&t, &mut HashMap::new())),
+                    None => {
+                        self.diagnostics.push(Diagnostic::error(codes::UNDEFINED_VARIABLE,
+                            format!("'{}' is not defined", name), *pos)
+                            .with_help(format!("Declare it first with 'let {} = ...'", name)));
+                        None
+                    },
+                },
+            },
+            Expr::Int(_, _) => Some(JType::Int),
+            Expr::Float(_, _) => Some(JType::Float),
+            Expr::Str(_, _) => Some(JType::String),
+            Expr::Char(_, _) => Some(JType::Char),
+            Expr::Bool(_, _) => Some(JType::Bool),
+            Expr::Null(_) => Some(JType::Optional(Box::new(self.fresh()))),
+            Expr::List(_, items) => self.list(items),
+            Expr::Tuple(_, items) => {
+                let types: Vec<Option<JType>> = items.iter().map(|item| self.expr(item)).collect();
+                let types = types.into_iter().collect::<Option<Vec<_>>>()?;
+                Some(JType::Tuple(types.iter().map(|t| self.resolve(t)).collect()))
+            },
+            Expr::Map(_, entries) => self.map(entries),
+            Expr::Neg(pos, operand) => {
+                let operand_type = self.expr(operand)?;
+                let operand_type = self.resolve(&operand_type);
+                // Negating a number keeps its type, and
+                // something that isn't known yet has to be one
+                if let JType::Var(_) = operand_type {
+                    self.fits(&operand_type, Constraint::Numeric);
+                    return Some(operand_type);
+                }
+                if is_numeric(&operand_type) {
+                    return Some(operand_type);
+                }
+                self.diagnostics.push(Diagnostic::error(codes::TYPE_MISMATCH,
+                    format!("You cannot negate '{}'", operand_type), *pos));
+                None
+            },
+            Expr::Add(pos, left, right) => self.arithmetic(pos, "add", left, right),
+            Expr::Sub(pos, left, right) => self.arithmetic(pos, "subtract", left, right),
+            Expr::Mul(pos, left, right) => self.arithmetic(pos, "multiply", left, right),
+            Expr::Div(pos, left, right) => self.arithmetic(pos, "divide", left, right),
+            Expr::Not(pos, operand) => self.require(operand, &JType::Bool,
+                |e| e.with_label(*pos, "'!' only works on bools")).then_some(JType::Bool),
+            Expr::Eq(pos, left, right) => self.comparison(pos, "==", left, right),
+            Expr::Ne(pos, left, right) => self.comparison(pos, "!=", left, right),
+            Expr::Lt(pos, left, right) => self.comparison(pos, "<", left, right),
+            Expr::Le(pos, left, right) => self.comparison(pos, "<=", left, right),
+            Expr::Gt(pos, left, right) => self.comparison(pos, ">", left, right),
+            Expr::Ge(pos, left, right) => self.comparison(pos, ">=", left, right),
+            Expr::And(pos, left, right) => self.logical(pos, "&&", left, right),
+            Expr::Or(pos, left, right) => self.logical(pos, "||", left, right),
+            Expr::Coalesce(pos, left, right) => self.coalesce(pos, left, right),
+        }
+    }
+}
+
+/// The expression a block ends with, which is where its value comes from.
+/// Used to point errors about a block's type at something small.
+fn tail(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Body(_, exprs) if !exprs.is_empty() => tail(&exprs[exprs.len() - 1]),
+        Expr::If(_, _, then, Some(_)) => tail(then),
+        _ => expr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::testing::check_errors;
+
+    fn ok(src: &str) {
+        assert_eq!(check_errors(src), Vec::<&str>::new(), "{:?} should check", src);
+    }
+
+    const SHAPES: &str = "enum Shape { Circle(float), Rect(float, float), Empty }\n";
+
+    #[test]
+    fn arithmetic() {
+        ok("1 + 2 * 3");
+        ok("let a = 2 a * 3.5");
+        assert_eq!(check_errors("1 + \"a\""), ["T001"]);
+        assert_eq!(check_errors("true * 2"), ["T001"]);
+        assert_eq!(check_errors("-\"a\""), ["T001"]);
+    }
+
+    #[test]
+    fn variables() {
+        ok("let x = 1 x + 1");
+        assert_eq!(check_errors("y"), ["T002"]);
+        assert_eq!(check_errors("let x = 1 let x = 2"), ["T003"]);
+        assert_eq!(check_errors("let x: int = \"a\""), ["T001"]);
+        ok("let x: float = 1.5 x * 2");
+        ok("let x = 1 if true { let x = \"a\" }");
+        // Variables hide natives and libraries with the same name
+        ok("let math = 1 let println = \"a\" math + 1 println");
+    }
+
+    #[test]
+    fn comparisons() {
+        ok("1 < 2.5 && \"a\" == \"b\" || !('a' >= 'b')");
+        assert_eq!(check_errors("1 < \"a\""), ["T001"]);
+        assert_eq!(check_errors("true < false"), ["T001"]);
+        assert_eq!(check_errors("1 && true"), ["T001"]);
+    }
+
+    #[test]
+    fn loops() {
+        ok("outer: while true { for i in 0..3 { break outer } }");
+        assert_eq!(check_errors("break"), ["T004"]);
+        assert_eq!(check_errors("while true { continue nope }"), ["T005"]);
+        assert_eq!(check_errors("while 1 { }"), ["T001"]);
+        assert_eq!(check_errors("if true { 1 } else { \"a\" }"), ["T001"]);
+    }
+
+    #[test]
+    fn functions() {
+        ok("fn add(a: int, b: int) -> int { a + b } add(1, 2)");
+        ok("fn fact(n: int) -> int { if n < 2 { 1 } else { n * fact(n - 1) } }");
+        assert_eq!(check_errors("fn f(a: int) -> int { a } f(1, 2)"), ["T007"]);
+        assert_eq!(check_errors("fn f(a: int) -> int { a } f(\"a\")"), ["T001"]);
+        assert_eq!(check_errors("fn f() -> int { \"a\" }"), ["T001"]);
+        assert_eq!(check_errors("return 1"), ["T006"]);
+    }
+
+    #[test]
+    fn assignment() {
+        ok("let x = 1 x = 2 x += 3");
+        ok("fn f(n: int) -> int { n -= 1 n }");
+        ok("let x: int? = null x = 3");
+        assert_eq!(check_errors("let x = 1 x = \"a\""), ["T001"]);
+        assert_eq!(check_errors("let x = 1 let y: int = x = 2"), ["T001"]);
+        assert_eq!(check_errors("y = 1"), ["T002"]);
+        assert_eq!(check_errors("fn f() -> int { 1 } f = f"), ["T014"]);
+        assert_eq!(check_errors("for i in 0..3 { i = 0 }"), ["T014"]);
+        assert_eq!(check_errors("println = println"), ["T014"]);
+        // Uses at other types would break
+        assert_eq!(check_errors("let f = fn(x) { x } f = fn(x) { 1 }"), ["T014"]);
+        ok("let f: fn(int) -> int = fn(x) { x } f = fn(x) { 1 }");
+    }
+
+    #[test]
+    fn functions_return_at_most_what_is_expected() {
+        ok("let f: fn(int) -> int? = fn(x: int) { x }");
+        ok("let f: fn(int) -> int? = fn(x) { x }");
+        ok("fn apply(g: fn(int) -> (int?, string)) { } apply(fn(x) { (x, \"a\") })");
+        assert_eq!(check_errors("let f: fn(int) -> int = fn(x) { \"a\" }"), ["T001"]);
+        ok("let f: fn(int) -> int = fn(x: int?) { 1 }");
+        assert_eq!(check_errors("let f: fn(int?) -> int = fn(x: int) { 1 }"), ["T001"]);
+    }
+
+    #[test]
+    fn unknown_operands_are_numbers() {
+        ok("fn add(a, b) { a + b } add(1, 2) add(1.5, 2.5)");
+        assert_eq!(check_errors("fn add(a, b) { a + b } add(\"x\", \"y\")"), ["T001", "T001"]);
+        assert_eq!(check_errors("fn add(a, b) { a + b } add(1, \"y\")"), ["T001"]);
+        ok("fn neg(x) { -x } neg(1) neg(2.5)");
+        assert_eq!(check_errors("fn neg(x) { -x } neg(true)"), ["T001"]);
+        assert_eq!(check_errors("fn f(x) { let s: string = -x }"), ["T001"]);
+    }
+
+    #[test]
+    fn unknown_operands_can_be_ordered() {
+        ok("fn f(n) { n <= 1 } f(2)");
+        ok("fn fact(n) { if n <= 1 { 1 } else { n * fact(n - 1) } } fact(5)");
+        ok("let f = fn(n) { n < 3 } f(1)");
+        ok("fn first(a, b) { a < b } first(\"a\", \"b\")");
+        assert_eq!(check_errors("fn f(n) { n < true }"), ["T001"]);
+    }
+
+    #[test]
+    fn unknown_operands_keep_what_operators_need() {
+        ok("fn eq(a, b) { a == b } eq(1, 2) eq(\"a\", \"b\") eq(null, 1)");
+        assert_eq!(check_errors("fn lt(a, b) { a < b } lt([1], [2])"), ["T001", "T001"]);
+        assert_eq!(check_errors("fn lt(a, b) { a < b } lt(fn() { 1 }, fn() { 2 })"), ["T001", "T001"]);
+        assert_eq!(check_errors("fn lt(a, b) { a < b } lt(true, false)"), ["T001", "T001"]);
+        assert_eq!(check_errors("fn eq(a, b) { a == b } eq(fn() { 1 }, fn() { 2 })"), ["T001", "T001"]);
+        assert_eq!(check_errors("fn eq(a, b) { a == b } eq([1: 2], [1: 2])"), ["T001", "T001"]);
+        assert_eq!(check_errors("struct P { x: int } fn eq(a, b) { a == b } eq(P(1), P(1))"), ["T001", "T001"]);
+        assert_eq!(check_errors("fn eq(a, b) { a == b } eq(1, \"a\")"), ["T001"]);
+        // Ints turn into floats the same way they do for literals
+        ok("fn add(a, b) { a + b } let x: float = add(1, 2.5) let y: int = add(1, 2)");
+        ok("fn inc(a) { a + 1 } let x: float = inc(2.5) let y: int = inc(2)");
+        ok("fn lt(a, b) { a < b } lt(1, 2.5)");
+        assert_eq!(check_errors("fn add(a, b) { a + b } let x: int = add(1, 2.5)"), ["T001"]);
+        assert_eq!(check_errors("fn add(a, b) -> int { a + b } add(1, 2.5)"), ["T001"]);
+    }
+
+    #[test]
+    fn generic_values() {
+        ok("let f = fn(x) { x } f(1) f(\"a\")");
+        ok("let p = println p(1) p(\"a\")");
+        ok("fn id(x) { x } let a: int = id(1) let b: string = id(\"a\")");
+        // Other values are only worked out once
+        assert_eq!(check_errors("let xs = [] lists.push(xs, 1) lists.push(xs, \"a\")"), ["T001"]);
+        // Types from around the function can't change between uses
+        assert_eq!(check_errors("fn f(y) { let g = fn(x) { y } let a: int = g(1) let b: string = g(1) }"), ["T001"]);
+    }
+
+    #[test]
+    fn structs() {
+        ok("struct P { x: int fn get(self) -> int { self.x } } P(1).get()");
+        assert_eq!(check_errors("struct P { x: int } P(1).y"), ["T009"]);
+        assert_eq!(check_errors("struct P { x: int } P(\"a\")"), ["T001"]);
+        assert_eq!(check_errors("fn f(p: Nope) { }"), ["T008"]);
+        assert_eq!(check_errors("struct P { x: int } struct P { y: int }"), ["T003"]);
+    }
+
+    #[test]
+    fn types_declared_further_down() {
+        ok("fn norm(p: Point) -> float { p.x * p.x + p.y.len() } struct Point { x: float, y: Inner } struct Inner { n: float fn len(self) -> float { self.n } }");
+        ok("fn area(s: Shape) -> float { match s { Shape::Square(x) => x * x, Shape::Empty => 0.0 } } enum Shape { Square(float), Empty }");
+        ok("if true { fn f(c: C) -> int { c.n } struct C { n: int } f(C(1)) }");
+        // Only the types are known early, not the constructors
+        assert_eq!(check_errors("let p = P(1) struct P { x: int }"), ["T002"]);
+    }
+
+    #[test]
+    fn exhaustive_match() {
+        ok(&format!("{}fn f(s: Shape) -> float {{ match s {{ Shape::Circle(r) => r, Shape::Rect(w, h) => w * h, Shape::Empty => 0.0 }} }}", SHAPES));
+        ok(&format!("{}fn f(s: Shape) -> float {{ match s {{ Shape::Circle(r) => r, _ => 0.0 }} }}", SHAPES));
+        assert_eq!(check_errors(&format!("{}fn f(s: Shape) -> float {{ match s {{ Shape::Circle(r) => r, Shape::Empty => 0.0 }} }}", SHAPES)), ["T011"]);
+        assert_eq!(check_errors("match 1 { 0 => 1 }"), ["T011"]);
+        assert_eq!(check_errors("match true { true => 1, false => 2 }"), Vec::<&str>::new());
+        assert_eq!(check_errors(&format!("{}Shape::Nope", SHAPES)), ["T010"]);
+    }
+
+    #[test]
+    fn guarded_arms_dont_count() {
+        assert_eq!(check_errors("match true { true => 1, false if 1 < 2 => 2 }"), ["T011"]);
+    }
+
+    #[test]
+    fn unreachable_arms_are_warnings() {
+        ok("match 1 { _ => 1, 0 => 2 }");
+    }
+
+    #[test]
+    fn optionals() {
+        ok("let x: int? = null let y: int = x ?? 0");
+        ok("let x: string? = \"a\" x == \"a\" && x != null");
+        ok("fn f(x: int?) -> int { x ?? 0 } f(1) + f(null)");
+        ok("let m = [\"a\": 1] let n: int = maps.get(m, \"a\") ?? 0");
+        assert_eq!(check_errors("let x: int = null"), ["T001"]);
+        assert_eq!(check_errors("1 ?? 2"), ["T001"]);
+        assert_eq!(check_errors("let x: int? = 1 x ?? \"a\""), ["T001"]);
+        assert_eq!(check_errors("let x: int? = 1 x < 2"), ["T001"]);
+        assert_eq!(check_errors("let x: int? = 1 x + 1"), ["T001"]);
+    }
+
+    #[test]
+    fn tuples_and_maps() {
+        ok("let p = (1, \"a\") let n: int = p.0 let s: string = p.1");
+        ok("let m: Map<string, int> = [:] maps.set(m, \"a\", 1)");
+        assert_eq!(check_errors("(1, 2).2"), ["T009"]);
+        assert_eq!(check_errors("(1, 2) == (1, 2)"), ["T001"]);
+        assert_eq!(check_errors("[1: \"a\", \"b\": \"c\"]"), ["T001"]);
+        assert_eq!(check_errors("let m = [1: \"a\"] maps.set(m, 2, 3)"), ["T001"]);
+    }
+
+    #[test]
+    fn lists() {
+        ok("let xs = [1, 2] lists.push(xs, 3)");
+        assert_eq!(check_errors("[1, \"a\"]"), ["T001"]);
+        assert_eq!(check_errors("let xs = [1] lists.push(xs, \"a\")"), ["T001"]);
+    }
+
+    #[test]
+    fn assignability() {
+        ok("let x: int? = 1");
+        assert_eq!(check_errors("let x: List<int> = 1"), ["T001"]);
+        assert_eq!(check_errors("let x: int? = 1 let y: int = x"), ["T001"]);
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        assert_eq!(check_errors("y + \"a\" let z = -'c' w"), ["T002", "T001", "T002"]);
+    }
+}
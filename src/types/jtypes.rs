@@ -2,20 +2,148 @@ use std::fmt::{ self, Display, Formatter };
 
 
 // Enum for each type in the language
+#[derive(Clone, PartialEq)]
 pub enum JType {
     Int,
     Float,
+    Bool,
+    String,
+    Char,
     Void,
+    // List<T>
+    List(Box<JType>),
+    // Map<K, V>
+    Map(Box<JType>, Box<JType>),
+    // (A, B, ...)
+    Tuple(Vec<JType>),
+    // fn(A, B) -> R
+    Fn(Vec<JType>, Box<JType>),
+    // T?, either a T or null
+    Optional(Box<JType>),
+    // A struct, which is only ever the same
+    // type as itself no matter what's in it
+    User(String),
+    // An imported module, eg the `math` in `import math`.
+    // Only imports make these, so they're never written out.
+    Module(String),
+    // A type the checker hasn't worked out yet.
+    // Never makes it past type checking.
+    Var(usize),
+}
+
+impl JType {
+    /// Finds the type a name in the code refers to, eg `int`.
+    /// Only covers types that don't take other types.
+    pub fn from_name(name: &str) -> Option<JType> {
+        match name {
+            "int" => Some(Self::Int),
+            "float" => Some(Self::Float),
+            "bool" => Some(Self::Bool),
+            "string" => Some(Self::String),
+            "char" => Some(Self::Char),
+            "void" => Some(Self::Void),
+            _ => None,
+        }
+    }
+
+    /// Returns true if a value of this type can be used
+    /// where a value of the target type is expected.
+    /// Anything can be used as an optional of itself, and tuples
+    /// and functions are compared piece by piece. Everything else
+    /// has to match exactly.
+    pub fn is_assignable_to(&self, target: &JType) -> bool {
+        match (self, target) {
+            _ if self == target => true,
+            (Self::Optional(a), Self::Optional(b)) => a.is_assignable_to(b),
+            (_, Self::Optional(b)) => self.is_assignable_to(b),
+            (Self::Tuple(a), Self::Tuple(b)) => a.len() == b.len()
+                && a.iter().zip(b).all(|(a, b)| a.is_assignable_to(b)),
+            // A function can stand in for another if it takes
+            // at least what the other takes and returns at most
+            // what the other returns, so parameters are flipped.
+            (Self::Fn(a_params, a_ret), Self::Fn(b_params, b_ret)) => a_params.len() == b_params.len()
+                && b_params.iter().zip(a_params).all(|(b, a)| b.is_assignable_to(a))
+                && a_ret.is_assignable_to(b_ret),
+            // Lists and maps can be written to, so what they
+            // hold has to match exactly.
+            _ => false,
+        }
+    }
+}
+
+/// Writes types separated by commas.
+fn write_list(f: &mut Formatter<'_>, types: &[JType]) -> fmt::Result {
+    for (i, t) in types.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", t)?;
+    }
+    Ok(())
 }
 
 impl Display for JType {
     // Type as a string
     // Very nice
+    // Written the same way the parser reads types,
+    // so anything printed can be pasted back into code.
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", match self {
-            Self::Int => "int",
-            Self::Float => "float",
-            Self::Void => "void",
-        })
+        match self {
+            Self::Int => write!(f, "int"),
+            Self::Float => write!(f, "float"),
+            Self::Bool => write!(f, "bool"),
+            Self::String => write!(f, "string"),
+            Self::Char => write!(f, "char"),
+            Self::Void => write!(f, "void"),
+            Self::List(t) => write!(f, "List<{}>", t),
+            Self::Map(k, v) => write!(f, "Map<{}, {}>", k, v),
+            Self::Tuple(types) => {
+                write!(f, "(")?;
+                write_list(f, types)?;
+                // (int,) so it isn't mistaken for just int in parentheses
+                if types.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            },
+            Self::Fn(params, ret) => {
+                write!(f, "fn(")?;
+                write_list(f, params)?;
+                write!(f, ") -> {}", ret)
+            },
+            // Otherwise the '?' would belong to the return type,
+            // or two of them would be read as '??'
+            Self::Optional(t) if matches!(**t, Self::Fn(_, _) | Self::Optional(_)) => write!(f, "({})?", t),
+            Self::Optional(t) => write!(f, "{}?", t),
+            Self::User(name) => write!(f, "{}", name),
+            Self::Module(name) => write!(f, "module {}", name),
+            // Unknown types only show up in errors about broken code
+            Self::Var(_) => write!(f, "_"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JType;
+
+    fn optional(t: JType) -> JType {
+        JType::Optional(Box::new(t))
+    }
+
+    #[test]
+    fn assignable() {
+        assert!(JType::Int.is_assignable_to(&optional(JType::Int)));
+        assert!(!optional(JType::Int).is_assignable_to(&JType::Int));
+        assert!(JType::Tuple(vec![JType::Int]).is_assignable_to(&JType::Tuple(vec![optional(JType::Int)])));
+        assert!(!JType::List(Box::new(JType::Int)).is_assignable_to(&JType::List(Box::new(optional(JType::Int)))));
+    }
+
+    #[test]
+    fn functions_flip_their_parameters() {
+        let takes_optional = JType::Fn(vec![optional(JType::Int)], Box::new(JType::Int));
+        let takes_int = JType::Fn(vec![JType::Int], Box::new(optional(JType::Int)));
+        assert!(takes_optional.is_assignable_to(&takes_int));
+        assert!(!takes_int.is_assignable_to(&takes_optional));
     }
 }
@@ -1,6 +1,7 @@
-
+mod checker;
 mod jtypes;
 mod result;
+mod patterns;
+pub use checker::{ Checker, Exports };
 pub use jtypes::{ JType };
-pub use result::{ TypeResult };
 pub mod utils;
@@ -0,0 +1,178 @@
+use super::JType;
+
+/*
+* Works out whether the arms of a match cover every value,
+* and whether any arm is covered by the ones before it.
+*
+* Both come down to one question: given some rows of patterns,
+* is there a value that matches a new row but none of the others?
+* If there is, the new row is useful. An arm is unreachable when
+* its pattern isn't useful after the arms before it, and a match
+* is missing something when `_` is still useful after every arm.
+*
+* Rows have a column for each value being looked at. Matching a
+* constructor swaps its column for columns of what it holds, so
+* nested patterns get looked at one level at a time.
+*/
+
+/// What a value can start with, as far as patterns care.
+#[derive(Clone, PartialEq)]
+pub enum Ctor {
+    // Index of the variant in its enum
+    Variant(usize),
+    Bool(bool),
+    // There are too many of these to ever list them
+    // all, so only a wildcard covers the rest
+    Int(i64),
+    Str(String),
+    Char(char),
+}
+
+/// A pattern boiled down to what matters for coverage.
+#[derive(Clone)]
+pub enum Space {
+    // `_` or a binding
+    Any,
+    // A constructor with patterns for what it holds
+    Ctor(Ctor, Vec<Space>),
+}
+
+type Row = Vec<Space>;
+
+/// Every constructor of a type, with a name to show it by
+/// and the types of what it holds.
+type Ctors = Vec<(Ctor, String, Vec<JType>)>;
+
+/// Names of the variants of an enum with what each of them holds.
+pub type Variants = Vec<(String, Vec<JType>)>;
+
+/// Checks coverage of patterns. `variants` finds the variants
+/// of an enum by name, along with what each of them holds.
+pub struct Coverage<'a> {
+    pub variants: &'a dyn Fn(&str) -> Option<Variants>,
+}
+
+impl Coverage<'_> {
+    /// Lists every constructor of a type, or None
+    /// if there are too many of them to list.
+    fn ctors(&self, t: &JType) -> Option<Ctors> {
+        match t {
+            JType::Bool => Some(vec![
+                (Ctor::Bool(true), "true".to_string(), Vec::new()),
+                (Ctor::Bool(false), "false".to_string(), Vec::new()),
+            ]),
+            JType::User(name) => (self.variants)(name).map(|variants| variants.into_iter().enumerate()
+                .map(|(i, (variant, fields))| (Ctor::Variant(i), format!("{}::{}", name, variant), fields))
+                .collect()),
+            _ => None,
+        }
+    }
+
+    /// Types of what a constructor of the type holds.
+    fn fields(&self, t: &JType, ctor: &Ctor, arity: usize) -> Vec<JType> {
+        self.ctors(t).and_then(|ctors| ctors.into_iter().find(|(c, _, _)| c == ctor))
+            .map(|(_, _, fields)| fields)
+            // Literals don't hold anything, and types
+            // without a list of constructors never get here
+            .unwrap_or_else(|| vec![JType::Void; arity])
+    }
+
+    /// Lists every constructor of the type if each of them
+    /// starts one of the rows. Otherwise returns None.
+    fn complete(&self, rows: &[Row], t: &JType) -> Option<Ctors> {
+        let ctors = self.ctors(t)?;
+        let used = |ctor: &Ctor| rows.iter().any(|row| matches!(&row[0], Space::Ctor(c, _) if c == ctor));
+        ctors.iter().all(|(ctor, _, _)| used(ctor)).then_some(ctors)
+    }
+
+    /// Keeps the rows that can start with the constructor,
+    /// with their first column swapped for what it holds.
+    fn specialize(rows: &[Row], ctor: &Ctor, arity: usize) -> Vec<Row> {
+        rows.iter().filter_map(|row| {
+            let fields = match &row[0] {
+                Space::Any => vec![Space::Any; arity],
+                Space::Ctor(c, fields) if c == ctor => fields.clone(),
+                Space::Ctor(_, _) => return None,
+            };
+            Some(fields.into_iter().chain(row[1..].iter().cloned()).collect())
+        }).collect()
+    }
+
+    /// Keeps the rows that start with a wildcard, without it.
+    fn default(rows: &[Row]) -> Vec<Row> {
+        rows.iter().filter(|row| matches!(row[0], Space::Any)).map(|row| row[1..].to_vec()).collect()
+    }
+
+    /// Returns true if some value matches the row but none of the rows.
+    /// `types` has the type of each column.
+    pub fn useful(&self, rows: &[Row], row: &[Space], types: &[JType]) -> bool {
+        let (first, rest) = match row.split_first() {
+            Some(split) => split,
+            None => return rows.is_empty(),
+        };
+        match first {
+            Space::Ctor(ctor, fields) => {
+                let types: Vec<JType> = self.fields(&types[0], ctor, fields.len()).into_iter()
+                    .chain(types[1..].iter().cloned()).collect();
+                let row: Row = fields.iter().chain(rest).cloned().collect();
+                self.useful(&Self::specialize(rows, ctor, fields.len()), &row, &types)
+            },
+            // A wildcard is useful if any constructor is, but if some
+            // are never used it's useful for those ones already
+            Space::Any => match self.complete(rows, &types[0]) {
+                Some(ctors) => ctors.into_iter().any(|(ctor, _, fields)| {
+                    let row: Row = vec![Space::Any; fields.len()].into_iter().chain(rest.iter().cloned()).collect();
+                    let types: Vec<JType> = fields.iter().chain(&types[1..]).cloned().collect();
+                    self.useful(&Self::specialize(rows, &ctor, fields.len()), &row, &types)
+                }),
+                None => self.useful(&Self::default(rows), rest, &types[1..]),
+            },
+        }
+    }
+
+    /// Finds a value none of the rows match, written as a
+    /// pattern for each column. Returns None if there isn't one.
+    fn missing(&self, rows: &[Row], types: &[JType]) -> Option<Vec<String>> {
+        let (t, rest) = match types.split_first() {
+            Some(split) => split,
+            None => return rows.is_empty().then(Vec::new),
+        };
+        if let Some(ctors) = self.complete(rows, t) {
+            return ctors.into_iter().find_map(|(ctor, name, fields)| {
+                let types: Vec<JType> = fields.iter().chain(rest).cloned().collect();
+                let mut missing = self.missing(&Self::specialize(rows, &ctor, fields.len()), &types)?;
+                let held: Vec<String> = missing.drain(..fields.len()).collect();
+                missing.insert(0, write_ctor(name, &held));
+                Some(missing)
+            });
+        }
+        let mut missing = self.missing(&Self::default(rows), rest)?;
+        // Any constructor that's never used is missing,
+        // or anything at all when they can't be listed
+        let used = |ctor: &Ctor| rows.iter().any(|row| matches!(&row[0], Space::Ctor(c, _) if c == ctor));
+        let head = match self.ctors(t).and_then(|ctors| ctors.into_iter().find(|(ctor, _, _)| !used(ctor))) {
+            Some((_, name, fields)) => write_ctor(name, &vec!["_".to_string(); fields.len()]),
+            None => "_".to_string(),
+        };
+        missing.insert(0, head);
+        Some(missing)
+    }
+
+    /// Lists values of the type that none of the patterns match,
+    /// one for each constructor that isn't fully covered.
+    pub fn uncovered(&self, patterns: &[Space], t: &JType) -> Vec<String> {
+        let rows: Vec<Row> = patterns.iter().map(|pattern| vec![pattern.clone()]).collect();
+        match self.ctors(t) {
+            Some(ctors) => ctors.into_iter().filter_map(|(ctor, name, fields)| {
+                let held = self.missing(&Self::specialize(&rows, &ctor, fields.len()), &fields)?;
+                Some(write_ctor(name, &held))
+            }).collect(),
+            None => self.missing(&rows, std::slice::from_ref(t)).unwrap_or_default(),
+        }
+    }
+}
+
+/// Writes a constructor with what it holds, eg `Shape::Rect(_, _)`.
+fn write_ctor(name: String, held: &[String]) -> String {
+    if held.is_empty() { name } else { format!("{}({})", name, held.join(", ")) }
+}
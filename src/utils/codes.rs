@@ -0,0 +1,70 @@
+use super::diagnostic::Code;
+
+/*
+* Every diagnostic code in one place.
+* The first letter says which phase reports it:
+* L for the lexer, P for the parser, T for types,
+* C for the compiler, B for loading bytecode, M for
+* loading modules, R for runtime and I for reading and
+* writing files.
+* Never reuse or renumber an id, only add new ones.
+*/
+
+// Lexer
+pub const UNKNOWN_TOKEN: Code = Code { id: "L001", name: "UnknownToken" };
+pub const LEX_END_OF_FILE: Code = Code { id: "L002", name: "EndOfFile" };
+pub const NUMBER_FORMAT: Code = Code { id: "L003", name: "NumberFormat" };
+pub const TOO_MANY_ERRORS: Code = Code { id: "L004", name: "TooManyErrors" };
+pub const INVALID_ESCAPE: Code = Code { id: "L005", name: "InvalidEscape" };
+pub const INVALID_CHAR: Code = Code { id: "L006", name: "InvalidChar" };
+
+// Parser
+pub const UNEXPECTED_TOKEN: Code = Code { id: "P001", name: "UnexpectedToken" };
+pub const PARSE_END_OF_FILE: Code = Code { id: "P002", name: "EndOfFile" };
+pub const UNKNOWN_TYPE: Code = Code { id: "P003", name: "UnknownType" };
+pub const TYPE_ARGUMENTS: Code = Code { id: "P004", name: "TypeArguments" };
+
+// Types
+pub const TYPE_MISMATCH: Code = Code { id: "T001", name: "TypeMismatch" };
+pub const UNDEFINED_VARIABLE: Code = Code { id: "T002", name: "UndefinedVariable" };
+pub const REDECLARED_VARIABLE: Code = Code { id: "T003", name: "RedeclaredVariable" };
+pub const MISPLACED_BREAK: Code = Code { id: "T004", name: "MisplacedBreak" };
+pub const UNDEFINED_LABEL: Code = Code { id: "T005", name: "UndefinedLabel" };
+pub const MISPLACED_RETURN: Code = Code { id: "T006", name: "MisplacedReturn" };
+pub const ARGUMENT_COUNT: Code = Code { id: "T007", name: "ArgumentCount" };
+pub const UNDEFINED_TYPE: Code = Code { id: "T008", name: "UndefinedType" };
+pub const UNKNOWN_FIELD: Code = Code { id: "T009", name: "UnknownField" };
+pub const UNKNOWN_VARIANT: Code = Code { id: "T010", name: "UnknownVariant" };
+pub const NON_EXHAUSTIVE: Code = Code { id: "T011", name: "NonExhaustiveMatch" };
+pub const UNREACHABLE_ARM: Code = Code { id: "T012", name: "UnreachableArm" };
+pub const NOT_EXPORTED: Code = Code { id: "T013", name: "NotExported" };
+pub const NOT_ASSIGNABLE: Code = Code { id: "T014", name: "NotAssignable" };
+
+// Compiler
+pub const TOO_MANY_CONSTANTS: Code = Code { id: "C001", name: "TooManyConstants" };
+pub const TOO_MANY_VARIABLES: Code = Code { id: "C002", name: "TooManyVariables" };
+pub const TOO_MUCH_CODE: Code = Code { id: "C003", name: "TooMuchCode" };
+pub const UNSUPPORTED: Code = Code { id: "C004", name: "Unsupported" };
+
+// Bytecode files
+pub const TRUNCATED: Code = Code { id: "B001", name: "Truncated" };
+pub const CORRUPT: Code = Code { id: "B002", name: "Corrupt" };
+pub const VERSION: Code = Code { id: "B003", name: "Version" };
+
+// Modules
+pub const MODULE_NOT_FOUND: Code = Code { id: "M001", name: "ModuleNotFound" };
+pub const IMPORT_CYCLE: Code = Code { id: "M002", name: "ImportCycle" };
+
+// Runtime
+pub const DIVISION_BY_ZERO: Code = Code { id: "R001", name: "DivisionByZero" };
+pub const OVERFLOW: Code = Code { id: "R002", name: "Overflow" };
+pub const RUNTIME_TYPE_MISMATCH: Code = Code { id: "R003", name: "TypeMismatch" };
+pub const RUNTIME_UNDEFINED_VARIABLE: Code = Code { id: "R004", name: "UndefinedVariable" };
+pub const INVALID_BYTECODE: Code = Code { id: "R005", name: "InvalidBytecode" };
+pub const RUNTIME_MISPLACED_BREAK: Code = Code { id: "R006", name: "MisplacedBreak" };
+pub const STACK_OVERFLOW: Code = Code { id: "R007", name: "StackOverflow" };
+pub const NO_MATCH: Code = Code { id: "R008", name: "NoMatch" };
+pub const OUT_OF_RANGE: Code = Code { id: "R009", name: "OutOfRange" };
+
+// Files
+pub const IO: Code = Code { id: "I001", name: "IO" };
@@ -0,0 +1,138 @@
+use crate::lexer::{ source::SourceMap, token::Position };
+use super::general as utils;
+use std::fmt::{ self, Display, Formatter };
+
+/// How serious a diagnostic is.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Self::Error => "Error",
+            Self::Warning => "Warning",
+            Self::Note => "Note",
+        })
+    }
+}
+
+/// Identifies what kind of problem a diagnostic is about.
+/// The id is stable so tools can match on it,
+/// the name is what gets shown to people.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Code {
+    pub id: &'static str,
+    pub name: &'static str,
+}
+
+/// A secondary selection explaining part of a diagnostic.
+#[derive(Clone)]
+pub struct Label {
+    pub pos: Position,
+    pub message: String,
+}
+
+/// A problem found in some phase of running a program.
+/// Every phase reports errors with this, so they can be
+/// shown the same way or inspected by tools.
+#[derive(Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub message: String,
+    // Primary selection. Problems that aren't about any
+    // source code, like unreadable files, don't have one.
+    pub pos: Option<Position>,
+    pub labels: Vec<Label>,
+    pub help: Vec<String>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: Code, message: impl Into<String>, pos: Option<Position>) -> Self {
+        Self {
+            severity,
+            code,
+            message: message.into(),
+            pos,
+            labels: Vec::new(),
+            help: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Shorthand for an error pointing at some code.
+    pub fn error(code: Code, message: impl Into<String>, pos: Position) -> Self {
+        Self::new(Severity::Error, code, message, Some(pos))
+    }
+
+    /// Shorthand for a warning pointing at some code.
+    pub fn warning(code: Code, message: impl Into<String>, pos: Position) -> Self {
+        Self::new(Severity::Warning, code, message, Some(pos))
+    }
+
+    /// Adds a secondary selection with a message.
+    pub fn with_label(mut self, pos: Position, message: impl Into<String>) -> Self {
+        self.labels.push(Label { pos, message: message.into() });
+        self
+    }
+
+    /// Adds a suggestion on how to fix the problem.
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help.push(help.into());
+        self
+    }
+
+    /// Adds extra context about the problem.
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Positions only know which file they're in,
+    /// so showing the code they point at needs the files.
+    pub fn display<'a>(&'a self, map: &'a SourceMap) -> Rendered<'a> {
+        Rendered { diagnostic: self, map }
+    }
+}
+
+/// A diagnostic along with the files its positions point into,
+/// ready to be shown.
+pub struct Rendered<'a> {
+    diagnostic: &'a Diagnostic,
+    map: &'a SourceMap,
+}
+
+/// Writes where a selection is, as file:line:col so editors
+/// can jump to it, followed by the selection itself underlined.
+fn write_selection(f: &mut Formatter<'_>, map: &SourceMap, pos: &Position) -> fmt::Result {
+    let file = map.get(pos.file());
+    let (line, col) = file.line_col(pos.start());
+    write!(f, "\nAt {}:{}:{}\n{}", file.name(), line, col, utils::underline_selection(file, pos))
+}
+
+impl<'a> Display for Rendered<'a> {
+    // Renders the diagnostic the same way for every phase
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (d, map) = (self.diagnostic, self.map);
+        write!(f, "{} {} [{}]: {}", d.code.name, d.severity, d.code.id, d.message)?;
+        if let Some(pos) = &d.pos {
+            write_selection(f, map, pos)?;
+        }
+        for label in &d.labels {
+            write!(f, "\n{}: {}", Severity::Note, label.message)?;
+            write_selection(f, map, &label.pos)?;
+        }
+        for help in &d.help {
+            write!(f, "\nHelp: {}", help)?;
+        }
+        for note in &d.notes {
+            write!(f, "\n{}: {}", Severity::Note, note)?;
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
-use crate::lexer::token::Position;
-use std::cmp::min;
+use crate::lexer::{ source::{ column, expand_tabs, SourceFile }, token::Position };
+use std::cmp::{ min, max };
 use locale_codes;
 
 /// Returns true if the given option
@@ -13,13 +13,9 @@ pub fn is_some_and<T>(option: &Option<T>, func: fn(&T) -> bool) -> bool {
     }
 }
 
-/// Gets the line number that the character
-/// at the given index is located on.
-pub fn line_of(src: &str, index: usize) -> usize {
-    src[0..=index].matches('\n').count()
-}
-
-pub fn underline_selection(pos: &Position) -> String {
+/// Shows the lines of the file the position covers,
+/// with the position underlined.
+pub fn underline_selection(file: &SourceFile, pos: &Position) -> String {
     // Determines the underline characters
     // based on whether or not UTF-8 can be used.
     let (left_pipe, underscore, right_pipe) = 
@@ -29,36 +25,27 @@ pub fn underline_selection(pos: &Position) -> String {
             ('\\', '_', '/')
     };
 
-    let index = pos.index();
-    let len = pos.len();
+    // Positions from compiled files can point past a source
+    // that changed since, so keep them inside it.
+    let index = file.clamp(pos.start());
     // See what I did there? ENDex? Get it?
     // Did you get the joke? I thought it was funny.
     // Laugh.
-    let endex = index + len;
+    let endex = max(file.clamp(pos.end()), index);
 
-    // Offset before starting highlighting the first line
-    let mut start_offset = 0;
-    // Offset before ending highlighting the last line
-    let mut end_offset = 0;
-    let mut i = 0;
-    let mut lines: Vec<&str> = Vec::new();
-    for line in pos.src().split('\n') {
-        if i <= index && index < i + line.len() {
-            start_offset = index - i;
-        }
-        if i <= endex && endex < i + line.len() {
-            end_offset = i + line.len() - index - len;
-        }
+    // The last highlighted byte, inclusive.
+    // Empty selections still get an arrow under their index.
+    let last = if endex == index { index } else { endex - 1 };
 
-        if i <= index && endex < i + line.len() {
-            lines.push(line);
-        } 
-        
-        if endex >= i + line.len() {
-            break;
-        }
-        
-        i += line.len();
+    // Every line the selection touches, with tabs turned into spaces,
+    // along with the columns of the line that are highlighted.
+    let mut lines: Vec<(String, usize, usize)> = Vec::new();
+    for line in file.line_index(index)..=file.line_index(last) {
+        let text = file.line(line);
+        let line_start = file.line_start(line);
+        let start = min(index.saturating_sub(line_start), text.len());
+        let end = min(endex - line_start, text.len());
+        lines.push((expand_tabs(text), column(&text[..start]), column(&text[..end])));
     }
 
     /*
@@ -68,42 +55,25 @@ pub fn underline_selection(pos: &Position) -> String {
     * flying way off to the right side of the terminal.
     */
     let mut indent_level = usize::MAX;
-    for line in &lines {
-        indent_level = min(indent_level, line
-            .chars()
-            .take_while(|x| x.is_whitespace())
-            .count()
-        );
+    for (line, _, _) in &lines {
+        indent_level = min(indent_level, line.chars().take_while(|c| c.is_whitespace()).count());
     }
 
     // Time to finally build the error.
     let mut s = String::new();
-    for (n, l) in lines.iter().enumerate() {
-        // Cut off trailing whitespace
-        let mut line = &l[indent_level..];
-        line = line.trim_end();
+    for (l, start, end) in &lines {
+        // Cut off the indent and trailing whitespace
+        let line: String = l.chars().skip(indent_level).collect();
         // Add the current line
-        s.push_str(line);
+        s.push_str(line.trim_end());
         s.push('\n');
 
-        // Get the start index if it's the first line
-        let start_index = if n == 0 {
-            start_offset - indent_level
-        } else {
-            0
-        };
-        // Get the end index if it's the last
-        let end_index = if n == lines.len() - 1 {
-            l.len() - end_offset - indent_level
-        } else {
-            l.len()
-        };
-
-        // Add n spaces to move to the start index
-        s.push_str(&" ".repeat(start_index));
+        let start = max(*start, indent_level);
+        let end = max(*end, start);
+        s.push_str(&" ".repeat(start - indent_level));
 
         // Calculate how far the underline spans for the given line
-        let span = end_index - start_index + 1;
+        let span = max(end - start, 1);
         if span == 1 {
             // If it's only one character, add an arrow
             s.push('^');
@@ -1,3 +1,6 @@
+use std::io::{ self, Write };
+use super::diagnostic::Diagnostic;
+use crate::lexer::source::SourceMap;
 
 /// Logs the msg (+ newline)
 pub fn println(msg: &str) {
@@ -7,9 +10,17 @@ pub fn println(msg: &str) {
 /// Logs the msg
 pub fn print(msg: &str) {
     print!("{}", msg);
+    // Flush so prompts show up before waiting for input
+    let _ = io::stdout().flush();
 }
 
 /// Logs the msg to stderr
 pub fn err(msg: &str) {
     eprintln!("{}", msg);
 }
+
+/// Logs a rendered diagnostic to stderr.
+/// The map has to hold every file its positions point into.
+pub fn diagnostic(diagnostic: &Diagnostic, map: &SourceMap) {
+    eprintln!("{}", diagnostic.display(map));
+}
@@ -1,3 +1,6 @@
-
 pub mod general;
 pub mod logging;
+pub mod diagnostic;
+pub mod codes;
+#[cfg(test)]
+pub mod testing;
@@ -0,0 +1,89 @@
+use crate::bytecode::{ self, result::CompileResult };
+use crate::interpreter::{ Interpreter, result::RunResult };
+use crate::lexer::{ self, source::SourceMap };
+use crate::parser::{ self, expr::Expr, result::ParseResult };
+use crate::types::Checker;
+use super::diagnostic::{ Diagnostic, Severity };
+
+/*
+* Helpers for tests that run source code through the phases.
+* They panic with the diagnostic as soon as a phase that's
+* supposed to work doesn't, so failures say what went wrong.
+*/
+
+/// Shows a diagnostic the way a test failure should show it.
+fn show(diagnostic: &Diagnostic) -> String {
+    format!("{} {}: {}", diagnostic.code.id, diagnostic.code.name, diagnostic.message)
+}
+
+/// Lexes and parses some code, returning the tree or the parser's error.
+pub fn try_parse(src: &str) -> ParseResult<Expr> {
+    let mut map = SourceMap::new();
+    let file = map.add("<test>", src);
+    let lexed = lexer::lexer::lex(map.get(file));
+    if let Some(error) = lexed.diagnostics.iter().find(|diagnostic| diagnostic.severity == Severity::Error) {
+        panic!("couldn't lex {:?}: {}", src, show(error));
+    }
+    parser::parse(map.get(file), lexed.tokens)
+}
+
+/// Lexes and parses code that has to be fine.
+pub fn parse(src: &str) -> Expr {
+    match try_parse(src) {
+        ParseResult::Ok(tree) => tree,
+        ParseResult::Err(e) => panic!("couldn't parse {:?}: {}", src, show(&e)),
+    }
+}
+
+/// Type checks some code and returns the id of every error.
+pub fn check_errors(src: &str) -> Vec<&'static str> {
+    Checker::new().check(&parse(src)).diagnostics.iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .map(|diagnostic| diagnostic.code.id)
+        .collect()
+}
+
+/// Parses and checks code that has to be fine, returning its tree.
+pub fn checked(src: &str) -> Expr {
+    let tree = parse(src);
+    let checked = Checker::new().check(&tree);
+    if let Some(error) = checked.diagnostics.iter().find(|diagnostic| diagnostic.severity == Severity::Error) {
+        panic!("couldn't check {:?}: {}", src, show(error));
+    }
+    tree
+}
+
+/// Runs code on the tree walker, returning its value written out
+/// or the id of the runtime error.
+pub fn try_run(src: &str) -> Result<String, &'static str> {
+    match Interpreter::new().run(&checked(src)) {
+        RunResult::Ok(value) => Ok(value.to_string()),
+        RunResult::Err(e) => Err(e.code.id),
+    }
+}
+
+/// Runs code that has to work on the tree walker and returns its value written out.
+pub fn run(src: &str) -> String {
+    try_run(src).unwrap_or_else(|id| panic!("{:?} failed with {}", src, id))
+}
+
+/// Compiles code and runs it on the VM, returning its value
+/// written out or the id of the runtime error.
+pub fn try_run_vm(src: &str) -> Result<String, &'static str> {
+    let chunk = match bytecode::compiler::compile("<test>", &checked(src)) {
+        CompileResult::Ok(chunk) => chunk,
+        CompileResult::Err(e) => panic!("couldn't compile {:?}: {}", src, show(&e)),
+    };
+    let mut map = SourceMap::new();
+    match bytecode::vm::run(&chunk, map.add("<test>", src)) {
+        RunResult::Ok(value) => Ok(value.to_string()),
+        RunResult::Err(e) => Err(e.code.id),
+    }
+}
+
+/// Runs code on both backends, making sure they agree, and returns the result.
+pub fn run_both(src: &str) -> Result<String, &'static str> {
+    let walked = try_run(src);
+    assert_eq!(walked, try_run_vm(src), "the backends disagree on {:?}", src);
+    walked
+}